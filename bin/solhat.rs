@@ -31,6 +31,9 @@ enum SolHat {
     SerInfo(serinfo::SerInfo),
     LdCorrect(ldcorrect::LdCorrect),
     Composite(composite::Composite),
+    Extract(extract::Extract),
+    Sharpen(sharpen::Sharpen),
+    RadialProfile(radialprofile::RadialProfileCmd),
 }
 
 #[tokio::main]
@@ -57,6 +60,9 @@ async fn main() -> Result<(), anyhow::Error> {
         SolHat::SerInfo(args) => args.run().await,
         SolHat::LdCorrect(args) => args.run().await,
         SolHat::Composite(args) => args.run().await,
+        SolHat::Extract(args) => args.run().await,
+        SolHat::Sharpen(args) => args.run().await,
+        SolHat::RadialProfile(args) => args.run().await,
     } {
         error!("{}", "Unhandled program error:".red());
         error!("{}", why);
@@ -3,6 +3,8 @@ use anyhow::Result;
 use clap::Parser;
 use sciimg::vector::Vector;
 use sciimg::{enums::ImageMode, imagebuffer, medianblur, path, prelude::ImageBuffer};
+use solhat::disk;
+use solhat::interpolation::InterpolationMethod;
 use std::process;
 
 pb_create_spinner!();
@@ -13,8 +15,19 @@ pub struct Composite {
     #[clap(long, short, help = "Input image")]
     input: String,
 
-    #[clap(long, short, help = "Chromosphere radius, in pixels")]
-    radius: f32,
+    #[clap(
+        long,
+        short,
+        help = "Chromosphere radius, in pixels (default: fitted to the limb)"
+    )]
+    radius: Option<f32>,
+
+    #[clap(
+        long,
+        short,
+        help = "Disk detection threshold (default: estimated from the image)"
+    )]
+    threshold: Option<f32>,
 
     #[clap(long, short, help = "Output image")]
     output: String,
@@ -96,11 +109,36 @@ impl RunnableSubcommand for Composite {
         info!("Normalizing to 16 bit boundaries");
         let normalized = overlaid.normalize(0.0, 65535.0).unwrap();
 
-        info!("Compositing chromosphere and prominence layers");
-        let middle_x = orig_image.width / 2;
-        let middle_y = orig_image.height / 2;
+        // The disk is centered on the fitted limb where it's found, otherwise on the image
+        let fitted = disk::fit_disk(&orig_image, self.threshold, InterpolationMethod::default());
+        let (mid_vec, radius) = match fitted {
+            Ok(disk) => {
+                info!(
+                    "Detected disk at {:.2}, {:.2} with radius {:.2} ± {:.2} pixels",
+                    disk.x, disk.y, disk.radius, disk.radius_err
+                );
+                (
+                    Vector::new(disk.x, disk.y, 0.0),
+                    self.radius.map(|r| r as f64).unwrap_or(disk.radius),
+                )
+            }
+            Err(why) => match self.radius {
+                Some(r) => {
+                    warn!("Disk detection failed, centering on the image: {}", why);
+                    (
+                        Vector::new(
+                            (orig_image.width / 2) as f64,
+                            (orig_image.height / 2) as f64,
+                            0.0,
+                        ),
+                        r as f64,
+                    )
+                }
+                None => return Err(why),
+            },
+        };
 
-        let mid_vec = Vector::new(middle_x as f64, middle_y as f64, 0.0);
+        info!("Compositing chromosphere and prominence layers");
 
         let border_blur_margin = 5.0;
         let mut composited = orig_image.clone();
@@ -111,12 +149,12 @@ impl RunnableSubcommand for Composite {
                 // Radial distance from the center of the disc at the pixel
                 let r = mid_vec.distance_to(&p);
 
-                if r <= self.radius as f64 {
+                if r <= radius {
                     let a = composited.get(x, y);
                     let b = normalized.get(x, y);
 
-                    let f = if self.radius as f64 - r <= border_blur_margin {
-                        let frac = ((self.radius as f64 - r) / border_blur_margin) as f32;
+                    let f = if radius - r <= border_blur_margin {
+                        let frac = ((radius - r) / border_blur_margin) as f32;
                         b * frac + (1.0 - frac) * a
                     } else {
                         b
@@ -0,0 +1,296 @@
+use anyhow::Result;
+use clap::Parser;
+
+use solhat::anaysis::frame_sigma_analysis;
+use solhat::calibrationframe::CalibrationImage;
+use solhat::calibrationframe::ComputeMethod;
+use solhat::context::*;
+use solhat::datasource::{ByteOrder, PixelFormat};
+use solhat::demosaic::DemosaicMethod;
+use solhat::drizzle::DrizzleKernel;
+use solhat::drizzle::Scale;
+use solhat::drizzle::StackAlgorithm;
+use solhat::extract::{extract_frames, frame_window_filter};
+use solhat::interpolation::InterpolationMethod;
+use solhat::limiting::frame_limit_determinate;
+use solhat::offsetting::frame_offset_analysis;
+use solhat::offsetting::RegistrationMethod;
+use solhat::output::OutputScaling;
+use solhat::sharpen::SharpenParameters;
+use solhat::target::Target;
+use solhat::timestamp::TimeStamp;
+use solhat::weighting::FrameWeighting;
+
+use crate::subs::runnable::RunnableSubcommand;
+
+pb_create!();
+
+#[derive(Parser)]
+#[command(author, version, about = "Extract selected frames to a new SER file", long_about = None)]
+pub struct Extract {
+    #[clap(
+        long,
+        short,
+        help = "Input files (SER, AVI, FITS, or a directory of images)"
+    )]
+    input_files: Vec<String>,
+
+    #[clap(long, short, help = "Output SER file")]
+    output: String,
+
+    #[clap(long, short, help = "Flat frame file")]
+    flat: Option<String>,
+
+    #[clap(long, short, help = "Dark frame file")]
+    dark: Option<String>,
+
+    #[clap(long, short = 'D', help = "Dark Flat frame file")]
+    darkflat: Option<String>,
+
+    #[clap(long, short, help = "Bias frame file")]
+    bias: Option<String>,
+
+    #[clap(long, short = 'p', help = "Hot pixel map")]
+    hotpixelmap: Option<String>,
+
+    #[clap(long, short, help = "Object detection threshold")]
+    threshold: Option<f64>,
+
+    #[clap(long, short = 's', help = "Minimum sigma value")]
+    minsigma: Option<f64>,
+
+    #[clap(long, short = 'S', help = "Maximum sigma value")]
+    maxsigma: Option<f64>,
+
+    #[clap(
+        long,
+        short = 'P',
+        help = "Keep only the top percentage of frames by quality (0-100)"
+    )]
+    percentofmax: Option<f64>,
+
+    #[clap(
+        long,
+        short = 'n',
+        help = "Keep only this many of the best frames by quality"
+    )]
+    top: Option<usize>,
+
+    #[clap(long, help = "First frame number to extract")]
+    first: Option<usize>,
+
+    #[clap(long, help = "Last frame number to extract")]
+    last: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Start of the time window to extract (UTC, YYYY-MM-DDTHH:MM:SS)"
+    )]
+    start_time: Option<String>,
+
+    #[clap(
+        long,
+        help = "End of the time window to extract (UTC, YYYY-MM-DDTHH:MM:SS)"
+    )]
+    end_time: Option<String>,
+
+    #[clap(long, short, help = "Center the target in each frame")]
+    center: bool,
+
+    #[clap(
+        long,
+        help = "Demosaic color frames (malvar, bilinear, superpixel, red). By default the raw mosaic is kept"
+    )]
+    demosaic: Option<String>,
+
+    #[clap(
+        long,
+        help = "Byte order of 16-bit pixel data, overriding the header flag and detection (auto, little, big)"
+    )]
+    byte_order: Option<String>,
+
+    #[clap(
+        long,
+        help = "Significant bits per pixel (e.g. 10, 12, 14), overriding the file header"
+    )]
+    bit_depth: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Don't shift pixel values of less than 16 bits to fill the 16-bit range"
+    )]
+    no_depth_shift: bool,
+}
+
+#[async_trait::async_trait]
+impl RunnableSubcommand for Extract {
+    async fn run(&self) -> Result<()> {
+        pb_set_print!();
+
+        let demosaic_method =
+            DemosaicMethod::from(&self.demosaic.to_owned().unwrap_or("raw".to_owned()))?;
+        let pixel_format = PixelFormat {
+            byte_order: ByteOrder::from(&self.byte_order.to_owned().unwrap_or("auto".to_owned()))?,
+            bit_depth: self.bit_depth,
+            shift_to_16bit: !self.no_depth_shift,
+        };
+        let start_time = self
+            .start_time
+            .as_ref()
+            .map(|s| TimeStamp::parse(s))
+            .transpose()?;
+        let end_time = self
+            .end_time
+            .as_ref()
+            .map(|s| TimeStamp::parse(s))
+            .transpose()?;
+
+        let master_flat = if let Some(inputs) = &self.flat {
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                demosaic_method,
+                &pixel_format,
+            )?
+        } else {
+            CalibrationImage::new_empty()
+        };
+
+        let master_darkflat = if let Some(inputs) = &self.darkflat {
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                demosaic_method,
+                &pixel_format,
+            )?
+        } else {
+            CalibrationImage::new_empty()
+        };
+
+        let master_dark = if let Some(inputs) = &self.dark {
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                demosaic_method,
+                &pixel_format,
+            )?
+        } else {
+            CalibrationImage::new_empty()
+        };
+
+        let master_bias = if let Some(inputs) = &self.bias {
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                demosaic_method,
+                &pixel_format,
+            )?
+        } else {
+            CalibrationImage::new_empty()
+        };
+
+        info!("Creating process context...");
+        let mut context: ProcessContext = ProcessContext::create_with_calibration_frames(
+            &ProcessParameters {
+                input_files: self.input_files.clone(),
+                obj_detection_threshold: self.threshold.unwrap_or(5000.0),
+                obs_latitude: 0.0,
+                obs_longitude: 0.0,
+                target: Target::Sun,
+                crop_width: None,
+                crop_height: None,
+                max_frames: None,
+                min_sigma: self.minsigma,
+                max_sigma: self.maxsigma,
+                top_percentage: self.percentofmax,
+                drizzle_scale: Scale::default(),
+                drizzle_kernel: DrizzleKernel::default(),
+                drizzle_pixfrac: 1.0,
+                interpolation: InterpolationMethod::default(),
+                algorithm: StackAlgorithm::Average,
+                stack_kappa: 2.5,
+                initial_rotation: 0.0,
+                flat_inputs: self.flat.to_owned(),
+                dark_inputs: self.dark.to_owned(),
+                darkflat_inputs: self.darkflat.to_owned(),
+                bias_inputs: self.bias.to_owned(),
+                hot_pixel_map: self.hotpixelmap.to_owned(),
+                analysis_window_size: 128,
+                vert_offset: 0,
+                horiz_offset: 0,
+                ap_tile_size: None,
+                ap_search_radius: 8,
+                ap_top_percentage: None,
+                ap_reference_frames: 10,
+                registration: RegistrationMethod::CenterOfMass,
+                demosaic_method,
+                pixel_format,
+                frame_cache_mb: 0, // Captures worth trimming are far larger than any useful cache
+                output_scaling: OutputScaling::default(),
+                frame_weighting: FrameWeighting::default(),
+                sharpen: SharpenParameters::default(),
+            },
+            master_flat,
+            master_darkflat,
+            master_dark,
+            master_bias,
+        )?;
+
+        // Narrowing by frame number and time is cheap, so it's done ahead of any analysis
+        context.frame_records =
+            frame_window_filter(&context, self.first, self.last, start_time, end_time)?;
+
+        let select_by_quality = self.minsigma.is_some()
+            || self.maxsigma.is_some()
+            || self.percentofmax.is_some()
+            || self.top.is_some();
+
+        if select_by_quality {
+            info!("Calculating frame sigma");
+            pb_set_prefix!("Calculating Frame Sigma");
+            pb_set_length!(context.frame_records.len());
+            context.frame_records = frame_sigma_analysis(&mut context, |_fr| {
+                pb_inc!();
+            })?;
+
+            info!("Applying frame limits");
+            pb_zero!();
+            pb_set_prefix!("Applying Frame Limits");
+            pb_set_length!(context.frame_records.len());
+            context.frame_records = frame_limit_determinate(&mut context, |_fr| {
+                pb_inc!();
+            })?;
+
+            // Frame limiting leaves the records sorted by descending sigma
+            if let Some(top) = self.top {
+                context.frame_records.truncate(top);
+            }
+        }
+
+        if self.center {
+            info!("Computing center of mass offsets for frames");
+            pb_zero!();
+            pb_set_prefix!("Computing Registration Offsets for Frames");
+            pb_set_length!(context.frame_records.len());
+            context.frame_records = frame_offset_analysis(&mut context, |_fr| {
+                pb_inc!();
+            })?;
+        }
+
+        if context.frame_records.is_empty() {
+            println!("Zero frames selected. Cannot continue");
+        } else {
+            info!("Writing {} frames", context.frame_records.len());
+            pb_zero!();
+            pb_set_prefix!("Writing Frames");
+            pb_set_length!(context.frame_records.len());
+            let frame_count = extract_frames(&mut context, &self.output, self.center, |_fr| {
+                pb_inc!();
+            })?;
+            info!("Extracted {} frames to {}", frame_count, self.output);
+        }
+
+        pb_done!();
+        Ok(())
+    }
+}
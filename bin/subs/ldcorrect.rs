@@ -4,7 +4,9 @@ use crate::subs::runnable::RunnableSubcommand;
 use anyhow::Result;
 use clap::Parser;
 use sciimg::path;
+use solhat::interpolation::InterpolationMethod;
 use solhat::ldcorrect;
+use solhat::limbdarkening::LimbDarkeningModel;
 
 pb_create_spinner!();
 
@@ -14,10 +16,41 @@ pub struct LdCorrect {
     #[clap(long, short, help = "Input image")]
     input_file: String,
 
-    #[clap(long, short, help = "Solar radius in pixels")]
-    radius_pixels: usize,
+    #[clap(
+        long,
+        short,
+        help = "Solar radius in pixels (default: fitted to the limb)"
+    )]
+    radius_pixels: Option<f64>,
 
-    #[clap(long, short, help = "Limb darkening coefficient")]
+    #[clap(
+        long,
+        short = 'x',
+        requires = "center_y",
+        help = "Disk center X in pixels (default: fitted to the limb)"
+    )]
+    center_x: Option<f64>,
+
+    #[clap(
+        long,
+        short = 'y',
+        requires = "center_x",
+        help = "Disk center Y in pixels (default: fitted to the limb)"
+    )]
+    center_y: Option<f64>,
+
+    #[clap(
+        long,
+        short = 'M',
+        help = "Limb darkening model (linear, quadratic, sqrt, log, neckel)"
+    )]
+    model: Option<String>,
+
+    #[clap(
+        long,
+        short,
+        help = "Limb darkening coefficients of the model, for all bands or each in turn (default: fitted)"
+    )]
     ld_coefficient: Option<Vec<f64>>,
 
     #[clap(long = "margin", short = 'm', help = "Composite margin blur")]
@@ -26,6 +59,12 @@ pub struct LdCorrect {
     #[clap(long, short = 'I', help = "Invert chromosphere luminance")]
     inverted_chromosphere: bool,
 
+    #[clap(
+        long,
+        help = "Interpolation used to sample the limb when fitting the disk (nearest, bilinear, bicubic, lanczos3)"
+    )]
+    interpolation: Option<String>,
+
     #[clap(long, short, help = "Output image")]
     output: String,
 }
@@ -56,19 +95,34 @@ impl RunnableSubcommand for LdCorrect {
                 0.0
             };
 
-        match ldcorrect::limb_darkening_correction(
+        // Clap requires the center X and Y to be given together
+        let center = self.center_x.zip(self.center_y);
+
+        let model =
+            LimbDarkeningModel::from(&self.model.to_owned().unwrap_or("linear".to_owned()))?;
+
+        let interpolation = InterpolationMethod::from(
+            &self
+                .interpolation
+                .to_owned()
+                .unwrap_or("bilinear".to_owned()),
+        )?;
+
+        let fits = ldcorrect::limb_darkening_correction(
             &self.input_file,
             &self.output,
+            center,
             self.radius_pixels,
+            model,
             &ld_coefficient,
             composite_gradient_margin,
             self.inverted_chromosphere,
-        ) {
-            Ok(_) => {
-                vprintln!("Done")
-            }
-            Err(why) => eprintln!("Error: {}", why),
-        };
+            interpolation,
+        )?;
+        fits.iter()
+            .enumerate()
+            .for_each(|(band, fit)| println!("Band #{}: {}", band, fit));
+        vprintln!("Done");
 
         pb_done!();
         Ok(())
@@ -3,6 +3,8 @@ use anyhow::Result;
 use clap::Parser;
 use solhat::calibrationframe::CalibrationImage;
 use solhat::calibrationframe::ComputeMethod;
+use solhat::datasource::{ByteOrder, PixelFormat};
+use solhat::demosaic::DemosaicMethod;
 use solhat::hotpixel;
 
 pb_create_spinner!();
@@ -10,7 +12,11 @@ pb_create_spinner!();
 #[derive(Parser)]
 #[command(author, version, about = "Create a calibration as the median of pixels", long_about = None)]
 pub struct Median {
-    #[clap(long, short, help = "Input ser file")]
+    #[clap(
+        long,
+        short,
+        help = "Input file (SER, AVI, FITS, or a directory of images)"
+    )]
     input_files: String,
 
     #[clap(long, short, help = "Output image")]
@@ -18,14 +24,49 @@ pub struct Median {
 
     #[clap(long, short = 'p', help = "Hot pixel map")]
     hotpixelmap: Option<String>,
+
+    #[clap(
+        long,
+        help = "Demosaic method for color sensors (malvar, bilinear, superpixel, red, raw)"
+    )]
+    demosaic: Option<String>,
+
+    #[clap(
+        long,
+        help = "Byte order of 16-bit pixel data, overriding the header flag and detection (auto, little, big)"
+    )]
+    byte_order: Option<String>,
+
+    #[clap(
+        long,
+        help = "Significant bits per pixel (e.g. 10, 12, 14), overriding the file header"
+    )]
+    bit_depth: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Don't shift pixel values of less than 16 bits to fill the 16-bit range"
+    )]
+    no_depth_shift: bool,
 }
 
 #[async_trait::async_trait]
 impl RunnableSubcommand for Median {
     async fn run(&self) -> Result<()> {
         pb_set_print!();
-        let mut calimage =
-            CalibrationImage::new_from_file(&self.input_files, ComputeMethod::Median)?;
+        let demosaic_method =
+            DemosaicMethod::from(&self.demosaic.to_owned().unwrap_or("malvar".to_owned()))?;
+        let pixel_format = PixelFormat {
+            byte_order: ByteOrder::from(&self.byte_order.to_owned().unwrap_or("auto".to_owned()))?,
+            bit_depth: self.bit_depth,
+            shift_to_16bit: !self.no_depth_shift,
+        };
+        let mut calimage = CalibrationImage::new_from_file(
+            &self.input_files,
+            ComputeMethod::Median,
+            demosaic_method,
+            &pixel_format,
+        )?;
 
         if let Some(hpm_path) = &self.hotpixelmap {
             let hpm = hotpixel::load_hotpixel_map(hpm_path)?;
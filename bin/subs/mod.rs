@@ -145,10 +145,13 @@ macro_rules! pb_done_with_error {
 }
 
 pub mod composite;
+pub mod extract;
 pub mod ldcorrect;
 pub mod mean;
 pub mod median;
 pub mod preprocess;
 pub mod process;
+pub mod radialprofile;
 pub mod serinfo;
+pub mod sharpen;
 pub mod threshtest;
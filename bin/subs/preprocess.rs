@@ -5,10 +5,17 @@ use clap::Parser;
 use solhat::calibrationframe::CalibrationImage;
 use solhat::calibrationframe::ComputeMethod;
 use solhat::context::*;
+use solhat::datasource::PixelFormat;
+use solhat::demosaic::DemosaicMethod;
+use solhat::drizzle::DrizzleKernel;
 use solhat::drizzle::Scale;
 use solhat::drizzle::StackAlgorithm;
-use solhat::ser::SerFile;
+use solhat::interpolation::InterpolationMethod;
+use solhat::offsetting::RegistrationMethod;
+use solhat::output::OutputScaling;
+use solhat::sharpen::SharpenParameters;
 use solhat::target::Target;
+use solhat::weighting::FrameWeighting;
 
 use crate::subs::runnable::RunnableSubcommand;
 
@@ -17,7 +24,11 @@ pb_create!();
 #[derive(Parser)]
 #[command(author, version, about = "Preprocess an observation", long_about = None)]
 pub struct PreProcess {
-    #[clap(long, short, help = "Input ser files")]
+    #[clap(
+        long,
+        short,
+        help = "Input files (SER, AVI, FITS, or a directory of images)"
+    )]
     input_files: Vec<String>,
 
     #[clap(long, short, help = "Flat frame file")]
@@ -69,7 +80,7 @@ pub struct PreProcess {
     #[clap(
         long,
         short = 'P',
-        help = "Scale maximum value to percentage max possible (0-100)"
+        help = "Use only the top percentage of frames by quality (0-100). Does not affect output scaling"
     )]
     percentofmax: Option<f64>,
 
@@ -79,7 +90,11 @@ pub struct PreProcess {
     #[clap(long, short = 'T', help = "Target (Moon, Sun)")]
     target: Option<String>,
 
-    #[clap(long, short = 'u', help = "Drizze upscale (1.5, 2.0, 3.0")]
+    #[clap(
+        long,
+        short = 'u',
+        help = "Drizzle upscale factor (e.g. 1.5, 2.0, 3.0)"
+    )]
     drizzle: Option<String>,
 
     #[clap(long, short = 'r', help = "Process report path")]
@@ -95,30 +110,50 @@ impl RunnableSubcommand for PreProcess {
         pb_set_print!();
 
         let master_flat = if let Some(inputs) = &self.flat {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                DemosaicMethod::default(),
+                &PixelFormat::default(),
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_darkflat = if let Some(inputs) = &self.darkflat {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                DemosaicMethod::default(),
+                &PixelFormat::default(),
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_dark = if let Some(inputs) = &self.dark {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                DemosaicMethod::default(),
+                &PixelFormat::default(),
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_bias = if let Some(inputs) = &self.bias {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                DemosaicMethod::default(),
+                &PixelFormat::default(),
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
-        let _context: ProcessContext<SerFile> = ProcessContext::create_with_calibration_frames(
+        let _context: ProcessContext = ProcessContext::create_with_calibration_frames(
             &ProcessParameters {
                 input_files: self.input_files.clone(),
                 obj_detection_threshold: self.threshold.unwrap_or(5000.0),
@@ -132,7 +167,11 @@ impl RunnableSubcommand for PreProcess {
                 max_sigma: self.maxsigma,
                 top_percentage: self.percentofmax,
                 drizzle_scale: Scale::from(&self.drizzle.to_owned().unwrap_or("1.0".to_owned()))?,
+                drizzle_kernel: DrizzleKernel::default(),
+                drizzle_pixfrac: 1.0,
+                interpolation: InterpolationMethod::default(),
                 algorithm: StackAlgorithm::Average,
+                stack_kappa: 2.5,
                 initial_rotation: self.rotation.unwrap_or(0.0),
                 flat_inputs: self.flat.to_owned(),
                 dark_inputs: self.dark.to_owned(),
@@ -142,6 +181,17 @@ impl RunnableSubcommand for PreProcess {
                 analysis_window_size: 128,
                 vert_offset: 0,
                 horiz_offset: 0,
+                ap_tile_size: None,
+                ap_search_radius: 8,
+                ap_top_percentage: None,
+                ap_reference_frames: 10,
+                registration: RegistrationMethod::CenterOfMass,
+                demosaic_method: DemosaicMethod::default(),
+                pixel_format: PixelFormat::default(),
+                frame_cache_mb: 1024,
+                output_scaling: OutputScaling::default(),
+                frame_weighting: FrameWeighting::default(),
+                sharpen: SharpenParameters::default(),
             },
             master_flat,
             master_darkflat,
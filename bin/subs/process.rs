@@ -1,18 +1,33 @@
 use anyhow::Result;
 use clap::Parser;
+use sciimg::prelude::Image;
 
+use solhat::alignmentpoints::frame_alignment_point_analysis;
 use solhat::anaysis::frame_sigma_analysis;
 use solhat::calibrationframe::CalibrationImage;
 use solhat::calibrationframe::ComputeMethod;
 use solhat::context::*;
+use solhat::coverage::{fully_covered_region, Region};
+use solhat::datasource::{ByteOrder, PixelFormat, FITS_EXTENSIONS};
+use solhat::demosaic::DemosaicMethod;
+use solhat::drizzle::DrizzleKernel;
 use solhat::drizzle::Scale;
 use solhat::drizzle::StackAlgorithm;
+use solhat::fits::FitsDataType;
+use solhat::imagesequence::has_extension;
+use solhat::interpolation::InterpolationMethod;
 use solhat::limiting::frame_limit_determinate;
 use solhat::offsetting::frame_offset_analysis;
+use solhat::offsetting::RegistrationMethod;
+use solhat::output::{
+    is_float_format, save_output, scale_for_output, validate_output, OutputScaling,
+};
+use solhat::provenance::stack_keywords;
 use solhat::rotation::frame_rotation_analysis;
-use solhat::ser::SerFile;
+use solhat::sharpen::{process_sharpening, Psf, SharpenParameters, WaveletLayer};
 use solhat::stacking::process_frame_stacking;
 use solhat::target::Target;
+use solhat::weighting::{frame_weight_determinate, FrameWeighting};
 
 use crate::subs::runnable::RunnableSubcommand;
 
@@ -21,10 +36,18 @@ pb_create!();
 #[derive(Parser)]
 #[command(author, version, about = "Process an observation", long_about = None)]
 pub struct Process {
-    #[clap(long, short, help = "Input ser files")]
+    #[clap(
+        long,
+        short,
+        help = "Input files (SER, AVI, FITS, or a directory of images)"
+    )]
     input_files: Vec<String>,
 
-    #[clap(long, short, help = "Output image")]
+    #[clap(
+        long,
+        short,
+        help = "Output image. FITS (.fit, .fits, .fts) output includes capture and processing metadata"
+    )]
     output: String,
 
     #[clap(long, short, help = "Flat frame file")]
@@ -76,7 +99,7 @@ pub struct Process {
     #[clap(
         long,
         short = 'P',
-        help = "Scale maximum value to percentage max possible (0-100)"
+        help = "Use only the top percentage of frames by quality (0-100). Does not affect output scaling"
     )]
     percentofmax: Option<f64>,
 
@@ -86,14 +109,160 @@ pub struct Process {
     #[clap(long, short = 'T', help = "Target (Moon, Sun)")]
     target: Option<String>,
 
-    #[clap(long, short = 'u', help = "Drizze upscale (1.5, 2.0, 3.0")]
+    #[clap(
+        long,
+        short = 'u',
+        help = "Drizzle upscale factor (e.g. 1.5, 2.0, 3.0)"
+    )]
     drizzle: Option<String>,
 
+    #[clap(
+        long,
+        help = "Drizzle kernel (square, point, gaussian, lanczos, interpolate)"
+    )]
+    kernel: Option<String>,
+
+    #[clap(
+        long,
+        help = "Drizzle drop size as a fraction of an input pixel (default=1.0)"
+    )]
+    pixfrac: Option<f32>,
+
+    #[clap(
+        long,
+        help = "Interpolation used to resample frames, for alignment points and to sample the limb when fitting the disk (nearest, bilinear, bicubic, lanczos3). Frames are only resampled with --kernel interpolate"
+    )]
+    interpolation: Option<String>,
+
+    #[clap(long, help = "Output path of the drizzle weight map")]
+    weight_map: Option<String>,
+
+    #[clap(
+        long,
+        help = "Output path of the coverage map, the number of frames stacked into each pixel"
+    )]
+    coverage_map: Option<String>,
+
+    #[clap(
+        long,
+        help = "Crop the stack to the largest rectangle covered by every frame"
+    )]
+    auto_crop: bool,
+
+    #[clap(
+        long,
+        help = "Stacking algorithm (average, median, minimum, sigmaclip, winsorized). Median reads every frame four times"
+    )]
+    algorithm: Option<String>,
+
+    #[clap(
+        long,
+        help = "Rejection limit, in standard deviations, of sigmaclip and winsorized stacking (default=2.5)"
+    )]
+    kappa: Option<f32>,
+
+    #[clap(
+        long,
+        help = "Weighting of frames by quality (equal, linear, rank, power, or power:<exponent>). Average and sigma clipped stacking only"
+    )]
+    weighting: Option<String>,
+
     #[clap(long, short = 'r', help = "Process report path")]
     report: Option<String>,
 
     #[clap(long, short = 'p', help = "Hot pixel map")]
     hotpixelmap: Option<String>,
+
+    #[clap(
+        long,
+        short = 'R',
+        help = "Registration method (com = center of mass, phase = phase correlation, disk = fitted limb circle)"
+    )]
+    registration: Option<String>,
+
+    #[clap(
+        long,
+        help = "Demosaic method for color sensors (malvar, bilinear, superpixel, red, raw)"
+    )]
+    demosaic: Option<String>,
+
+    #[clap(
+        long,
+        help = "Byte order of 16-bit pixel data, overriding the header flag and detection (auto, little, big)"
+    )]
+    byte_order: Option<String>,
+
+    #[clap(
+        long,
+        help = "Significant bits per pixel (e.g. 10, 12, 14), overriding the file header"
+    )]
+    bit_depth: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Don't shift pixel values of less than 16 bits to fill the 16-bit range"
+    )]
+    no_depth_shift: bool,
+
+    #[clap(
+        long,
+        help = "Memory limit, in megabytes, of decoded frames kept between stages (default=1024, 0 disables)"
+    )]
+    frame_cache: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Alignment point tile size in pixels (enables multi-point alignment)"
+    )]
+    ap_size: Option<usize>,
+
+    #[clap(long, help = "Alignment point search radius in pixels (default=8)")]
+    ap_search: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Per alignment point, use only the top percentage of frames by local quality (0-100)"
+    )]
+    ap_percent: Option<f64>,
+
+    #[clap(
+        long,
+        help = "Number of best frames used to build the alignment reference (default=10)"
+    )]
+    ap_reference: Option<usize>,
+
+    #[clap(long, help = "Data type of FITS output (float32, uint16)")]
+    fits_type: Option<String>,
+
+    #[clap(
+        long,
+        help = "Output value scaling: normalize (stretch min/max to 16 bits, default), linear (unchanged calibrated values, float TIFF or FITS only), or a fixed factor"
+    )]
+    output_scaling: Option<String>,
+
+    #[clap(
+        long,
+        help = "Sharpen the stack with wavelet layers, finest first, each as gain or gain:denoise (e.g. 2.0:1.0,1.5:0.5,1.2)"
+    )]
+    wavelets: Option<String>,
+
+    #[clap(
+        long,
+        help = "Deconvolve the stack with a Gaussian PSF of this sigma in pixels"
+    )]
+    psf_sigma: Option<f32>,
+
+    #[clap(long, help = "Deconvolve the stack with the PSF in this image")]
+    psf: Option<String>,
+
+    #[clap(long, help = "Richardson-Lucy deconvolution iterations (default=20)")]
+    rl_iterations: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Suppress sharpening ringing within this many pixels of the limb"
+    )]
+    dering: Option<usize>,
 }
 
 #[async_trait::async_trait]
@@ -101,32 +270,86 @@ impl RunnableSubcommand for Process {
     async fn run(&self) -> Result<()> {
         pb_set_print!();
 
+        let demosaic_method =
+            DemosaicMethod::from(&self.demosaic.to_owned().unwrap_or("malvar".to_owned()))?;
+        let pixel_format = PixelFormat {
+            byte_order: ByteOrder::from(&self.byte_order.to_owned().unwrap_or("auto".to_owned()))?,
+            bit_depth: self.bit_depth,
+            shift_to_16bit: !self.no_depth_shift,
+        };
+        let stack_algorithm =
+            StackAlgorithm::from(&self.algorithm.to_owned().unwrap_or("average".to_owned()))?;
+        let output_scaling = OutputScaling::from(
+            &self
+                .output_scaling
+                .to_owned()
+                .unwrap_or("normalize".to_owned()),
+        )?;
+        let fits_data_type =
+            FitsDataType::from(&self.fits_type.to_owned().unwrap_or("float32".to_owned()))?;
+        let drizzle_kernel =
+            DrizzleKernel::from(&self.kernel.to_owned().unwrap_or("square".to_owned()))?;
+
+        // Only the interpolate kernel resamples frames, the others drop input pixels as they are
+        if self.interpolation.is_some() && drizzle_kernel != DrizzleKernel::Interpolate {
+            warn!(
+                "The {:?} kernel does not resample frames, the interpolation is only used for alignment points and the disk fit",
+                drizzle_kernel
+            );
+        }
+
+        // Bad output options would otherwise only fail once the frames are stacked
+        validate_output(&self.output, output_scaling, fits_data_type)?;
+        for path in [&self.weight_map, &self.coverage_map].into_iter().flatten() {
+            validate_output(path, OutputScaling::Normalize, FitsDataType::Float32)?;
+        }
+
         let master_flat = if let Some(inputs) = &self.flat {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                demosaic_method,
+                &pixel_format,
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_darkflat = if let Some(inputs) = &self.darkflat {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                demosaic_method,
+                &pixel_format,
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_dark = if let Some(inputs) = &self.dark {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                demosaic_method,
+                &pixel_format,
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_bias = if let Some(inputs) = &self.bias {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                demosaic_method,
+                &pixel_format,
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         info!("Creating process context...");
-        let mut context: ProcessContext<SerFile> = ProcessContext::create_with_calibration_frames(
+        let mut context: ProcessContext = ProcessContext::create_with_calibration_frames(
             &ProcessParameters {
                 input_files: self.input_files.clone(),
                 obj_detection_threshold: self.threshold.unwrap_or(5000.0),
@@ -140,7 +363,16 @@ impl RunnableSubcommand for Process {
                 max_sigma: self.maxsigma,
                 top_percentage: self.percentofmax,
                 drizzle_scale: Scale::from(&self.drizzle.to_owned().unwrap_or("1.0".to_owned()))?,
-                algorithm: StackAlgorithm::Average,
+                drizzle_kernel,
+                drizzle_pixfrac: self.pixfrac.unwrap_or(1.0),
+                interpolation: InterpolationMethod::from(
+                    &self
+                        .interpolation
+                        .to_owned()
+                        .unwrap_or("bilinear".to_owned()),
+                )?,
+                algorithm: stack_algorithm,
+                stack_kappa: self.kappa.unwrap_or(2.5),
                 initial_rotation: self.rotation.unwrap_or(0.0),
                 flat_inputs: self.flat.to_owned(),
                 dark_inputs: self.dark.to_owned(),
@@ -150,6 +382,30 @@ impl RunnableSubcommand for Process {
                 analysis_window_size: 128,
                 vert_offset: 0,
                 horiz_offset: 0,
+                ap_tile_size: self.ap_size,
+                ap_search_radius: self.ap_search.unwrap_or(8),
+                ap_top_percentage: self.ap_percent,
+                ap_reference_frames: self.ap_reference.unwrap_or(10),
+                registration: RegistrationMethod::from(
+                    &self.registration.to_owned().unwrap_or("com".to_owned()),
+                )?,
+                demosaic_method,
+                pixel_format,
+                frame_cache_mb: self.frame_cache.unwrap_or(1024),
+                output_scaling,
+                frame_weighting: FrameWeighting::from(
+                    &self.weighting.to_owned().unwrap_or("equal".to_owned()),
+                )?,
+                sharpen: SharpenParameters {
+                    wavelet_layers: match &self.wavelets {
+                        Some(w) => WaveletLayer::parse_layers(w)?,
+                        None => vec![],
+                    },
+                    psf: Psf::from_options(self.psf_sigma, &self.psf)?,
+                    iterations: self.rl_iterations.unwrap_or(20),
+                    dering_width: self.dering,
+                    limb_threshold: self.threshold.unwrap_or(5000.0) as f32,
+                },
             },
             master_flat,
             master_darkflat,
@@ -161,7 +417,7 @@ impl RunnableSubcommand for Process {
         info!("Calculating frame sigma");
         pb_set_prefix!("Calculating Frame Sigma");
         pb_set_length!(context.frame_records.len());
-        context.frame_records = frame_sigma_analysis(&context, |_fr| {
+        context.frame_records = frame_sigma_analysis(&mut context, |_fr| {
             pb_inc!();
         })?;
 
@@ -170,7 +426,16 @@ impl RunnableSubcommand for Process {
         pb_zero!();
         pb_set_prefix!("Applying Frame Limits");
         pb_set_length!(context.frame_records.len());
-        context.frame_records = frame_limit_determinate(&context, |_fr| {
+        context.frame_records = frame_limit_determinate(&mut context, |_fr| {
+            pb_inc!();
+        })?;
+
+        // Weight the remaining frames by quality
+        info!("Computing frame weights");
+        pb_zero!();
+        pb_set_prefix!("Computing Frame Weights");
+        pb_set_length!(context.frame_records.len());
+        context.frame_records = frame_weight_determinate(&mut context, |_fr| {
             pb_inc!();
         })?;
 
@@ -179,7 +444,7 @@ impl RunnableSubcommand for Process {
         pb_zero!();
         pb_set_prefix!("Computing Parallactic Angle Frame Rotations");
         pb_set_length!(context.frame_records.len());
-        context.frame_records = frame_rotation_analysis(&context, |fr| {
+        context.frame_records = frame_rotation_analysis(&mut context, |fr| {
             info!(
                 "Rotation for frame is {} degrees",
                 fr.computed_rotation.to_degrees()
@@ -187,15 +452,29 @@ impl RunnableSubcommand for Process {
             pb_inc!();
         })?;
 
-        // Compute center-of-mass offsets for each frame
-        info!("Computing center-of-mass offsets for frames");
+        // Compute registration offsets for each frame
+        info!(
+            "Computing {:?} offsets for frames",
+            context.parameters.registration
+        );
         pb_zero!();
-        pb_set_prefix!("Computing Center-of-Mass Offsets for Frames");
+        pb_set_prefix!("Computing Registration Offsets for Frames");
         pb_set_length!(context.frame_records.len());
-        context.frame_records = frame_offset_analysis(&context, |_fr| {
+        context.frame_records = frame_offset_analysis(&mut context, |_fr| {
             pb_inc!();
         })?;
 
+        // Compute local per-tile shifts against the reference frame
+        if context.parameters.ap_tile_size.is_some() {
+            info!("Computing alignment point shifts for frames");
+            pb_zero!();
+            pb_set_prefix!("Computing Alignment Point Shifts for Frames");
+            pb_set_length!(context.frame_records.len());
+            context.frame_records = frame_alignment_point_analysis(&mut context, |_fr| {
+                pb_inc!();
+            })?;
+        }
+
         if context.frame_records.is_empty() {
             println!("Zero frames to stack. Cannot continue");
         } else {
@@ -203,8 +482,8 @@ impl RunnableSubcommand for Process {
             info!("Stacking");
             pb_zero!();
             pb_set_prefix!("Stacking Frames");
-            pb_set_length!(context.frame_records.len());
-            let mut stacked_buffer = process_frame_stacking(&context, |_fr| {
+            pb_set_length!(context.frame_records.len() * stack_algorithm.num_passes());
+            let mut stacked_buffer = process_frame_stacking(&mut context, |_fr| {
                 pb_inc!();
             })?;
 
@@ -229,7 +508,37 @@ impl RunnableSubcommand for Process {
                 );
                 let x = (stacked_buffer.width - crop_width) / 2;
                 let y = (stacked_buffer.height - crop_height) / 2;
-                stacked_buffer.crop(x, y, crop_width, crop_height);
+                crop_stack(
+                    &mut stacked_buffer,
+                    &mut context,
+                    &Region {
+                        x,
+                        y,
+                        width: crop_width,
+                        height: crop_height,
+                    },
+                );
+            }
+
+            if self.auto_crop {
+                let frame_count = context.frame_records.len();
+                match context
+                    .coverage_map
+                    .as_ref()
+                    .and_then(|coverage| fully_covered_region(coverage, frame_count))
+                {
+                    Some(region) => {
+                        info!("Cropping image to the fully covered region: {:?}", region);
+                        crop_stack(&mut stacked_buffer, &mut context, &region);
+                    }
+                    None => warn!("No region is covered by every frame, not cropping"),
+                }
+            }
+
+            if context.parameters.sharpen.is_enabled() {
+                info!("Sharpening");
+                pb_set_prefix!("Sharpening");
+                stacked_buffer = process_sharpening(&mut context, &stacked_buffer)?;
             }
 
             // Let the user know some stuff...
@@ -240,17 +549,67 @@ impl RunnableSubcommand for Process {
                 stackmax,
                 context.frame_records.len()
             );
-            stacked_buffer.normalize_to_16bit();
+            // Disk detection for the header works on the values prior to normalization
+            let fits_keywords = if has_extension(&self.output, &FITS_EXTENSIONS) {
+                stack_keywords(&context, &stacked_buffer)?
+            } else {
+                vec![]
+            };
+
+            scale_for_output(&mut stacked_buffer, context.parameters.output_scaling);
             info!(
                 "Final image size: {}, {}",
                 stacked_buffer.width, stacked_buffer.height
             );
 
             // Save finalized image to disk
-            stacked_buffer.save(&self.output)?;
+            save_output(
+                &stacked_buffer,
+                &self.output,
+                context.parameters.output_scaling,
+                fits_data_type,
+                &fits_keywords,
+            )?;
+
+            if let (Some(path), Some(weight_map)) = (&self.weight_map, &context.weight_map) {
+                // Weights are sums of fractional drops, kept unscaled where the format allows
+                let scaling = if is_float_format(path) {
+                    OutputScaling::Linear
+                } else {
+                    OutputScaling::Normalize
+                };
+                let mut weight_map = weight_map.clone();
+                scale_for_output(&mut weight_map, scaling);
+                save_output(&weight_map, path, scaling, FitsDataType::Float32, &[])?;
+                info!("Saved drizzle weight map to {}", path);
+            }
+
+            if let (Some(path), Some(coverage_map)) = (&self.coverage_map, &context.coverage_map) {
+                // Frame counts are written as is
+                let scaling = OutputScaling::Factor(1.0);
+                let mut coverage_map = coverage_map.clone();
+                scale_for_output(&mut coverage_map, scaling);
+                save_output(&coverage_map, path, scaling, FitsDataType::Float32, &[])?;
+                info!("Saved coverage map to {}", path);
+            }
+        }
+
+        if let Some(report_path) = &self.report {
+            context.write_report(report_path)?;
         }
 
         pb_done!();
         Ok(())
     }
 }
+
+/// Crops the stack along with its weight and coverage maps
+fn crop_stack(stacked_buffer: &mut Image, context: &mut ProcessContext, region: &Region) {
+    stacked_buffer.crop(region.x, region.y, region.width, region.height);
+    for map in [&mut context.weight_map, &mut context.coverage_map]
+        .into_iter()
+        .flatten()
+    {
+        map.crop(region.x, region.y, region.width, region.height);
+    }
+}
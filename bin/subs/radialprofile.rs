@@ -0,0 +1,105 @@
+use crate::subs::runnable::RunnableSubcommand;
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use sciimg::path;
+use sciimg::prelude::Image;
+use solhat::disk;
+use solhat::interpolation::InterpolationMethod;
+use solhat::radialprofile::RadialProfile;
+
+pb_create_spinner!();
+
+#[derive(Parser)]
+#[command(author, version, about = "Radial intensity profile of the disk", long_about = None)]
+pub struct RadialProfileCmd {
+    #[clap(long, short, help = "Input image")]
+    input_file: String,
+
+    #[clap(
+        long,
+        short,
+        help = "Output profile, as JSON if ending in .json, else CSV"
+    )]
+    output: String,
+
+    #[clap(
+        long,
+        short = 'x',
+        help = "Profile center X in pixels (default: fitted disk center)"
+    )]
+    center_x: Option<f64>,
+
+    #[clap(
+        long,
+        short = 'y',
+        help = "Profile center Y in pixels (default: fitted disk center)"
+    )]
+    center_y: Option<f64>,
+
+    #[clap(
+        long,
+        short,
+        help = "Maximum radius in pixels (default: to the farthest image corner)"
+    )]
+    max_radius: Option<f64>,
+
+    #[clap(long, short, help = "Width of each annulus in pixels (default=1.0)")]
+    bin_width: Option<f64>,
+
+    #[clap(
+        long,
+        short,
+        help = "Disk detection threshold (default: estimated from the image)"
+    )]
+    threshold: Option<f32>,
+}
+
+#[async_trait::async_trait]
+impl RunnableSubcommand for RadialProfileCmd {
+    async fn run(&self) -> Result<()> {
+        pb_set_print!();
+
+        if !path::file_exists(&self.input_file) {
+            return Err(anyhow!("Input file not found: {}", self.input_file));
+        }
+
+        if !path::parent_exists_and_writable(&self.output) {
+            return Err(anyhow!(
+                "Output directory not found or is not writable: {}",
+                path::get_parent(&self.output)
+            ));
+        }
+
+        info!("Opening image at {}", self.input_file);
+        let image = Image::open(&self.input_file)?;
+
+        let center = match (self.center_x, self.center_y) {
+            (Some(x), Some(y)) => (x, y),
+            (None, None) => {
+                let disk = disk::fit_disk(
+                    image.get_band(0),
+                    self.threshold,
+                    InterpolationMethod::default(),
+                )?;
+                info!(
+                    "Detected disk at {:.2}, {:.2} with radius {:.2} pixels",
+                    disk.x, disk.y, disk.radius
+                );
+                (disk.x, disk.y)
+            }
+            _ => return Err(anyhow!("Both the center X and Y must be specified")),
+        };
+
+        let profile = RadialProfile::compute(
+            &image,
+            center,
+            self.max_radius,
+            self.bin_width.unwrap_or(1.0),
+        )?;
+        profile.save(&self.output)?;
+        info!("Saved radial profile to {}", self.output);
+
+        pb_done!();
+        Ok(())
+    }
+}
@@ -2,15 +2,14 @@ use anyhow::Result;
 use clap::Parser;
 use sciimg::path;
 
-use solhat::datasource::DataSource;
-use solhat::ser;
+use solhat::datasource::{self, DataSource};
 
 use crate::subs::runnable::RunnableSubcommand;
 
 #[derive(Parser)]
-#[command(author, version, about = "Print information from a SER file", long_about = None)]
+#[command(author, version, about = "Print information from a SER, AVI, or FITS file", long_about = None)]
 pub struct SerInfo {
-    #[clap(long, short, help = "Input ser file")]
+    #[clap(long, short, help = "Input file")]
     input_file: String,
 }
 
@@ -24,8 +23,8 @@ fn do_validation<F: DataSource>(ser_file: &F) -> Result<()> {
 impl RunnableSubcommand for SerInfo {
     async fn run(&self) -> Result<()> {
         if path::file_exists(self.input_file.as_str()) {
-            let ser_file =
-                ser::SerFile::load_ser(&self.input_file).expect("Unable to load SER file");
+            let ser_file = datasource::open_data_source(&[self.input_file.clone()])
+                .expect("Unable to load input file");
             do_validation(&ser_file)?;
         }
         Ok(())
@@ -0,0 +1,123 @@
+use crate::subs::runnable::RunnableSubcommand;
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use sciimg::path;
+use solhat::fits::FitsDataType;
+use solhat::output::{save_output, scale_for_output, OutputScaling};
+use solhat::sharpen::{self, Psf, SharpenParameters, WaveletLayer};
+
+pb_create_spinner!();
+
+#[derive(Parser)]
+#[command(author, version, about = "Wavelet and deconvolution sharpening", long_about = None)]
+pub struct Sharpen {
+    #[clap(long, short, help = "Input image")]
+    input_file: String,
+
+    #[clap(long, short, help = "Output image")]
+    output: String,
+
+    #[clap(
+        long,
+        short,
+        help = "Wavelet layers, finest first, each as gain or gain:denoise (e.g. 2.0:1.0,1.5:0.5,1.2)"
+    )]
+    wavelets: Option<String>,
+
+    #[clap(long, help = "Deconvolve with a Gaussian PSF of this sigma in pixels")]
+    psf_sigma: Option<f32>,
+
+    #[clap(long, help = "Deconvolve with the PSF in this image")]
+    psf: Option<String>,
+
+    #[clap(
+        long,
+        short = 'n',
+        help = "Richardson-Lucy deconvolution iterations (default=20)"
+    )]
+    rl_iterations: Option<usize>,
+
+    #[clap(
+        long,
+        short,
+        help = "Suppress ringing within this many pixels of the limb"
+    )]
+    dering: Option<usize>,
+
+    #[clap(
+        long,
+        short,
+        help = "Value separating the disk from the sky when deringing (default=5000)"
+    )]
+    threshold: Option<f32>,
+
+    #[clap(long, help = "Data type of FITS output (float32, uint16)")]
+    fits_type: Option<String>,
+
+    #[clap(
+        long,
+        help = "Output value scaling: normalize (default), linear (float TIFF or FITS only), or a fixed factor"
+    )]
+    output_scaling: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl RunnableSubcommand for Sharpen {
+    async fn run(&self) -> Result<()> {
+        pb_set_print!();
+
+        if !path::file_exists(&self.input_file) {
+            return Err(anyhow!("Input file not found: {}", self.input_file));
+        }
+
+        if !path::parent_exists_and_writable(&self.output) {
+            return Err(anyhow!(
+                "Output directory not found or is not writable: {}",
+                path::get_parent(&self.output)
+            ));
+        }
+
+        let params = SharpenParameters {
+            wavelet_layers: match &self.wavelets {
+                Some(w) => WaveletLayer::parse_layers(w)?,
+                None => vec![],
+            },
+            psf: Psf::from_options(self.psf_sigma, &self.psf)?,
+            iterations: self.rl_iterations.unwrap_or(20),
+            dering_width: self.dering,
+            limb_threshold: self.threshold.unwrap_or(5000.0),
+        };
+
+        if !params.is_enabled() {
+            return Err(anyhow!(
+                "Nothing to do. Specify wavelet layers and/or a PSF to deconvolve with"
+            ));
+        }
+
+        let output_scaling = OutputScaling::from(
+            &self
+                .output_scaling
+                .to_owned()
+                .unwrap_or("normalize".to_owned()),
+        )?;
+
+        info!("Opening image at {}", self.input_file);
+        let image = sharpen::open_image(&self.input_file)?;
+
+        info!("Sharpening with {:?}", params);
+        let mut sharpened = sharpen::sharpen(&image, &params)?;
+
+        scale_for_output(&mut sharpened, output_scaling);
+        save_output(
+            &sharpened,
+            &self.output,
+            output_scaling,
+            FitsDataType::from(&self.fits_type.to_owned().unwrap_or("float32".to_owned()))?,
+            &[],
+        )?;
+        info!("Saved sharpened image to {}", self.output);
+
+        pb_done!();
+        Ok(())
+    }
+}
@@ -4,11 +4,18 @@ use clap::Parser;
 use solhat::calibrationframe::CalibrationImage;
 use solhat::calibrationframe::ComputeMethod;
 use solhat::context::*;
+use solhat::datasource::PixelFormat;
+use solhat::demosaic::DemosaicMethod;
+use solhat::drizzle::DrizzleKernel;
 use solhat::drizzle::Scale;
 use solhat::drizzle::StackAlgorithm;
-use solhat::ser::SerFile;
+use solhat::interpolation::InterpolationMethod;
+use solhat::offsetting::RegistrationMethod;
+use solhat::output::OutputScaling;
+use solhat::sharpen::SharpenParameters;
 use solhat::target::Target;
 use solhat::threshtest::compute_threshtest_image;
+use solhat::weighting::FrameWeighting;
 
 use crate::subs::runnable::RunnableSubcommand;
 
@@ -17,7 +24,11 @@ pb_create_spinner!();
 #[derive(Parser)]
 #[command(author, version, about = "Compute a threshold test frame", long_about = None)]
 pub struct ThreshTest {
-    #[clap(long, short, help = "Input ser files")]
+    #[clap(
+        long,
+        short,
+        help = "Input files (SER, AVI, FITS, or a directory of images)"
+    )]
     input_files: Vec<String>,
 
     #[clap(long, short, help = "Output image")]
@@ -45,30 +56,50 @@ impl RunnableSubcommand for ThreshTest {
         pb_set_print!();
 
         let master_flat = if let Some(inputs) = &self.flat {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                DemosaicMethod::default(),
+                &PixelFormat::default(),
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_darkflat = if let Some(inputs) = &self.darkflat {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                DemosaicMethod::default(),
+                &PixelFormat::default(),
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_dark = if let Some(inputs) = &self.dark {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                DemosaicMethod::default(),
+                &PixelFormat::default(),
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
         let master_bias = if let Some(inputs) = &self.bias {
-            CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+            CalibrationImage::new_from_file(
+                inputs,
+                ComputeMethod::Mean,
+                DemosaicMethod::default(),
+                &PixelFormat::default(),
+            )?
         } else {
             CalibrationImage::new_empty()
         };
 
-        let context: ProcessContext<SerFile> = ProcessContext::create_with_calibration_frames(
+        let context: ProcessContext = ProcessContext::create_with_calibration_frames(
             &ProcessParameters {
                 input_files: self.input_files.clone(),
                 obj_detection_threshold: self.threshold.unwrap_or(5000.0),
@@ -81,8 +112,12 @@ impl RunnableSubcommand for ThreshTest {
                 min_sigma: None,
                 max_sigma: None,
                 top_percentage: None,
-                drizzle_scale: Scale::Scale1_0,
+                drizzle_scale: Scale::default(),
+                drizzle_kernel: DrizzleKernel::default(),
+                drizzle_pixfrac: 1.0,
+                interpolation: InterpolationMethod::default(),
                 algorithm: StackAlgorithm::Average,
+                stack_kappa: 2.5,
                 initial_rotation: 0.0,
                 flat_inputs: self.flat.to_owned(),
                 dark_inputs: self.dark.to_owned(),
@@ -92,6 +127,17 @@ impl RunnableSubcommand for ThreshTest {
                 analysis_window_size: 128,
                 vert_offset: 0,
                 horiz_offset: 0,
+                ap_tile_size: None,
+                ap_search_radius: 8,
+                ap_top_percentage: None,
+                ap_reference_frames: 10,
+                registration: RegistrationMethod::CenterOfMass,
+                demosaic_method: DemosaicMethod::default(),
+                pixel_format: PixelFormat::default(),
+                frame_cache_mb: 1024,
+                output_scaling: OutputScaling::default(),
+                frame_weighting: FrameWeighting::default(),
+                sharpen: SharpenParameters::default(),
             },
             master_flat,
             master_darkflat,
@@ -0,0 +1,412 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use rayon::prelude::*;
+use sciimg::prelude::*;
+use sciimg::quality;
+
+use crate::context::ProcessContext;
+use crate::datasource::DataSource;
+use crate::drizzle::{AverageStackBuffer, Drizzle, Scale, StackAlgorithmImpl, StackBuffer};
+use crate::error::SolHatError;
+use crate::framerecord::FrameRecord;
+use crate::interpolation::InterpolationMethod;
+use crate::phasecorrelation::parabolic_peak;
+
+/// A single alignment point (AP) of the registration grid. Positions are in the
+/// globally registered (reference) frame space.
+#[derive(Debug, Clone)]
+pub struct AlignmentPoint {
+    pub x: usize,
+    pub y: usize,
+    pub shift_h: f32,
+    pub shift_v: f32,
+    pub quality: f64,
+    pub valid: bool,    // A shift was successfully measured for this point
+    pub accepted: bool, // The frame ranks within the per-point quality limit
+}
+
+/// Grid of local residual shifts for a frame, relative to the reference frame.
+#[derive(Debug, Clone)]
+pub struct ShiftMap {
+    pub tile_size: usize,
+    pub grid_width: usize,
+    pub grid_height: usize,
+    pub points: Vec<AlignmentPoint>,
+}
+
+impl ShiftMap {
+    pub fn new(width: usize, height: usize, tile_size: usize) -> Self {
+        let grid_width = (width / tile_size).max(1);
+        let grid_height = (height / tile_size).max(1);
+
+        let points = iproduct!(0..grid_height, 0..grid_width)
+            .map(|(gy, gx)| AlignmentPoint {
+                x: tile_size / 2 + gx * tile_size,
+                y: tile_size / 2 + gy * tile_size,
+                shift_h: 0.0,
+                shift_v: 0.0,
+                quality: 0.0,
+                valid: false,
+                accepted: true,
+            })
+            .collect();
+
+        ShiftMap {
+            tile_size,
+            grid_width,
+            grid_height,
+            points,
+        }
+    }
+
+    pub fn get(&self, gx: usize, gy: usize) -> &AlignmentPoint {
+        &self.points[gy * self.grid_width + gx]
+    }
+
+    /// Fractional grid coordinate of an x/y point in reference space, clamped to the grid
+    fn grid_coordinate(&self, x: f32, y: f32) -> (f32, f32) {
+        let half = self.tile_size as f32 / 2.0;
+        let gx = ((x - half) / self.tile_size as f32).clamp(0.0, (self.grid_width - 1) as f32);
+        let gy = ((y - half) / self.tile_size as f32).clamp(0.0, (self.grid_height - 1) as f32);
+        (gx, gy)
+    }
+
+    /// Local displacement at an x/y point in reference space, bilinearly interpolated
+    /// from the surrounding alignment points. Points without a measured shift contribute
+    /// zero displacement.
+    pub fn displacement_at(&self, x: f32, y: f32) -> (f32, f32) {
+        let (gx, gy) = self.grid_coordinate(x, y);
+
+        let gx0 = gx.floor() as usize;
+        let gy0 = gy.floor() as usize;
+        let gx1 = (gx0 + 1).min(self.grid_width - 1);
+        let gy1 = (gy0 + 1).min(self.grid_height - 1);
+        let xd = gx - gx0 as f32;
+        let yd = gy - gy0 as f32;
+
+        let shift = |gx: usize, gy: usize| {
+            let ap = self.get(gx, gy);
+            if ap.valid {
+                (ap.shift_h, ap.shift_v)
+            } else {
+                (0.0, 0.0)
+            }
+        };
+
+        let (h00, v00) = shift(gx0, gy0);
+        let (h01, v01) = shift(gx1, gy0);
+        let (h10, v10) = shift(gx0, gy1);
+        let (h11, v11) = shift(gx1, gy1);
+
+        let h0 = h10 * yd + h00 * (1.0 - yd);
+        let h1 = h11 * yd + h01 * (1.0 - yd);
+        let v0 = v10 * yd + v00 * (1.0 - yd);
+        let v1 = v11 * yd + v01 * (1.0 - yd);
+
+        (h1 * xd + h0 * (1.0 - xd), v1 * xd + v0 * (1.0 - xd))
+    }
+
+    /// Whether the alignment point nearest to the x/y point accepts this frame
+    pub fn accepted_at(&self, x: f32, y: f32) -> bool {
+        let (gx, gy) = self.grid_coordinate(x, y);
+        self.get(gx.round() as usize, gy.round() as usize).accepted
+    }
+}
+
+/// Applies the frame's global offset and rotation, producing an image in reference space
+fn register_frame(
+    frame: &Image,
+    fr: &FrameRecord,
+    interpolation: InterpolationMethod,
+) -> Result<Image> {
+    let mut drizzle = Drizzle::new(
+        frame.width,
+        frame.height,
+        Scale::default(),
+        0,
+        0,
+        StackAlgorithmImpl::Average(AverageStackBuffer::new(
+            frame.width,
+            frame.height,
+            frame.num_bands(),
+        )?),
+    );
+    drizzle.set_interpolation(interpolation);
+    drizzle.add_with_transform(frame, &fr.offset, fr.computed_rotation)?;
+    drizzle.get_finalized()
+}
+
+fn tile_mean(buffer: &ImageBuffer, cx: usize, cy: usize, tile_size: usize) -> f64 {
+    let half = tile_size / 2;
+    let mut ttl = 0.0;
+    for y in (cy - half)..(cy + half) {
+        for x in (cx - half)..(cx + half) {
+            ttl += buffer.get(x, y) as f64;
+        }
+    }
+    ttl / (half * half * 4) as f64
+}
+
+/// Zero-mean normalized cross-correlation between the reference tile centered on cx/cy and
+/// the frame tile displaced by dx/dy
+fn tile_correlation(
+    reference: &ImageBuffer,
+    frame: &ImageBuffer,
+    cx: usize,
+    cy: usize,
+    tile_size: usize,
+    dx: i32,
+    dy: i32,
+) -> f64 {
+    let half = tile_size / 2;
+    let fx = (cx as i32 + dx) as usize;
+    let fy = (cy as i32 + dy) as usize;
+
+    let ref_mean = tile_mean(reference, cx, cy, tile_size);
+    let frm_mean = tile_mean(frame, fx, fy, tile_size);
+
+    let mut num = 0.0;
+    let mut ref_var = 0.0;
+    let mut frm_var = 0.0;
+    for ty in 0..(half * 2) {
+        for tx in 0..(half * 2) {
+            let r = reference.get(cx - half + tx, cy - half + ty) as f64 - ref_mean;
+            let f = frame.get(fx - half + tx, fy - half + ty) as f64 - frm_mean;
+            num += r * f;
+            ref_var += r * r;
+            frm_var += f * f;
+        }
+    }
+
+    let denom = (ref_var * frm_var).sqrt();
+    if denom > 0.0 {
+        num / denom
+    } else {
+        0.0
+    }
+}
+
+/// Measures the local shift of the frame tile against the reference tile. Returns `None`
+/// if the tile plus search area falls off the image or the correlation peak lies on the
+/// edge of the search area.
+fn tile_shift(
+    reference: &ImageBuffer,
+    frame: &ImageBuffer,
+    cx: usize,
+    cy: usize,
+    tile_size: usize,
+    search_radius: usize,
+) -> Option<(f32, f32)> {
+    let margin = tile_size / 2 + search_radius + 1;
+    if cx < margin
+        || cy < margin
+        || cx + margin >= reference.width
+        || cy + margin >= reference.height
+    {
+        return None;
+    }
+
+    let r = search_radius as i32;
+    let side = (2 * r + 1) as usize;
+    let mut surface = vec![0.0; side * side];
+
+    for dy in -r..=r {
+        for dx in -r..=r {
+            surface[(dy + r) as usize * side + (dx + r) as usize] =
+                tile_correlation(reference, frame, cx, cy, tile_size, dx, dy);
+        }
+    }
+
+    let (peak_idx, _) =
+        surface.iter().enumerate().fold(
+            (0, f64::MIN),
+            |(bi, bv), (i, v)| {
+                if *v > bv {
+                    (i, *v)
+                } else {
+                    (bi, bv)
+                }
+            },
+        );
+
+    let px = peak_idx % side;
+    let py = peak_idx / side;
+    if px == 0 || py == 0 || px == side - 1 || py == side - 1 {
+        return None;
+    }
+
+    let at = |x: usize, y: usize| surface[y * side + x];
+    let sub_x = parabolic_peak(at(px - 1, py), at(px, py), at(px + 1, py)) as f32;
+    let sub_y = parabolic_peak(at(px, py - 1), at(px, py), at(px, py + 1)) as f32;
+
+    Some((px as f32 - r as f32 + sub_x, py as f32 - r as f32 + sub_y))
+}
+
+/// Computes the shift map of a registered frame against the reference frame
+pub fn compute_shift_map(
+    reference: &ImageBuffer,
+    registered: &ImageBuffer,
+    tile_size: usize,
+    search_radius: usize,
+    min_brightness: f64,
+) -> ShiftMap {
+    let mut shift_map = ShiftMap::new(reference.width, reference.height, tile_size);
+
+    shift_map.points.iter_mut().for_each(|ap| {
+        if let Some((h, v)) =
+            tile_shift(reference, registered, ap.x, ap.y, tile_size, search_radius)
+        {
+            // Skip the background. There's nothing to align on there.
+            if tile_mean(reference, ap.x, ap.y, tile_size) >= min_brightness {
+                ap.shift_h = h;
+                ap.shift_v = v;
+                ap.quality = quality::get_point_quality_estimation_on_buffer(
+                    registered, tile_size, ap.x, ap.y,
+                ) as f64;
+                ap.valid = true;
+            }
+        }
+    });
+
+    shift_map
+}
+
+/// Builds the reference frame as the mean of the highest quality, globally registered frames.
+/// Assumes the frame records have been sorted by descending sigma.
+fn build_reference_frame<F: DataSource>(
+    context: &ProcessContext<F>,
+    num_frames: usize,
+) -> Result<ImageBuffer> {
+    let first = context.frame_records[0].get_calibrated_frame(context)?;
+    let mut drizzle = Drizzle::new(
+        first.buffer.width,
+        first.buffer.height,
+        Scale::default(),
+        0,
+        0,
+        StackAlgorithmImpl::Average(AverageStackBuffer::new(
+            first.buffer.width,
+            first.buffer.height,
+            first.buffer.num_bands(),
+        )?),
+    );
+    drizzle.set_interpolation(context.parameters.interpolation);
+
+    for fr in context.frame_records.iter().take(num_frames.max(1)) {
+        let frame = fr.get_calibrated_frame(context)?;
+        drizzle.add_with_transform(&frame.buffer, &fr.offset, fr.computed_rotation)?;
+    }
+
+    Ok(drizzle.get_finalized()?.get_band(0).clone())
+}
+
+/// Marks, for each alignment point, the frames that fall within the top percentage of
+/// local quality at that point. Frames not accepted at a point do not contribute to the
+/// stack in that tile.
+fn rank_alignment_points(frame_records: &mut [FrameRecord], top_percentage: f64) {
+    let num_points = match frame_records.iter().find_map(|fr| fr.shift_map.as_ref()) {
+        Some(sm) => sm.points.len(),
+        None => return,
+    };
+
+    (0..num_points).for_each(|i| {
+        let mut qualities: Vec<f64> = frame_records
+            .iter()
+            .filter_map(|fr| fr.shift_map.as_ref())
+            .filter(|sm| sm.points[i].valid)
+            .map(|sm| sm.points[i].quality)
+            .filter(|q| !q.is_nan())
+            .collect();
+
+        if qualities.is_empty() {
+            return;
+        }
+
+        qualities.sort_by(|a, b| b.total_cmp(a));
+        let keep = ((top_percentage / 100.0) * qualities.len() as f64).ceil() as usize;
+        let cutoff = qualities[keep.clamp(1, qualities.len()) - 1];
+
+        frame_records
+            .iter_mut()
+            .filter_map(|fr| fr.shift_map.as_mut())
+            .for_each(|sm| {
+                let ap = &mut sm.points[i];
+                ap.accepted = !ap.valid || ap.quality >= cutoff;
+            });
+    });
+}
+
+/// Determines the local (per-tile) residual shifts of each frame against a reference built
+/// from the best frames. Must be run after rotation and offset analysis.
+pub fn frame_alignment_point_analysis<C, F>(
+    context: &mut ProcessContext<F>,
+    on_frame_checked: C,
+) -> Result<Vec<FrameRecord>>
+where
+    C: Fn(&FrameRecord) + Send + Sync + 'static,
+    F: DataSource + Send + Sync + 'static,
+{
+    let tile_size = match context.parameters.ap_tile_size {
+        Some(tile_size) => tile_size,
+        None => return Ok(context.frame_records.clone()),
+    };
+
+    if tile_size < 8 {
+        return Err(SolHatError::InvalidParameter(format!(
+            "Alignment point tile size too small: {}. Minimum is 8",
+            tile_size
+        ))
+        .into());
+    }
+
+    if context.parameters.ap_search_radius == 0 {
+        return Err(SolHatError::InvalidParameter(
+            "Alignment point search radius must be at least 1".to_string(),
+        )
+        .into());
+    }
+
+    if context.frame_records.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let t = Instant::now();
+    let ctx: &ProcessContext<F> = context;
+
+    info!(
+        "Building alignment point reference from {} frames",
+        ctx.parameters.ap_reference_frames
+    );
+    let reference = build_reference_frame(ctx, ctx.parameters.ap_reference_frames)?;
+
+    let mut frame_records = ctx
+        .frame_records
+        .par_iter()
+        .map(|fr| {
+            let mut fr_copy = fr.clone();
+            let frame = fr.get_calibrated_frame(ctx)?;
+            let registered = register_frame(&frame.buffer, fr, ctx.parameters.interpolation)?;
+
+            fr_copy.shift_map = Some(compute_shift_map(
+                &reference,
+                registered.get_band(0),
+                tile_size,
+                ctx.parameters.ap_search_radius,
+                ctx.parameters.obj_detection_threshold,
+            ));
+
+            on_frame_checked(&fr_copy);
+            Ok(fr_copy)
+        })
+        .collect::<Result<Vec<FrameRecord>>>()?;
+
+    if let Some(top_percentage) = ctx.parameters.ap_top_percentage {
+        rank_alignment_points(&mut frame_records, top_percentage);
+    }
+
+    context
+        .stats
+        .record_stage_timing("alignment_points", t.elapsed());
+    Ok(frame_records)
+}
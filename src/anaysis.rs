@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 use anyhow::Result;
 use rayon::prelude::*;
 use sciimg::quality;
@@ -7,7 +9,7 @@ use crate::datasource::DataSource;
 use crate::framerecord::FrameRecord;
 
 pub fn frame_sigma_analysis<C, F>(
-    context: &ProcessContext<F>,
+    context: &mut ProcessContext<F>,
     on_frame_checked: C,
 ) -> Result<Vec<FrameRecord>>
 where
@@ -18,7 +20,7 @@ where
 }
 
 pub fn frame_sigma_analysis_window_size<C, F>(
-    context: &ProcessContext<F>,
+    context: &mut ProcessContext<F>,
     window_size: usize,
     on_frame_checked: C,
 ) -> Result<Vec<FrameRecord>>
@@ -26,12 +28,14 @@ where
     C: Fn(&FrameRecord) + Send + Sync + 'static,
     F: DataSource + Send + Sync + 'static,
 {
-    let frame_records: Vec<FrameRecord> = context
+    let t = Instant::now();
+    let ctx: &ProcessContext<F> = context;
+    let frame_records = ctx
         .frame_records
         .par_iter()
         .map(|fr| {
             let mut fr_copy = fr.clone();
-            let frame = fr.get_frame(context).expect("");
+            let frame = fr.get_frame(ctx)?;
 
             let x = frame.buffer.width / 2 + fr_copy.offset.h as usize;
             let y = frame.buffer.height / 2 + fr_copy.offset.v as usize;
@@ -46,8 +50,11 @@ where
             ) as f64;
 
             on_frame_checked(&fr_copy);
-            fr_copy
+            Ok(fr_copy)
         })
-        .collect();
+        .collect::<Result<Vec<FrameRecord>>>()?;
+
+    context.stats.set_frames(&frame_records);
+    context.stats.record_stage_timing("sigma", t.elapsed());
     Ok(frame_records)
 }
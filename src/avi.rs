@@ -0,0 +1,483 @@
+// Reader for uncompressed AVI video as written by planetary capture software. Supports 8-bit
+// greyscale (Y800/GREY/Y8 or BI_RGB with a grey palette), 16-bit greyscale (Y16) and 24-bit BGR
+// frames, including OpenDML (AVI 2.0) files larger than 1GB.
+
+use std::fs::File;
+
+use anyhow::Result;
+use memmap::Mmap;
+use sciimg::{enums::ImageMode, image::Image, imagebuffer::ImageBuffer};
+
+use crate::datasource::{ColorFormatId, DataFrame, DataSource, PixelFormat};
+use crate::demosaic::DemosaicMethod;
+use crate::error::SolHatError;
+use crate::timestamp::TimeStamp;
+
+const BI_RGB: u32 = 0;
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| {
+            SolHatError::Format(format!("Unexpected end of AVI file at byte {}", at)).into()
+        })
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| {
+            SolHatError::Format(format!("Unexpected end of AVI file at byte {}", at)).into()
+        })
+}
+
+fn read_fourcc(data: &[u8], at: usize) -> Result<[u8; 4]> {
+    data.get(at..at + 4)
+        .map(|b| [b[0], b[1], b[2], b[3]])
+        .ok_or_else(|| {
+            SolHatError::Format(format!("Unexpected end of AVI file at byte {}", at)).into()
+        })
+}
+
+fn fourcc_to_string(fourcc: &[u8; 4]) -> String {
+    String::from_utf8_lossy(fourcc).trim().to_string()
+}
+
+/// The fields of the video stream's BITMAPINFOHEADER we need
+#[derive(Debug, Clone, Copy)]
+struct BitmapInfo {
+    width: usize,
+    height: usize,
+    bottom_up: bool,
+    bit_count: usize,
+    compression: u32,
+}
+
+/// State collected while walking the RIFF chunk tree
+#[derive(Default)]
+struct RiffScan {
+    stream_count: usize,
+    video_stream: Option<usize>,
+    micro_sec_per_frame: u32,
+    bitmap_info: Option<BitmapInfo>,
+    palette: Vec<[u8; 4]>, // Blue, green, red, reserved color table entries of 8-bit BI_RGB
+    frame_chunks: Vec<(usize, usize)>, // Byte offset and size of each frame's data
+}
+
+impl RiffScan {
+    fn scan(&mut self, data: &[u8], start: usize, end: usize, in_movi: bool) -> Result<()> {
+        let mut pos = start;
+        while pos + 8 <= end {
+            let id = read_fourcc(data, pos)?;
+            let size = read_u32(data, pos + 4)? as usize;
+            let body = pos + 8;
+            let body_end = (body + size).min(end);
+
+            match &id {
+                b"RIFF" | b"LIST" => {
+                    let list_type = read_fourcc(data, body)?;
+                    self.scan(data, body + 4, body_end, in_movi || &list_type == b"movi")?;
+                }
+                b"avih" => {
+                    self.micro_sec_per_frame = read_u32(data, body)?;
+                }
+                b"strh" => {
+                    if &read_fourcc(data, body)? == b"vids" && self.video_stream.is_none() {
+                        self.video_stream = Some(self.stream_count);
+                    }
+                    self.stream_count += 1;
+                }
+                b"strf" => {
+                    // Only the format of the first video stream is of interest. The strh for
+                    // a stream always preceeds its strf.
+                    if self.video_stream.is_some()
+                        && self.video_stream == self.stream_count.checked_sub(1)
+                    {
+                        let height = read_u32(data, body + 8)? as i32;
+                        let info = BitmapInfo {
+                            width: read_u32(data, body + 4)? as usize,
+                            height: height.unsigned_abs() as usize,
+                            bottom_up: height > 0,
+                            bit_count: read_u16(data, body + 14)? as usize,
+                            compression: read_u32(data, body + 16)?,
+                        };
+
+                        // The color table follows the header, with 256 entries unless given
+                        if info.compression == BI_RGB && info.bit_count == 8 {
+                            let table = body + read_u32(data, body)? as usize;
+                            let entries = match read_u32(data, body + 32)? as usize {
+                                0 => 256,
+                                n => n.min(256),
+                            };
+                            self.palette = (0..entries)
+                                .map(|i| table + i * 4)
+                                .take_while(|at| at + 4 <= body_end)
+                                .map(|at| read_fourcc(data, at))
+                                .collect::<Result<Vec<[u8; 4]>>>()?;
+                        }
+                        self.bitmap_info = Some(info);
+                    }
+                }
+                _ if in_movi => {
+                    // Frame chunks are named with the stream number followed by 'db'
+                    // (uncompressed) or 'dc' (compressed). Zero length chunks are dropped frames.
+                    let is_frame = matches!(&id[2..4], b"db" | b"dc")
+                        && std::str::from_utf8(&id[0..2])
+                            .ok()
+                            .and_then(|s| s.parse::<usize>().ok())
+                            == self.video_stream;
+                    if is_frame && size > 0 {
+                        self.frame_chunks.push((body, size));
+                    }
+                }
+                _ => {}
+            }
+
+            // Chunks are padded to an even size
+            pos = body + size + (size & 1);
+        }
+        Ok(())
+    }
+}
+
+/// Grey level of each palette index of 8-bit BI_RGB video. Returns None when the palette is
+/// missing or maps each index to its own level, so that the indices are the values. Color
+/// palettes are rejected, as the frames would need to be read as RGB.
+fn grey_levels(palette: &[[u8; 4]], file_path: &str) -> Result<Option<Vec<f32>>> {
+    if palette.iter().any(|[b, g, r, _]| b != g || g != r) {
+        return Err(SolHatError::Format(format!(
+            "{} has a color palette. Only grey palettes are supported",
+            file_path
+        ))
+        .into());
+    }
+
+    if palette
+        .iter()
+        .enumerate()
+        .all(|(i, [v, _, _, _])| *v as usize == i)
+    {
+        return Ok(None);
+    }
+
+    // Indices past the end of the palette are black
+    let mut levels: Vec<f32> = palette.iter().map(|[v, _, _, _]| *v as f32).collect();
+    levels.resize(256, 0.0);
+    Ok(Some(levels))
+}
+
+pub struct AviFile {
+    pub source_file: String,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub bit_count: usize,
+    pub compression: String,
+    pub micro_sec_per_frame: u32,
+    pub date_time: TimeStamp,
+    bottom_up: bool,
+    row_stride: usize,
+    grey_levels: Option<Vec<f32>>, // Values of palette indices, if not the indices themselves
+    frame_chunks: Vec<(usize, usize)>,
+    data: Mmap,
+    total_size: usize,
+}
+
+impl AviFile {
+    pub fn load_avi(file_path: &str) -> Result<AviFile> {
+        let data = unsafe { Mmap::map(&File::open(file_path).map_err(SolHatError::from)?) }
+            .map_err(SolHatError::from)?;
+
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"AVI " {
+            return Err(SolHatError::Format(format!("{} is not an AVI file", file_path)).into());
+        }
+
+        let mut scan = RiffScan::default();
+        scan.scan(&data, 0, data.len(), false)?;
+
+        let info = scan.bitmap_info.ok_or_else(|| {
+            SolHatError::Format(format!("No video stream found in {}", file_path))
+        })?;
+
+        let compression = read_fourcc(&info.compression.to_le_bytes(), 0)?;
+        let supported = match (info.compression, info.bit_count) {
+            (BI_RGB, 8) | (BI_RGB, 24) => true,
+            _ => matches!(
+                (&compression, info.bit_count),
+                (b"Y800", 8) | (b"Y8  ", 8) | (b"GREY", 8) | (b"Y16 ", 16)
+            ),
+        };
+        if !supported {
+            return Err(SolHatError::Format(format!(
+                "Unsupported AVI format in {}: compression '{}', {} bits per pixel. Only uncompressed video is supported",
+                file_path,
+                fourcc_to_string(&compression),
+                info.bit_count
+            ))
+            .into());
+        }
+
+        // Only BI_RGB rows are padded to four bytes, and are bottom-up unless the height is negative
+        let (row_stride, bottom_up) = if info.compression == BI_RGB {
+            ((info.width * info.bit_count + 31) / 32 * 4, info.bottom_up)
+        } else {
+            (info.width * info.bit_count / 8, false)
+        };
+
+        let grey_levels = grey_levels(&scan.palette, file_path)?;
+
+        // The file modification time marks the end of the capture
+        let duration_ticks = scan.micro_sec_per_frame as u64 * scan.frame_chunks.len() as u64 * 10;
+        let modified = TimeStamp::from_file_modified(file_path);
+
+        let avi = AviFile {
+            source_file: file_path.to_string(),
+            image_width: info.width,
+            image_height: info.height,
+            bit_count: info.bit_count,
+            compression: if info.compression == BI_RGB {
+                "BI_RGB".to_string()
+            } else {
+                fourcc_to_string(&compression)
+            },
+            micro_sec_per_frame: scan.micro_sec_per_frame,
+            date_time: TimeStamp::from_u64(modified.timestamp.saturating_sub(duration_ticks)),
+            bottom_up,
+            row_stride,
+            grey_levels,
+            frame_chunks: scan.frame_chunks,
+            total_size: data.len(),
+            data,
+        };
+
+        if stump::is_verbose() {
+            avi.print_header_details();
+        }
+
+        Ok(avi)
+    }
+
+    pub fn print_avi_header_details(&self) {
+        println!("AVI Header Values:");
+        println!("Source File: {}", self.source_file);
+        println!("Image Width: {}", self.image_width);
+        println!("Image Height: {}", self.image_height);
+        println!("Bits per Pixel: {}", self.bit_count);
+        println!("Compression: {}", self.compression);
+        println!("Microseconds per Frame: {}", self.micro_sec_per_frame);
+        println!("Frame Count: {}", self.frame_chunks.len());
+        println!("Date/Time: {:?}", self.date_time);
+        println!("Total File Size: {}", self.total_size);
+    }
+
+    fn frame_bytes(&self, frame_num: usize) -> Result<&[u8]> {
+        let (offset, size) =
+            self.frame_chunks
+                .get(frame_num)
+                .ok_or_else(|| SolHatError::FrameOutOfRange {
+                    frame: frame_num,
+                    frame_count: self.frame_chunks.len(),
+                })?;
+        self.data.get(*offset..*offset + *size).ok_or_else(|| {
+            SolHatError::Format(format!(
+                "Frame #{} extends past the end of the file",
+                frame_num
+            ))
+            .into()
+        })
+    }
+
+    pub fn get_avi_frame(&self, frame_num: usize) -> Result<Image> {
+        let bytes = self.frame_bytes(frame_num)?;
+        if bytes.len() < self.row_stride * self.image_height {
+            return Err(SolHatError::Format(format!(
+                "Frame #{} is {} bytes, expected {}",
+                frame_num,
+                bytes.len(),
+                self.row_stride * self.image_height
+            ))
+            .into());
+        }
+
+        let samples_per_pixel = if self.bit_count == 24 { 3 } else { 1 };
+        let mut planes: Vec<Vec<f32>> = (0..samples_per_pixel)
+            .map(|_| Vec::with_capacity(self.image_width * self.image_height))
+            .collect();
+
+        for y in 0..self.image_height {
+            let src_y = if self.bottom_up {
+                self.image_height - 1 - y
+            } else {
+                y
+            };
+            let row = &bytes[src_y * self.row_stride..(src_y + 1) * self.row_stride];
+
+            match self.bit_count {
+                16 => planes[0].extend(
+                    row.chunks_exact(2)
+                        .take(self.image_width)
+                        .map(|b| u16::from_le_bytes([b[0], b[1]]) as f32),
+                ),
+                8 => planes[0].extend(row.iter().take(self.image_width).map(|v| {
+                    match &self.grey_levels {
+                        Some(levels) => levels[*v as usize],
+                        None => *v as f32,
+                    }
+                })),
+                _ => (0..samples_per_pixel).for_each(|s| {
+                    planes[s].extend(
+                        row[s..]
+                            .iter()
+                            .step_by(samples_per_pixel)
+                            .take(self.image_width)
+                            .map(|v| *v as f32),
+                    )
+                }),
+            }
+        }
+
+        let mode = if self.bit_count == 16 {
+            ImageMode::U16BIT
+        } else {
+            ImageMode::U8BIT
+        };
+
+        let buffers = planes
+            .iter()
+            .map(|p| ImageBuffer::from_vec_as_mode(p, self.image_width, self.image_height, mode))
+            .collect::<Result<Vec<ImageBuffer>>>()?;
+
+        match samples_per_pixel {
+            // Samples are stored in blue, green, red order
+            3 => Image::new_from_buffers_rgb(&buffers[2], &buffers[1], &buffers[0], mode),
+            _ => Image::new_from_buffer_mono(&buffers[0]),
+        }
+    }
+
+    fn frame_timestamp(&self, frame_num: usize) -> TimeStamp {
+        TimeStamp::from_u64(
+            self.date_time.timestamp + self.micro_sec_per_frame as u64 * frame_num as u64 * 10,
+        )
+    }
+}
+
+impl DataSource for AviFile {
+    fn color_id(&self) -> ColorFormatId {
+        match self.bit_count {
+            24 => ColorFormatId::Bgr,
+            _ => ColorFormatId::Mono,
+        }
+    }
+
+    fn file_id(&self) -> String {
+        "AVI".to_string()
+    }
+
+    fn image_width(&self) -> usize {
+        self.image_width
+    }
+
+    fn image_height(&self) -> usize {
+        self.image_height
+    }
+
+    fn pixel_depth(&self) -> usize {
+        if self.bit_count == 16 {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn frame_count(&self) -> usize {
+        self.frame_chunks.len()
+    }
+
+    fn observer(&self) -> String {
+        "".to_string()
+    }
+
+    fn instrument(&self) -> String {
+        "".to_string()
+    }
+
+    fn telescope(&self) -> String {
+        "".to_string()
+    }
+
+    fn date_time(&self) -> TimeStamp {
+        self.date_time
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        self.date_time
+    }
+
+    fn total_file_size(&self) -> usize {
+        self.total_size
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        Ok(DataFrame {
+            buffer: self.get_avi_frame(frame_num)?,
+            timestamp: self.frame_timestamp(frame_num),
+        })
+    }
+
+    fn get_frame_timestamp(&self, frame_num: usize) -> Result<TimeStamp> {
+        if frame_num >= self.frame_chunks.len() {
+            return Err(SolHatError::FrameOutOfRange {
+                frame: frame_num,
+                frame_count: self.frame_chunks.len(),
+            }
+            .into());
+        }
+        Ok(self.frame_timestamp(frame_num))
+    }
+
+    fn source_file(&self) -> String {
+        self.source_file.clone()
+    }
+
+    fn open(path: &[String]) -> Result<Self> {
+        if path.len() != 1 {
+            Err(SolHatError::InvalidParameter(
+                "Only one avi file supported at this time".to_string(),
+            )
+            .into())
+        } else {
+            AviFile::load_avi(&path[0])
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.frame_chunks.is_empty() {
+            return Err(
+                SolHatError::Format(format!("No frames found in {}", self.source_file)).into(),
+            );
+        }
+        let expected = self.row_stride * self.image_height;
+        match self.frame_chunks.iter().position(|(_, s)| *s < expected) {
+            Some(i) => Err(SolHatError::Format(format!(
+                "Frame #{} of {} is truncated: {} < {} bytes",
+                i, self.source_file, self.frame_chunks[i].1, expected
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    // Only mono and BGR frames are supported, neither of which needs demosaicing
+    fn set_demosaic_method(&mut self, _method: DemosaicMethod) {}
+
+    fn set_pixel_format(&mut self, _pixel_format: &PixelFormat) -> Result<()> {
+        Ok(())
+    }
+
+    fn print_header_details(&self) {
+        self.print_avi_header_details();
+    }
+
+    fn file_hash(&self) -> String {
+        self.source_file.clone()
+    }
+}
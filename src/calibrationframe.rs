@@ -1,12 +1,13 @@
 use std::{ffi::OsStr, path::Path};
 
-use anyhow::{Error, Result};
+use anyhow::Result;
 use sciimg::prelude::*;
 
-use crate::datasource::DataSource;
+use crate::datasource::{self, DataSource, PixelFormat};
+use crate::demosaic::DemosaicMethod;
+use crate::error::SolHatError;
 use crate::mean;
 use crate::median;
-use crate::ser::SerFile;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ComputeMethod {
@@ -28,14 +29,26 @@ fn create_median_from_ser<F: DataSource + Send + Sync + 'static>(ser_file: &F) -
 }
 
 impl CalibrationImage {
-    pub fn new_from_file(file_path: &str, method: ComputeMethod) -> Result<Self> {
-        if let Some(extension) = Path::new(file_path).extension().and_then(OsStr::to_str) {
+    pub fn new_from_file(
+        file_path: &str,
+        method: ComputeMethod,
+        demosaic_method: DemosaicMethod,
+        pixel_format: &PixelFormat,
+    ) -> Result<Self> {
+        if Path::new(file_path).is_dir() {
+            CalibrationImage::new_from_data_source(file_path, method, demosaic_method, pixel_format)
+        } else if let Some(extension) = Path::new(file_path).extension().and_then(OsStr::to_str) {
             match extension.to_string().to_uppercase().as_str() {
-                "SER" => CalibrationImage::new_from_ser(file_path, method),
+                "SER" | "AVI" | "FIT" | "FITS" | "FTS" => CalibrationImage::new_from_data_source(
+                    file_path,
+                    method,
+                    demosaic_method,
+                    pixel_format,
+                ),
                 _ => CalibrationImage::new_from_image(file_path),
             }
         } else {
-            Err(Error::msg("Unable to determine file type"))
+            Err(SolHatError::UnsupportedFileType(file_path.to_string()).into())
         }
     }
 
@@ -43,11 +56,19 @@ impl CalibrationImage {
         CalibrationImage { image: None }
     }
 
-    pub fn new_from_ser(ser_path: &str, method: ComputeMethod) -> Result<Self> {
-        let ser_file = SerFile::load_ser(ser_path)?;
+    /// Computes the calibration image from the frames of a SER, AVI, FITS, or image sequence
+    pub fn new_from_data_source(
+        path: &str,
+        method: ComputeMethod,
+        demosaic_method: DemosaicMethod,
+        pixel_format: &PixelFormat,
+    ) -> Result<Self> {
+        let mut source = datasource::open_data_source(&[path.to_string()])?;
+        source.set_pixel_format(pixel_format)?;
+        source.set_demosaic_method(demosaic_method);
         let image = match method {
-            ComputeMethod::Mean => create_mean_from_ser(&ser_file)?,
-            ComputeMethod::Median => create_median_from_ser(&ser_file)?,
+            ComputeMethod::Mean => create_mean_from_ser(&source)?,
+            ComputeMethod::Median => create_median_from_ser(&source)?,
         };
         Ok(CalibrationImage { image: Some(image) })
     }
@@ -70,6 +91,20 @@ impl CalibrationImage {
         })
     }
 
+    /// Fails if the calibration image isn't of the dimensions of the frames it is applied to
+    pub fn check_dimensions(&self, name: &str, width: usize, height: usize) -> Result<()> {
+        match &self.image {
+            Some(image) if image.width != width || image.height != height => {
+                Err(SolHatError::CalibrationMismatch(format!(
+                    "{} is {}x{}, frames are {}x{}",
+                    name, image.width, image.height, width, height
+                ))
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn new_as_mean_of_image(image: &Image) -> Result<Self> {
         let mean = image.get_band(0).mean();
         Ok(CalibrationImage {
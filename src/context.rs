@@ -1,18 +1,29 @@
-use anyhow::Result;
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{Context, Result};
 use rayon::prelude::*;
 use sciimg::imagebuffer::Offset;
-use sciimg::prelude::ImageBuffer;
+use sciimg::prelude::{Image, ImageBuffer};
+use serde::Serialize;
 
 use crate::calibrationframe::CalibrationImage;
-use crate::datasource::DataSource;
-use crate::drizzle::{Scale, StackAlgorithm};
+use crate::datasource::{self, ColorFormatId, DataSource, ImageDataSource, PixelFormat};
+use crate::demosaic::DemosaicMethod;
+use crate::drizzle::{DrizzleKernel, Scale, StackAlgorithm};
 use crate::fpmap::FpMap;
+use crate::framecache::FrameCache;
 use crate::framerecord::FrameRecord;
 use crate::hotpixel;
+use crate::interpolation::InterpolationMethod;
+use crate::offsetting::RegistrationMethod;
+use crate::output::OutputScaling;
+use crate::sharpen::SharpenParameters;
+use crate::stats::ProcessReport;
 use crate::stats::ProcessStats;
 use crate::target::Target;
+use crate::weighting::FrameWeighting;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ProcessParameters {
     pub input_files: Vec<String>,
     pub obj_detection_threshold: f64,
@@ -26,7 +37,11 @@ pub struct ProcessParameters {
     pub max_sigma: Option<f64>,
     pub top_percentage: Option<f64>,
     pub drizzle_scale: Scale,
+    pub drizzle_kernel: DrizzleKernel,
+    pub drizzle_pixfrac: f32, // Drop size as a fraction of an input pixel
+    pub interpolation: InterpolationMethod,
     pub algorithm: StackAlgorithm,
+    pub stack_kappa: f32, // Rejection limit, in standard deviations, of the clipping algorithms
     pub initial_rotation: f64,
     pub flat_inputs: Option<String>,
     pub dark_inputs: Option<String>,
@@ -36,9 +51,22 @@ pub struct ProcessParameters {
     pub analysis_window_size: usize,
     pub vert_offset: i32,
     pub horiz_offset: i32,
+    pub ap_tile_size: Option<usize>,
+    pub ap_search_radius: usize,
+    pub ap_top_percentage: Option<f64>,
+    pub ap_reference_frames: usize,
+    pub registration: RegistrationMethod,
+    pub demosaic_method: DemosaicMethod,
+    pub pixel_format: PixelFormat,
+    pub frame_cache_mb: usize, // Memory limit of cached decoded frames. Zero disables the cache.
+    pub output_scaling: OutputScaling,
+    pub frame_weighting: FrameWeighting,
+    pub sharpen: SharpenParameters, // Applied to the stack before output, when enabled
 }
 
-pub struct ProcessContext<F: DataSource> {
+/// Inputs of any supported format may be mixed with the default, dynamically dispatched,
+/// data source type.
+pub struct ProcessContext<F: DataSource = Box<ImageDataSource>> {
     pub parameters: ProcessParameters,
     pub fp_map: FpMap<F>,
     pub master_flat: CalibrationImage,
@@ -48,11 +76,15 @@ pub struct ProcessContext<F: DataSource> {
     pub stats: ProcessStats,
     pub frame_records: Vec<FrameRecord>,
     pub hotpixel_mask: Option<ImageBuffer>,
+    pub frame_cache: Mutex<FrameCache>,
+    pub weight_map: Option<Image>, // Total sample weight of each pixel of the stack, once stacked
+    pub coverage_map: Option<Image>, // Number of frames covering each pixel of the stack
 }
 
 fn load_frame_records_for_data_source<F: DataSource>(
     ser_file: &F,
     number_of_frames: usize,
+    demosaic_method: DemosaicMethod,
 ) -> Vec<FrameRecord> {
     let frame_count = if ser_file.frame_count() > number_of_frames {
         number_of_frames
@@ -60,15 +92,23 @@ fn load_frame_records_for_data_source<F: DataSource>(
         ser_file.frame_count()
     };
 
+    let (frame_width, frame_height) = demosaic_method.output_size(
+        ser_file.color_id(),
+        ser_file.image_width(),
+        ser_file.image_height(),
+    );
+
     (0..frame_count)
         .map(|i| FrameRecord {
             source_file_id: ser_file.file_hash(),
             frame_id: i,
-            frame_width: ser_file.image_width(),
-            frame_height: ser_file.image_height(),
+            frame_width,
+            frame_height,
             sigma: 0.0,
             computed_rotation: 0.0,
             offset: Offset { h: 0.0, v: 0.0 },
+            shift_map: None,
+            weight: 1.0,
         })
         .collect::<Vec<FrameRecord>>()
 }
@@ -101,21 +141,35 @@ impl<F: DataSource + Send + Sync + 'static> ProcessContext<F> {
             stats: ProcessStats::default(),
             frame_records: vec![],
             hotpixel_mask: load_hot_pixel_mask(&params.hot_pixel_map)?,
+            frame_cache: Mutex::new(FrameCache::new(params.frame_cache_mb)),
+            weight_map: None,
+            coverage_map: None,
         };
 
-        params.input_files.iter().for_each(|input_file| {
+        for input_file in params.input_files.iter() {
             info!("Loading input file: {}", input_file);
             pc.fp_map
-                .open(&[input_file.to_string()])
-                .expect("Failed to open input file");
-        });
+                .open(
+                    &[input_file.to_string()],
+                    params.demosaic_method,
+                    &params.pixel_format,
+                )
+                .with_context(|| format!("Failed to open input file {}", input_file))?;
+        }
+
+        // Frames from all inputs end up on the same stack
+        datasource::check_compatible(pc.fp_map.get_map().values())?;
 
         pc.frame_records = pc
             .fp_map
             .get_map()
             .par_iter()
             .map(|(_, ser)| {
-                load_frame_records_for_data_source(ser, params.max_frames.unwrap_or(100000000))
+                load_frame_records_for_data_source(
+                    ser,
+                    params.max_frames.unwrap_or(100000000),
+                    params.demosaic_method,
+                )
             })
             .collect::<Vec<Vec<FrameRecord>>>()
             .iter()
@@ -123,6 +177,48 @@ impl<F: DataSource + Send + Sync + 'static> ProcessContext<F> {
             .map(|fr| fr.to_owned())
             .collect::<Vec<FrameRecord>>();
 
+        if let Some(fr) = pc.frame_records.first() {
+            for (name, calibration) in [
+                ("Flat", &pc.master_flat),
+                ("Dark", &pc.master_dark),
+                ("Dark flat", &pc.master_darkflat),
+                ("Bias", &pc.master_bias),
+            ] {
+                calibration.check_dimensions(name, fr.frame_width, fr.frame_height)?;
+            }
+        }
+
         Ok(pc)
     }
+
+    /// The color filter array pattern of the inputs, if frames are to be stacked as raw mosaics
+    pub fn cfa_pattern(&self) -> Option<ColorFormatId> {
+        if self.parameters.demosaic_method != DemosaicMethod::Raw {
+            return None;
+        }
+        self.fp_map
+            .get_map()
+            .values()
+            .map(|ds| ds.color_id())
+            .find(|c| c.is_mosaic())
+    }
+
+    /// Serializes the process parameters and collected statistics as JSON to `report_path`
+    pub fn write_report(&self, report_path: &str) -> Result<()> {
+        info!("Writing process report to {}", report_path);
+        let report = ProcessReport {
+            parameters: &self.parameters,
+            stats: &self.stats,
+        };
+        std::fs::write(report_path, serde_json::to_string_pretty(&report)?)?;
+        Ok(())
+    }
+}
+
+impl<F: DataSource> ProcessContext<F> {
+    /// Locks the frame cache. A worker panicking while holding the lock can't leave the cache
+    /// inconsistent, so the poisoning is cleared rather than failing every later frame read.
+    pub fn lock_frame_cache(&self) -> MutexGuard<FrameCache> {
+        self.frame_cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
 }
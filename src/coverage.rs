@@ -0,0 +1,61 @@
+use sciimg::prelude::*;
+
+/// A rectangular region of an image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Finds the largest rectangle of pixels covered by every one of the `frame_count` frames of
+/// the stack. None if no pixel is covered by all of them.
+pub fn fully_covered_region(coverage_map: &Image, frame_count: usize) -> Option<Region> {
+    if frame_count == 0 {
+        return None;
+    }
+    let buffer = coverage_map.get_band(0);
+    let (width, height) = (coverage_map.width, coverage_map.height);
+    let full_coverage = frame_count as f32;
+
+    // Each row is treated as the base of a histogram of the fully covered run above each
+    // column, in which the largest rectangle is found with a stack of increasing heights
+    let mut runs = vec![0; width];
+    let mut best: Option<Region> = None;
+    let mut best_area = 0;
+    for y in 0..height {
+        for (x, run) in runs.iter_mut().enumerate() {
+            *run = if buffer.get(x, y) >= full_coverage {
+                *run + 1
+            } else {
+                0
+            };
+        }
+
+        let mut stack: Vec<usize> = vec![];
+        for x in 0..=width {
+            let run = if x < width { runs[x] } else { 0 };
+            while let Some(&top) = stack.last() {
+                if runs[top] < run {
+                    break;
+                }
+                stack.pop();
+                let left = stack.last().map(|l| l + 1).unwrap_or(0);
+                let area = runs[top] * (x - left);
+                if area > best_area {
+                    best_area = area;
+                    best = Some(Region {
+                        x: left,
+                        y: y + 1 - runs[top],
+                        width: x - left,
+                        height: runs[top],
+                    });
+                }
+            }
+            stack.push(x);
+        }
+    }
+
+    best
+}
@@ -1,6 +1,16 @@
-use anyhow::{Error, Result};
+use std::path::Path;
+
+use anyhow::Result;
 use sciimg::image;
+use serde::{Deserialize, Serialize};
 
+use crate::avi::AviFile;
+use crate::demosaic::DemosaicMethod;
+use crate::error::SolHatError;
+use crate::fits::FitsSource;
+use crate::imagesequence::{self, ImageSequence, IMAGE_EXTENSIONS};
+use crate::multisource::MultiSource;
+use crate::ser::SerFile;
 use crate::timestamp;
 use crate::timestamp::TimeStamp;
 
@@ -20,8 +30,8 @@ pub enum ColorFormatId {
 }
 
 impl ColorFormatId {
-    pub fn from_i32(v: i32) -> ColorFormatId {
-        match v {
+    pub fn from_i32(v: i32) -> Result<ColorFormatId> {
+        Ok(match v {
             0 => ColorFormatId::Mono,
             8 => ColorFormatId::BayerRggb,
             9 => ColorFormatId::BayerGrbg,
@@ -33,7 +43,69 @@ impl ColorFormatId {
             19 => ColorFormatId::BayerMyyc,
             100 => ColorFormatId::Rgb,
             101 => ColorFormatId::Bgr,
-            _ => panic!("Invalid color format enum value: {}", v),
+            _ => return Err(SolHatError::UnsupportedColorFormat(v).into()),
+        })
+    }
+
+    /// The number of values stored per pixel in the raw frame data
+    pub fn samples_per_pixel(&self) -> usize {
+        match self {
+            ColorFormatId::Rgb | ColorFormatId::Bgr => 3,
+            _ => 1,
+        }
+    }
+
+    /// Whether the frame data is a color filter array mosaic needing demosaicing
+    pub fn is_mosaic(&self) -> bool {
+        !matches!(
+            self,
+            ColorFormatId::Mono | ColorFormatId::Rgb | ColorFormatId::Bgr
+        )
+    }
+}
+
+/// Byte order of pixel values stored in 16-bit containers
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum ByteOrder {
+    #[default]
+    Auto, // Taken from the file header where trusted, otherwise detected from the frame data
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    pub fn from(s: &str) -> Result<ByteOrder> {
+        match s.to_uppercase().as_str() {
+            "AUTO" => Ok(ByteOrder::Auto),
+            "LITTLE" | "LE" => Ok(ByteOrder::LittleEndian),
+            "BIG" | "BE" => Ok(ByteOrder::BigEndian),
+            _ => Err(SolHatError::InvalidParameter(format!(
+                "Invalid byte order: {}. Valid options: auto, little, big",
+                s
+            ))
+            .into()),
+        }
+    }
+}
+
+/// Overrides for how raw pixel values are interpreted, for when the file header can't be trusted
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PixelFormat {
+    pub byte_order: ByteOrder,
+
+    /// Number of significant bits per sample. Defaults to the header pixel depth.
+    pub bit_depth: Option<usize>,
+
+    /// Left-shift samples of less than 16 significant bits to fill the 16-bit range
+    pub shift_to_16bit: bool,
+}
+
+impl Default for PixelFormat {
+    fn default() -> Self {
+        PixelFormat {
+            byte_order: ByteOrder::Auto,
+            bit_depth: None,
+            shift_to_16bit: true,
         }
     }
 }
@@ -68,14 +140,198 @@ pub trait DataSource {
 
     fn validate(&self) -> Result<()>;
 
+    fn set_demosaic_method(&mut self, method: DemosaicMethod);
+
+    fn set_pixel_format(&mut self, pixel_format: &PixelFormat) -> Result<()>;
+
     fn print_header_details(&self);
 
     fn file_hash(&self) -> String;
 }
 
-#[allow(dead_code)]
 pub type ImageDataSource = dyn DataSource + Send + Sync + 'static;
 
+const SER_EXTENSIONS: [&str; 1] = ["SER"];
+const AVI_EXTENSIONS: [&str; 1] = ["AVI"];
+pub const FITS_EXTENSIONS: [&str; 3] = ["FIT", "FITS", "FTS"];
+
+/// Checks that the sources can be processed together, returning an error describing the
+/// first one that differs from the first source in frame size, bit depth, or color format.
+pub fn check_compatible<'a, F, I>(sources: I) -> Result<()>
+where
+    F: DataSource + 'a,
+    I: Iterator<Item = &'a F>,
+{
+    let mut first: Option<&F> = None;
+    for source in sources {
+        let reference = match first {
+            Some(f) => f,
+            None => {
+                first = Some(source);
+                continue;
+            }
+        };
+
+        let mismatch = if (source.image_width(), source.image_height())
+            != (reference.image_width(), reference.image_height())
+        {
+            Some(format!(
+                "frame size {}x{} differs from {}x{}",
+                source.image_width(),
+                source.image_height(),
+                reference.image_width(),
+                reference.image_height()
+            ))
+        } else if source.pixel_depth() != reference.pixel_depth() {
+            Some(format!(
+                "pixel depth {} differs from {}",
+                source.pixel_depth(),
+                reference.pixel_depth()
+            ))
+        } else if source.color_id() != reference.color_id() {
+            Some(format!(
+                "color format {:?} differs from {:?}",
+                source.color_id(),
+                reference.color_id()
+            ))
+        } else {
+            None
+        };
+
+        if let Some(m) = mismatch {
+            return Err(SolHatError::IncompatibleInputs(format!(
+                "Input {} cannot be processed with {}: {}",
+                source.source_file(),
+                reference.source_file(),
+                m
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Opens the input as the data source matching its file extension. Multiple paths are
+/// treated as a sequence of FITS or image files if all of that type, otherwise each is
+/// opened individually and joined. A directory is opened as the sequence of FITS files
+/// within it, or if none, the sequence of images.
+pub fn open_data_source(paths: &[String]) -> Result<Box<ImageDataSource>> {
+    let first = paths
+        .first()
+        .ok_or_else(|| SolHatError::InvalidParameter("No input files specified".to_string()))?;
+
+    let all_have = |exts: &[&str]| paths.iter().all(|p| imagesequence::has_extension(p, exts));
+
+    if paths.len() == 1 && Path::new(first).is_dir() {
+        let fits_files = imagesequence::list_directory(first, &FITS_EXTENSIONS)?;
+        if !fits_files.is_empty() {
+            Ok(Box::new(FitsSource::open(&fits_files)?))
+        } else {
+            Ok(Box::new(ImageSequence::open(paths)?))
+        }
+    } else if all_have(&FITS_EXTENSIONS) {
+        Ok(Box::new(FitsSource::open(paths)?))
+    } else if all_have(&IMAGE_EXTENSIONS) {
+        Ok(Box::new(ImageSequence::open(paths)?))
+    } else if paths.len() > 1 {
+        Ok(Box::new(MultiSource::open(paths)?))
+    } else if all_have(&SER_EXTENSIONS) {
+        Ok(Box::new(SerFile::open(paths)?))
+    } else if all_have(&AVI_EXTENSIONS) {
+        Ok(Box::new(AviFile::open(paths)?))
+    } else {
+        Err(SolHatError::UnsupportedFileType(first.to_string()).into())
+    }
+}
+
+/// Allows the dynamically dispatched data source to be used wherever a concrete data source
+/// type is expected, with the type of each input determined when it is opened.
+impl DataSource for Box<ImageDataSource> {
+    fn color_id(&self) -> ColorFormatId {
+        self.as_ref().color_id()
+    }
+
+    fn file_id(&self) -> String {
+        self.as_ref().file_id()
+    }
+
+    fn image_width(&self) -> usize {
+        self.as_ref().image_width()
+    }
+
+    fn image_height(&self) -> usize {
+        self.as_ref().image_height()
+    }
+
+    fn pixel_depth(&self) -> usize {
+        self.as_ref().pixel_depth()
+    }
+
+    fn frame_count(&self) -> usize {
+        self.as_ref().frame_count()
+    }
+
+    fn observer(&self) -> String {
+        self.as_ref().observer()
+    }
+
+    fn instrument(&self) -> String {
+        self.as_ref().instrument()
+    }
+
+    fn telescope(&self) -> String {
+        self.as_ref().telescope()
+    }
+
+    fn date_time(&self) -> TimeStamp {
+        self.as_ref().date_time()
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        self.as_ref().date_time_utc()
+    }
+
+    fn total_file_size(&self) -> usize {
+        self.as_ref().total_file_size()
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        self.as_ref().get_frame(frame_num)
+    }
+
+    fn get_frame_timestamp(&self, frame_num: usize) -> Result<TimeStamp> {
+        self.as_ref().get_frame_timestamp(frame_num)
+    }
+
+    fn source_file(&self) -> String {
+        self.as_ref().source_file()
+    }
+
+    fn open(path: &[String]) -> Result<Self> {
+        open_data_source(path)
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.as_ref().validate()
+    }
+
+    fn set_demosaic_method(&mut self, method: DemosaicMethod) {
+        self.as_mut().set_demosaic_method(method)
+    }
+
+    fn set_pixel_format(&mut self, pixel_format: &PixelFormat) -> Result<()> {
+        self.as_mut().set_pixel_format(pixel_format)
+    }
+
+    fn print_header_details(&self) {
+        self.as_ref().print_header_details()
+    }
+
+    fn file_hash(&self) -> String {
+        self.as_ref().file_hash()
+    }
+}
+
 pub struct EmptyDataSource {}
 
 impl DataSource for EmptyDataSource {
@@ -127,8 +383,12 @@ impl DataSource for EmptyDataSource {
         0
     }
 
-    fn get_frame(&self, _frame_num: usize) -> Result<DataFrame> {
-        Err(Error::msg("No frames to retrieve"))
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        Err(SolHatError::FrameOutOfRange {
+            frame: frame_num,
+            frame_count: 0,
+        }
+        .into())
     }
 
     fn get_frame_timestamp(&self, _frame_num: usize) -> Result<TimeStamp> {
@@ -147,7 +407,13 @@ impl DataSource for EmptyDataSource {
     }
 
     fn validate(&self) -> Result<()> {
-        Err(Error::msg("Cannot validate: data source is empty"))
+        Err(SolHatError::Format("Cannot validate: data source is empty".to_string()).into())
+    }
+
+    fn set_demosaic_method(&mut self, _method: DemosaicMethod) {}
+
+    fn set_pixel_format(&mut self, _pixel_format: &PixelFormat) -> Result<()> {
+        Ok(())
     }
 
     fn print_header_details(&self) {
@@ -0,0 +1,288 @@
+use anyhow::Result;
+use sciimg::{debayer, image::Image, imagebuffer::ImageBuffer};
+use serde::{Deserialize, Serialize};
+
+use crate::datasource::ColorFormatId;
+use crate::error::SolHatError;
+
+/// Supported methods of converting a color filter array mosaic into a usable frame
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum DemosaicMethod {
+    #[default]
+    Malvar,
+    Bilinear,
+    SuperPixel, // 2x2 binning to a half resolution RGB image
+    RedChannel, // Half resolution mono image of only the red pixels
+    Raw,        // Frames are left as the mosaic and demosaiced at the end of stacking
+}
+
+impl DemosaicMethod {
+    pub fn from(s: &str) -> Result<DemosaicMethod> {
+        match s.to_uppercase().as_str() {
+            "MALVAR" => Ok(DemosaicMethod::Malvar),
+            "BILINEAR" => Ok(DemosaicMethod::Bilinear),
+            "SUPERPIXEL" => Ok(DemosaicMethod::SuperPixel),
+            "RED" | "REDCHANNEL" => Ok(DemosaicMethod::RedChannel),
+            "RAW" | "CFA" => Ok(DemosaicMethod::Raw),
+            _ => Err(SolHatError::InvalidParameter(format!(
+                "Invalid demosaic method: {}. Valid options: malvar, bilinear, superpixel, red, raw",
+                s
+            ))
+            .into()),
+        }
+    }
+
+    /// Dimensions of the frame produced by this method from a sensor of the given size
+    pub fn output_size(
+        &self,
+        color_id: ColorFormatId,
+        width: usize,
+        height: usize,
+    ) -> (usize, usize) {
+        match self {
+            DemosaicMethod::SuperPixel | DemosaicMethod::RedChannel if color_id.is_mosaic() => {
+                (width / 2, height / 2)
+            }
+            _ => (width, height),
+        }
+    }
+}
+
+/// The number of pixels of padding on the left and top needed to shift a mosaic onto
+/// an RGGB (or the CMY equivalent, CYYM) phase.
+fn pattern_phase(color_id: ColorFormatId) -> Result<(usize, usize)> {
+    match color_id {
+        ColorFormatId::BayerRggb | ColorFormatId::BayerCyym => Ok((0, 0)),
+        ColorFormatId::BayerGrbg | ColorFormatId::BayerYcmy => Ok((1, 0)),
+        ColorFormatId::BayerGbrg | ColorFormatId::BayerYmcy => Ok((0, 1)),
+        ColorFormatId::BayerBggr | ColorFormatId::BayerMyyc => Ok((1, 1)),
+        _ => Err(SolHatError::UnsupportedColorFormat(color_id as i32).into()),
+    }
+}
+
+pub fn is_cmy(color_id: ColorFormatId) -> bool {
+    matches!(
+        color_id,
+        ColorFormatId::BayerCyym
+            | ColorFormatId::BayerYcmy
+            | ColorFormatId::BayerYmcy
+            | ColorFormatId::BayerMyyc
+    )
+}
+
+/// The output band (0 = R/C, 1 = G/Y, 2 = B/M) sampled by the mosaic pixel at x/y
+pub fn cfa_band(color_id: ColorFormatId, x: usize, y: usize) -> usize {
+    let (pad_x, pad_y) = pattern_phase(color_id).unwrap_or((0, 0));
+    match ((x + pad_x) % 2, (y + pad_y) % 2) {
+        (0, 0) => 0,
+        (1, 1) => 2,
+        _ => 1,
+    }
+}
+
+/// Pads the mosaic by mirroring the second row and/or column onto the top and left edges. This
+/// moves the pattern onto an RGGB phase without changing the color of any existing pixel.
+fn pad_mosaic(buffer: &ImageBuffer, pad_x: usize, pad_y: usize) -> Result<ImageBuffer> {
+    let width = buffer.width + pad_x;
+    let height = buffer.height + pad_y;
+
+    let mirror = |v: usize, pad: usize, max: usize| {
+        if v < pad {
+            1.min(max - 1)
+        } else {
+            v - pad
+        }
+    };
+
+    let values: Vec<f32> = iproduct!(0..height, 0..width)
+        .map(|(y, x)| {
+            buffer.get(
+                mirror(x, pad_x, buffer.width),
+                mirror(y, pad_y, buffer.height),
+            )
+        })
+        .collect();
+
+    ImageBuffer::from_vec_as_mode(&values, width, height, buffer.mode)
+}
+
+/// Converts complementary cyan/yellow/magenta bands to red/green/blue in place,
+/// where C = G + B, Y = R + G, and M = R + B.
+pub fn cmy_to_rgb(image: &mut Image) {
+    iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+        let c = image.get_band(0).get(x, y);
+        let ye = image.get_band(1).get(x, y);
+        let m = image.get_band(2).get(x, y);
+
+        image.put(x, y, ((ye + m - c) / 2.0).max(0.0), 0);
+        image.put(x, y, ((c + ye - m) / 2.0).max(0.0), 1);
+        image.put(x, y, ((c + m - ye) / 2.0).max(0.0), 2);
+    });
+}
+
+fn demosaic_malvar(buffer: &ImageBuffer, color_id: ColorFormatId) -> Result<Image> {
+    let (pad_x, pad_y) = pattern_phase(color_id)?;
+
+    if pad_x > 0 || pad_y > 0 {
+        let padded = pad_mosaic(buffer, pad_x, pad_y)?;
+        let mut rgb = debayer::debayer(&padded, debayer::DebayerMethod::Malvar)?;
+        rgb.crop(pad_x, pad_y, buffer.width, buffer.height);
+        Ok(rgb)
+    } else {
+        debayer::debayer(buffer, debayer::DebayerMethod::Malvar)
+    }
+}
+
+/// Each missing color is the mean of the neighboring pixels of that color
+fn demosaic_bilinear(buffer: &ImageBuffer, color_id: ColorFormatId) -> Result<Image> {
+    let mut rgb = Image::new_with_bands(buffer.width, buffer.height, 3, buffer.mode)?;
+
+    iproduct!(0..buffer.height, 0..buffer.width).for_each(|(y, x)| {
+        let mut ttl = [0.0; 3];
+        let mut cnt = [0; 3];
+
+        for ny in y.saturating_sub(1)..(y + 2).min(buffer.height) {
+            for nx in x.saturating_sub(1)..(x + 2).min(buffer.width) {
+                let band = cfa_band(color_id, nx, ny);
+                ttl[band] += buffer.get(nx, ny);
+                cnt[band] += 1;
+            }
+        }
+
+        let own_band = cfa_band(color_id, x, y);
+        (0..3).for_each(|band| {
+            let v = if band == own_band {
+                buffer.get(x, y)
+            } else if cnt[band] > 0 {
+                ttl[band] / cnt[band] as f32
+            } else {
+                0.0
+            };
+            rgb.put(x, y, v, band);
+        });
+    });
+
+    Ok(rgb)
+}
+
+/// Bins each 2x2 block of the mosaic into a single RGB pixel
+fn demosaic_superpixel(buffer: &ImageBuffer, color_id: ColorFormatId) -> Result<Image> {
+    let width = buffer.width / 2;
+    let height = buffer.height / 2;
+    let mut rgb = Image::new_with_bands(width, height, 3, buffer.mode)?;
+
+    iproduct!(0..height, 0..width).for_each(|(y, x)| {
+        let mut ttl = [0.0; 3];
+        let mut cnt = [0; 3];
+
+        iproduct!(0..2, 0..2).for_each(|(dy, dx)| {
+            let band = cfa_band(color_id, x * 2 + dx, y * 2 + dy);
+            ttl[band] += buffer.get(x * 2 + dx, y * 2 + dy);
+            cnt[band] += 1;
+        });
+
+        (0..3).for_each(|band| rgb.put(x, y, ttl[band] / cnt[band] as f32, band));
+    });
+
+    Ok(rgb)
+}
+
+/// Extracts the red pixels of each 2x2 block into a half resolution mono image. Useful for
+/// hydrogen-alpha on color sensors where the green and blue pixels carry little signal.
+fn demosaic_red_channel(buffer: &ImageBuffer, color_id: ColorFormatId) -> Result<Image> {
+    if is_cmy(color_id) {
+        // Red channel extraction isn't possible without red pixels
+        return Err(SolHatError::UnsupportedColorFormat(color_id as i32).into());
+    }
+
+    let (pad_x, pad_y) = pattern_phase(color_id)?;
+    let red_x = pad_x % 2;
+    let red_y = pad_y % 2;
+
+    let width = buffer.width / 2;
+    let height = buffer.height / 2;
+    let values: Vec<f32> = iproduct!(0..height, 0..width)
+        .map(|(y, x)| buffer.get(x * 2 + red_x, y * 2 + red_y))
+        .collect();
+
+    Image::new_from_buffer_mono(&ImageBuffer::from_vec_as_mode(
+        &values,
+        width,
+        height,
+        buffer.mode,
+    )?)
+}
+
+/// Demosaics a raw single band color filter array buffer using the requested method. Returns
+/// a three band RGB image, except for the red channel and raw methods which return a single band.
+pub fn demosaic(
+    buffer: &ImageBuffer,
+    color_id: ColorFormatId,
+    method: DemosaicMethod,
+) -> Result<Image> {
+    // Validates the color format for all methods, including raw
+    pattern_phase(color_id)?;
+
+    let mut image = match method {
+        DemosaicMethod::Malvar => demosaic_malvar(buffer, color_id)?,
+        DemosaicMethod::Bilinear => demosaic_bilinear(buffer, color_id)?,
+        DemosaicMethod::SuperPixel => demosaic_superpixel(buffer, color_id)?,
+        DemosaicMethod::RedChannel => return demosaic_red_channel(buffer, color_id),
+        DemosaicMethod::Raw => return Image::new_from_buffer_mono(buffer),
+    };
+
+    if is_cmy(color_id) {
+        cmy_to_rgb(&mut image);
+    }
+
+    Ok(image)
+}
+
+/// Fills pixels of a stacked raw mosaic that received no samples for a band with the mean
+/// of the surrounding pixels that did. This is the final demosaic step of raw CFA stacking.
+pub fn fill_cfa_holes<H>(image: &mut Image, has_samples: H)
+where
+    H: Fn(usize, usize, usize) -> bool,
+{
+    (0..image.num_bands()).for_each(|band| {
+        let mut filled: Vec<bool> = iproduct!(0..image.height, 0..image.width)
+            .map(|(y, x)| has_samples(x, y, band))
+            .collect();
+
+        // A handful of passes will close any hole smaller than the CFA pattern repeat and
+        // the drizzle dithering. Anything left after that is beyond the stacked coverage.
+        for _ in 0..4 {
+            let mut updates: Vec<(usize, usize, f32)> = vec![];
+
+            iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+                if filled[y * image.width + x] {
+                    return;
+                }
+
+                let mut ttl = 0.0;
+                let mut cnt = 0;
+                for ny in y.saturating_sub(1)..(y + 2).min(image.height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(image.width) {
+                        if filled[ny * image.width + nx] {
+                            ttl += image.get_band(band).get(nx, ny);
+                            cnt += 1;
+                        }
+                    }
+                }
+
+                if cnt > 0 {
+                    updates.push((x, y, ttl / cnt as f32));
+                }
+            });
+
+            if updates.is_empty() {
+                break;
+            }
+
+            updates.iter().for_each(|(x, y, v)| {
+                image.put(*x, *y, *v, band);
+                filled[y * image.width + x] = true;
+            });
+        }
+    });
+}
@@ -0,0 +1,337 @@
+use anyhow::Result;
+use sciimg::imagebuffer::Offset;
+use sciimg::prelude::*;
+use serde::Serialize;
+
+use crate::error::SolHatError;
+use crate::interpolation::InterpolationMethod;
+use crate::point::Point;
+
+/// Number of rays cast outward from the initial center in search of the limb
+const NUM_RAYS: usize = 720;
+
+/// Distance, in pixels, between samples along each ray
+const RAY_STEP: f32 = 0.5;
+
+/// Samples to either side averaged into each point of a ray's profile
+const PROFILE_SMOOTHING: usize = 2;
+
+/// Number of random three point circles tried by RANSAC
+const RANSAC_ITERATIONS: usize = 256;
+
+/// Gauss-Newton iterations refining the circle against the inlying edge points
+const REFINE_ITERATIONS: usize = 10;
+
+/// Smallest number of pixels above the threshold considered a disk
+const MIN_DISK_PIXELS: usize = 64;
+
+/// A disk fitted to the limb, in pixel coordinates of the image. The errors are standard
+/// errors estimated from the scatter of the edge points about the circle.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct Disk {
+    pub x: f64,
+    pub y: f64,
+    pub radius: f64,
+    pub x_err: f64,
+    pub y_err: f64,
+    pub radius_err: f64,
+    pub edge_points: usize, // Limb points found along the rays
+    pub inliers: usize,     // Limb points agreeing with the fitted circle
+}
+
+impl Disk {
+    /// The offset needed to move the disk center to the center of an image of the given size
+    pub fn offset(&self, width: usize, height: usize) -> Offset {
+        Offset {
+            h: (width as f64 / 2.0 - self.x) as f32,
+            v: (height as f64 / 2.0 - self.y) as f32,
+        }
+    }
+}
+
+/// Separates the disk from the background by iterating the midpoint of the mean values to
+/// either side of the threshold (Ridler-Calvard isodata)
+pub fn estimate_threshold(buffer: &ImageBuffer) -> f32 {
+    let mut threshold = buffer.mean();
+    for _ in 0..100 {
+        let (mut below, mut num_below, mut above, mut num_above) = (0.0, 0, 0.0, 0);
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                let v = buffer.get(x, y) as f64;
+                if v >= threshold as f64 {
+                    above += v;
+                    num_above += 1;
+                } else {
+                    below += v;
+                    num_below += 1;
+                }
+            }
+        }
+        if num_below == 0 || num_above == 0 {
+            break;
+        }
+        let next = ((below / num_below as f64 + above / num_above as f64) / 2.0) as f32;
+        if (next - threshold).abs() < 0.5 {
+            return next;
+        }
+        threshold = next;
+    }
+    threshold
+}
+
+/// Centroid and equivalent circle radius of the pixels at or above the threshold
+fn initial_estimate(buffer: &ImageBuffer, threshold: f32) -> Option<(f32, f32, f32)> {
+    let (mut count, mut sum_x, mut sum_y) = (0, 0.0, 0.0);
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            if buffer.get(x, y) >= threshold {
+                count += 1;
+                sum_x += x as f64;
+                sum_y += y as f64;
+            }
+        }
+    }
+    if count < MIN_DISK_PIXELS {
+        None
+    } else {
+        Some((
+            (sum_x / count as f64) as f32,
+            (sum_y / count as f64) as f32,
+            (count as f64 / std::f64::consts::PI).sqrt() as f32,
+        ))
+    }
+}
+
+/// Finds the steepest falling edge along each ray from the center, where the value inside is
+/// above the threshold. Rays leaving the image before reaching the limb find nothing, so a
+/// disk partially outside of the frame is still measured by the part within it.
+fn find_edge_points(
+    buffer: &ImageBuffer,
+    threshold: f32,
+    cx: f32,
+    cy: f32,
+    radius: f32,
+    interpolation: InterpolationMethod,
+) -> Vec<(f64, f64)> {
+    let max_radius = (buffer.width.max(buffer.height) as f32).max(radius * 2.0);
+    let min_radius = radius * 0.25;
+    let s = PROFILE_SMOOTHING;
+
+    (0..NUM_RAYS)
+        .filter_map(|n| {
+            let angle = n as f32 * std::f32::consts::TAU / NUM_RAYS as f32;
+            let (dx, dy) = (angle.cos(), angle.sin());
+
+            let profile: Vec<f32> = (0..)
+                .map(|i| min_radius + i as f32 * RAY_STEP)
+                .take_while(|r| *r <= max_radius)
+                .map_while(|r| {
+                    Point {
+                        x: cx + dx * r,
+                        y: cy + dy * r,
+                        valid: true,
+                    }
+                    .get_interpolated_color(buffer, interpolation)
+                })
+                .collect();
+            if profile.len() < 4 * s + 3 || profile.iter().any(|v| v.is_nan()) {
+                return None;
+            }
+
+            let smoothed: Vec<f32> = (s..profile.len() - s)
+                .map(|i| profile[i - s..=i + s].iter().sum::<f32>() / (2 * s + 1) as f32)
+                .collect();
+            let gradient: Vec<f32> = (1..smoothed.len() - 1)
+                .map(|i| smoothed[i + 1] - smoothed[i - 1])
+                .collect();
+
+            let (i, g) = gradient
+                .iter()
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(b.1))?;
+            let inside_disk = smoothed[..=i].iter().any(|v| *v >= threshold);
+            if *g >= 0.0 || !inside_disk || i == 0 || i + 1 == gradient.len() {
+                return None;
+            }
+
+            // Parabolic refinement of the steepest point between its neighbors
+            let (g0, g1, g2) = (gradient[i - 1], gradient[i], gradient[i + 1]);
+            let denom = g0 - 2.0 * g1 + g2;
+            let frac = if denom.abs() > f32::EPSILON {
+                (0.5 * (g0 - g2) / denom).clamp(-0.5, 0.5)
+            } else {
+                0.0
+            };
+
+            // Gradient index i is centered on smoothed index i + 1, which is profile i + 1 + s
+            let r = min_radius + (i as f32 + 1.0 + s as f32 + frac) * RAY_STEP;
+            Some(((cx + dx * r) as f64, (cy + dy * r) as f64))
+        })
+        .collect()
+}
+
+/// Circle through three points, if they aren't collinear
+fn circle_from_points(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Option<(f64, f64, f64)> {
+    let d = 2.0 * (a.0 * (b.1 - c.1) + b.0 * (c.1 - a.1) + c.0 * (a.1 - b.1));
+    if d.abs() < 1.0e-9 {
+        return None;
+    }
+    let (a2, b2, c2) = (
+        a.0 * a.0 + a.1 * a.1,
+        b.0 * b.0 + b.1 * b.1,
+        c.0 * c.0 + c.1 * c.1,
+    );
+    let x = (a2 * (b.1 - c.1) + b2 * (c.1 - a.1) + c2 * (a.1 - b.1)) / d;
+    let y = (a2 * (c.0 - b.0) + b2 * (a.0 - c.0) + c2 * (b.0 - a.0)) / d;
+    Some((x, y, ((a.0 - x).powi(2) + (a.1 - y).powi(2)).sqrt()))
+}
+
+fn residual(p: &(f64, f64), circle: (f64, f64, f64)) -> f64 {
+    ((p.0 - circle.0).powi(2) + (p.1 - circle.1).powi(2)).sqrt() - circle.2
+}
+
+/// Inverse of a symmetric 3x3 matrix, if not singular
+fn invert3(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    if det.abs() < 1.0e-12 {
+        return None;
+    }
+    let mut inv = [[0.0; 3]; 3];
+    for (r, row) in inv.iter_mut().enumerate() {
+        for (c, v) in row.iter_mut().enumerate() {
+            // Cofactor of the transposed position
+            let (r0, r1) = ((c + 1) % 3, (c + 2) % 3);
+            let (c0, c1) = ((r + 1) % 3, (r + 2) % 3);
+            *v = (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) / det;
+        }
+    }
+    Some(inv)
+}
+
+/// Minimal linear congruential generator, keeping the fit deterministic
+struct Lcg(u64);
+
+impl Lcg {
+    fn next_index(&mut self, n: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % n as u64) as usize
+    }
+}
+
+/// Fits a circle to the edge points by RANSAC, then refines it by least squares against the
+/// geometric distance of the inliers
+fn fit_circle(points: &[(f64, f64)], tolerance: f64) -> Option<Disk> {
+    if points.len() < 3 {
+        return None;
+    }
+
+    let mut rng = Lcg(0x5eed);
+    let mut best: Option<(f64, f64, f64)> = None;
+    let mut best_inliers = 0;
+    for _ in 0..RANSAC_ITERATIONS {
+        let (a, b, c) = (
+            rng.next_index(points.len()),
+            rng.next_index(points.len()),
+            rng.next_index(points.len()),
+        );
+        if a == b || b == c || a == c {
+            continue;
+        }
+        if let Some(circle) = circle_from_points(points[a], points[b], points[c]) {
+            let inliers = points
+                .iter()
+                .filter(|p| residual(p, circle).abs() <= tolerance)
+                .count();
+            if inliers > best_inliers {
+                best_inliers = inliers;
+                best = Some(circle);
+            }
+        }
+    }
+
+    let mut circle = best?;
+    let inliers: Vec<usize> = (0..points.len())
+        .filter(|i| residual(&points[*i], circle).abs() <= tolerance)
+        .collect();
+    if inliers.len() < 3 {
+        return None;
+    }
+
+    // Gauss-Newton on the distance of each inlier from the circle
+    let mut jtj_inv = [[0.0; 3]; 3];
+    for _ in 0..REFINE_ITERATIONS {
+        let mut jtj = [[0.0; 3]; 3];
+        let mut jtr = [0.0; 3];
+        for p in inliers.iter().map(|i| points[*i]) {
+            let d = ((p.0 - circle.0).powi(2) + (p.1 - circle.1).powi(2))
+                .sqrt()
+                .max(1.0e-9);
+            let j = [-(p.0 - circle.0) / d, -(p.1 - circle.1) / d, -1.0];
+            let r = d - circle.2;
+            for a in 0..3 {
+                jtr[a] += j[a] * r;
+                for b in 0..3 {
+                    jtj[a][b] += j[a] * j[b];
+                }
+            }
+        }
+        jtj_inv = invert3(&jtj)?;
+        let step: Vec<f64> = (0..3)
+            .map(|a| -(0..3).map(|b| jtj_inv[a][b] * jtr[b]).sum::<f64>())
+            .collect();
+        circle = (circle.0 + step[0], circle.1 + step[1], circle.2 + step[2]);
+        if step.iter().all(|s| s.abs() < 1.0e-4) {
+            break;
+        }
+    }
+
+    let dof = (inliers.len() as f64 - 3.0).max(1.0);
+    let variance = inliers
+        .iter()
+        .map(|i| residual(&points[*i], circle).powi(2))
+        .sum::<f64>()
+        / dof;
+
+    Some(Disk {
+        x: circle.0,
+        y: circle.1,
+        radius: circle.2,
+        x_err: (variance * jtj_inv[0][0]).sqrt(),
+        y_err: (variance * jtj_inv[1][1]).sqrt(),
+        radius_err: (variance * jtj_inv[2][2]).sqrt(),
+        edge_points: points.len(),
+        inliers: inliers.len(),
+    })
+}
+
+/// Detects the target disk by its limb and fits a circle to it. Without a threshold, one
+/// separating the disk from the background is estimated from the image. The rays across the
+/// limb are sampled with the given interpolation.
+pub fn fit_disk(
+    buffer: &ImageBuffer,
+    threshold: Option<f32>,
+    interpolation: InterpolationMethod,
+) -> Result<Disk> {
+    let threshold = threshold.unwrap_or_else(|| estimate_threshold(buffer));
+    let (cx, cy, radius) = initial_estimate(buffer, threshold).ok_or_else(|| {
+        SolHatError::FitFailed(format!(
+            "No disk found above the threshold of {}",
+            threshold
+        ))
+    })?;
+
+    let points = find_edge_points(buffer, threshold, cx, cy, radius, interpolation);
+    let tolerance = (radius as f64 * 0.01).max(1.5);
+    fit_circle(&points, tolerance).ok_or_else(|| {
+        SolHatError::FitFailed(format!(
+            "Failed to fit a circle to the limb from {} edge points",
+            points.len()
+        ))
+        .into()
+    })
+}
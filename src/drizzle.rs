@@ -1,30 +1,73 @@
 use std::fmt::Display;
 
+use crate::alignmentpoints::ShiftMap;
+use crate::datasource::ColorFormatId;
+use crate::demosaic;
+use crate::error::SolHatError;
+use crate::interpolation::{lanczos3, InterpolationMethod};
 use crate::point::Point;
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use sciimg::imagebuffer::Offset;
 use sciimg::matrix::Matrix;
 use sciimg::prelude::*;
 use sciimg::vector::Vector;
 use serde::{Deserialize, Serialize};
 
-fn round_f64(v: f64) -> f64 {
-    (v * 100000.0).round() / 100000.0
-}
-
 #[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
 pub enum StackAlgorithm {
     #[default]
     Average,
     Median,
     Minimum,
+    SigmaClip,  // Mean of the samples within kappa standard deviations of the mean
+    Winsorized, // Mean with samples beyond kappa standard deviations pulled in to that limit
 }
 
 impl StackAlgorithm {
+    pub fn from(s: &str) -> Result<StackAlgorithm> {
+        match s.to_uppercase().as_str() {
+            "AVERAGE" | "MEAN" => Ok(StackAlgorithm::Average),
+            "MEDIAN" => Ok(StackAlgorithm::Median),
+            "MINIMUM" | "MIN" => Ok(StackAlgorithm::Minimum),
+            "SIGMACLIP" | "SIGMA" => Ok(StackAlgorithm::SigmaClip),
+            "WINSORIZED" | "WINSOR" => Ok(StackAlgorithm::Winsorized),
+            _ => Err(SolHatError::InvalidParameter(format!(
+                "Invalid stack algorithm: {}. Valid options: average, median, minimum, sigmaclip, winsorized",
+                s
+            ))
+            .into()),
+        }
+    }
+
     pub fn allow_parallel(self) -> bool {
         match self {
-            StackAlgorithm::Average | Self::Minimum => true,
-            StackAlgorithm::Median => false, // Current implementation will use WAY too much memory
+            StackAlgorithm::Average
+            | Self::Minimum
+            | StackAlgorithm::Median
+            | StackAlgorithm::SigmaClip
+            | StackAlgorithm::Winsorized => true,
+        }
+    }
+
+    /// Whether frames may contribute to the stack in proportion to their weight
+    pub fn supports_weighting(self) -> bool {
+        match self {
+            StackAlgorithm::Average | StackAlgorithm::SigmaClip | StackAlgorithm::Winsorized => {
+                true
+            }
+            StackAlgorithm::Median | StackAlgorithm::Minimum => false,
+        }
+    }
+
+    /// Number of times every frame is added to the stack. Clipping algorithms gather the
+    /// per-pixel statistics in the first pass, refine them from the samples kept in the second,
+    /// and reject against the refined statistics in the third. The median finds the range of
+    /// each pixel, then refines its histograms.
+    pub fn num_passes(self) -> usize {
+        match self {
+            StackAlgorithm::SigmaClip | StackAlgorithm::Winsorized => 3,
+            StackAlgorithm::Median => 1 + MEDIAN_REFINE_PASSES,
+            _ => 1,
         }
     }
 }
@@ -34,62 +77,199 @@ pub enum StackAlgorithmImpl {
     Average(AverageStackBuffer),
     Median(MedianStackBuffer),
     Minimum(MinimumStackBuffer),
+    SigmaClip(SigmaClipStackBuffer),
 }
 
-/// Supported drizzle scalings
-#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
-pub enum Scale {
-    #[default]
-    Scale1_0, // No upscaling
-    Scale1_5,
-    Scale2_0,
-    Scale3_0,
+impl StackAlgorithmImpl {
+    /// Creates the stack buffer for the algorithm. Kappa applies only to the clipping algorithms.
+    pub fn new(
+        algorithm: StackAlgorithm,
+        kappa: f32,
+        width: usize,
+        height: usize,
+        num_bands: usize,
+    ) -> Result<StackAlgorithmImpl> {
+        Ok(match algorithm {
+            StackAlgorithm::Average => {
+                StackAlgorithmImpl::Average(AverageStackBuffer::new(width, height, num_bands)?)
+            }
+            StackAlgorithm::Median => {
+                StackAlgorithmImpl::Median(MedianStackBuffer::new(width, height, num_bands)?)
+            }
+            StackAlgorithm::Minimum => {
+                StackAlgorithmImpl::Minimum(MinimumStackBuffer::new(width, height, num_bands)?)
+            }
+            StackAlgorithm::SigmaClip => StackAlgorithmImpl::SigmaClip(
+                SigmaClipStackBuffer::new(width, height, num_bands)?.with_clipping(kappa, false),
+            ),
+            StackAlgorithm::Winsorized => StackAlgorithmImpl::SigmaClip(
+                SigmaClipStackBuffer::new(width, height, num_bands)?.with_clipping(kappa, true),
+            ),
+        })
+    }
 }
 
-impl Scale {
-    pub fn value(&self) -> f32 {
-        match *self {
-            Scale::Scale1_0 => 1.0,
-            Scale::Scale1_5 => 1.5,
-            Scale::Scale2_0 => 2.0,
-            Scale::Scale3_0 => 3.0,
-        }
+/// Drizzle upscale factor of the output relative to the input frames
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Scale(f32);
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale(1.0) // No upscaling
     }
 }
 
 impl Scale {
+    pub fn new(factor: f32) -> Result<Scale> {
+        if factor.is_finite() && factor > 0.0 {
+            Ok(Scale(factor))
+        } else {
+            Err(SolHatError::InvalidParameter(format!(
+                "Invalid drizzle scale: {}. Must be greater than zero",
+                factor
+            ))
+            .into())
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.0
+    }
+
     pub fn from(s: &str) -> Result<Scale> {
-        match s {
-            "1.0" => Ok(Scale::Scale1_0),
-            "1.5" => Ok(Scale::Scale1_5),
-            "2.0" => Ok(Scale::Scale2_0),
-            "3.0" => Ok(Scale::Scale3_0),
-            _ => Err(anyhow!(
-                "Invalid drizze scale: {}. Valid options: 1.0, 1.5, 2.0, 3.0",
+        match s.parse::<f32>() {
+            Ok(factor) => Scale::new(factor),
+            Err(_) => Err(SolHatError::InvalidParameter(format!(
+                "Invalid drizzle scale: {}. Expected a number such as 1.0, 1.5, or 2.0",
                 s
-            )),
+            ))
+            .into()),
         }
     }
 }
 
 impl Display for Scale {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Drizzle x{}", self.0)
+    }
+}
+
+/// Shape of the drop each input pixel is spread over the output with. The drop is the input
+/// pixel shrunk by the pixfrac, so its size in output pixels is pixfrac times the scale.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum DrizzleKernel {
+    #[default]
+    Square, // Weighted by the area of overlap with each output pixel
+    Point,       // Entirely into the output pixel under the center of the drop
+    Gaussian,    // Gaussian of full width at half maximum equal to the drop size
+    Lanczos,     // Lanczos-3, scaled to the drop size
+    Interpolate, // No drops, each output pixel is resampled from the frame by the interpolation method
+}
+
+impl DrizzleKernel {
+    pub fn from(s: &str) -> Result<DrizzleKernel> {
+        match s.to_uppercase().as_str() {
+            "SQUARE" => Ok(DrizzleKernel::Square),
+            "POINT" => Ok(DrizzleKernel::Point),
+            "GAUSSIAN" => Ok(DrizzleKernel::Gaussian),
+            "LANCZOS" | "LANCZOS3" => Ok(DrizzleKernel::Lanczos),
+            "INTERPOLATE" => Ok(DrizzleKernel::Interpolate),
+            _ => Err(SolHatError::InvalidParameter(format!(
+                "Invalid drizzle kernel: {}. Valid options: square, point, gaussian, lanczos, interpolate",
+                s
+            ))
+            .into()),
+        }
+    }
+
+    /// Reach of the kernel from the center of the drop, in output pixels
+    fn support(self, drop_size: f32) -> f32 {
+        match self {
+            DrizzleKernel::Square => drop_size / 2.0,
+            DrizzleKernel::Point | DrizzleKernel::Interpolate => 0.0,
+            DrizzleKernel::Gaussian => 3.0 * drop_size / GAUSSIAN_FWHM_TO_SIGMA,
+            DrizzleKernel::Lanczos => 3.0 * drop_size,
+        }
+    }
+
+    /// Weight of the output pixel spanning `[x0, x0 + 1) x [y0, y0 + 1)` for a drop centered at
+    /// `(cx, cy)`. Kernels are scaled to weigh a total of the area of the drop.
+    fn weight(self, drop_size: f32, cx: f32, cy: f32, x0: f32, y0: f32) -> f32 {
         match self {
-            Scale::Scale1_0 => write!(f, "Drizzle x1"),
-            Scale::Scale1_5 => write!(f, "Drizzle x1.5"),
-            Scale::Scale2_0 => write!(f, "Drizzle x2.0"),
-            Scale::Scale3_0 => write!(f, "Drizzle x3.0"),
+            DrizzleKernel::Square => {
+                let half = drop_size / 2.0;
+                let overlap =
+                    |c: f32, p: f32| ((c + half).min(p + 1.0) - (c - half).max(p)).max(0.0);
+                overlap(cx, x0) * overlap(cy, y0)
+            }
+            DrizzleKernel::Point | DrizzleKernel::Interpolate => {
+                if cx.floor() == x0 && cy.floor() == y0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            DrizzleKernel::Gaussian => {
+                let sigma = drop_size / GAUSSIAN_FWHM_TO_SIGMA;
+                let (dx, dy) = (x0 + 0.5 - cx, y0 + 0.5 - cy);
+                drop_size * drop_size * (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+                    / (2.0 * std::f32::consts::PI * sigma * sigma)
+            }
+            DrizzleKernel::Lanczos => {
+                lanczos3((x0 + 0.5 - cx) / drop_size) * lanczos3((y0 + 0.5 - cy) / drop_size)
+            }
+        }
+    }
+
+    /// Factor scaling the weights of a drop to total the area of the drop over the output
+    /// pixels it reaches. Only the Lanczos kernel, whose negative lobes sampled on the pixel
+    /// grid don't sum to its area, needs it. Zero if the drop doesn't sum to a positive weight.
+    fn normalization(self, drop_size: f32, cx: f32, cy: f32) -> f32 {
+        if self != DrizzleKernel::Lanczos {
+            return 1.0;
+        }
+        let support = self.support(drop_size);
+        let sum_axis = |c: f32| -> f32 {
+            ((c - support).floor() as i32..=(c + support).floor() as i32)
+                .map(|p| lanczos3((p as f32 + 0.5 - c) / drop_size))
+                .sum()
+        };
+        let sum = sum_axis(cx) * sum_axis(cy);
+        if sum > 0.0 {
+            drop_size * drop_size / sum
+        } else {
+            0.0
         }
     }
 }
 
+const GAUSSIAN_FWHM_TO_SIGMA: f32 = 2.354_82;
+
 pub trait StackBuffer {
-    fn new(width: usize, height: usize, num_bands: usize) -> Self;
+    fn new(width: usize, height: usize, num_bands: usize) -> Result<Self>
+    where
+        Self: Sized;
     fn put(&mut self, x: usize, y: usize, values: &[f32; 3]);
     fn get(&self, x: usize, y: usize, band: usize) -> f32;
     fn get_finalized(&self) -> Result<Image>;
     fn num_bands(&self) -> usize;
     fn add_other(&mut self, other: &Self);
+    fn has_samples(&self, x: usize, y: usize, band: usize) -> bool;
+
+    /// Adds samples counting for the given fraction of a full frame. Buffers which don't
+    /// support weighting add them as with `put`.
+    fn put_weighted(&mut self, x: usize, y: usize, values: &[f32; 3], _weight: f32) {
+        self.put(x, y, values);
+    }
+
+    /// An empty buffer of the same size, for accumulating a share of the frames of the current
+    /// pass to be merged back with `add_other`
+    fn empty_like(&self) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Called once all frames of a pass have been added and merged, before the next pass
+    fn next_pass(&mut self) {}
 }
 
 #[derive(Debug, Clone)]
@@ -99,16 +279,20 @@ pub struct MinimumStackBuffer {
 }
 
 impl StackBuffer for MinimumStackBuffer {
-    fn new(width: usize, height: usize, num_bands: usize) -> Self {
-        MinimumStackBuffer {
+    fn new(width: usize, height: usize, num_bands: usize) -> Result<Self> {
+        Ok(MinimumStackBuffer {
             num_bands,
-            buffer: Image::new_with_bands(width, height, num_bands, ImageMode::U16BIT)
-                .expect("Failed to allocate rgbimage"),
-        }
+            buffer: Image::new_with_bands(width, height, num_bands, ImageMode::U16BIT)?,
+        })
     }
 
     fn put(&mut self, x: usize, y: usize, values: &[f32; 3]) {
         (0..self.num_bands).for_each(|i| {
+            // NaN indicates no sample for the band
+            if values[i].is_nan() {
+                return;
+            }
+
             let v = self.get(x, y, i);
 
             let use_v = if v > 0.0 { values[i].min(v) } else { values[i] };
@@ -147,32 +331,47 @@ impl StackBuffer for MinimumStackBuffer {
             }
         }
     }
+
+    fn has_samples(&self, x: usize, y: usize, band: usize) -> bool {
+        self.get(x, y, band) > 0.0
+    }
+
+    fn empty_like(&self) -> Result<Self> {
+        Self::new(self.buffer.width, self.buffer.height, self.num_bands)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct AverageStackBuffer {
     num_bands: usize,
     buffer: Image,
-    divisor: ImageBuffer,
+    divisor: Image, // Per-band, as raw CFA stacking samples each band at different pixels
 }
 
 impl StackBuffer for AverageStackBuffer {
-    fn new(width: usize, height: usize, num_bands: usize) -> Self {
-        AverageStackBuffer {
+    fn new(width: usize, height: usize, num_bands: usize) -> Result<Self> {
+        Ok(AverageStackBuffer {
             num_bands,
-            buffer: Image::new_with_bands(width, height, num_bands, ImageMode::U16BIT)
-                .expect("Failed to allocate rgbimage"),
-            divisor: ImageBuffer::new(width, height)
-                .expect("Failed to create drizzle divisor buffer"),
-        }
+            buffer: Image::new_with_bands(width, height, num_bands, ImageMode::U16BIT)?,
+            divisor: Image::new_with_bands(width, height, num_bands, ImageMode::U16BIT)?,
+        })
     }
 
     fn put(&mut self, x: usize, y: usize, values: &[f32; 3]) {
+        self.put_weighted(x, y, values, 1.0);
+    }
+
+    fn put_weighted(&mut self, x: usize, y: usize, values: &[f32; 3], weight: f32) {
         (0..self.num_bands).for_each(|i| {
+            // NaN indicates no sample for the band
+            if values[i].is_nan() {
+                return;
+            }
             let v = self.get(x, y, i);
-            self.buffer.put(x, y, v + values[i], i);
+            self.buffer.put(x, y, v + values[i] * weight, i);
+            self.divisor
+                .put(x, y, self.divisor.get_band(i).get(x, y) + weight, i);
         });
-        self.divisor.put(x, y, 1.0);
     }
 
     fn get(&self, x: usize, y: usize, band: usize) -> f32 {
@@ -185,7 +384,17 @@ impl StackBuffer for AverageStackBuffer {
 
     fn get_finalized(&self) -> Result<Image> {
         let mut final_buffer = self.buffer.clone();
-        final_buffer.divide_from_each(&self.divisor);
+        iproduct!(
+            0..self.num_bands,
+            0..final_buffer.height,
+            0..final_buffer.width
+        )
+        .for_each(|(b, y, x)| {
+            // Pixels reached only by negative Lanczos lobes have no positive weight to divide by
+            let d = self.divisor.get_band(b).get(x, y);
+            let v = if d > 0.0 { self.get(x, y, b) / d } else { 0.0 };
+            final_buffer.put(x, y, v, b);
+        });
         Ok(final_buffer)
     }
 
@@ -195,104 +404,419 @@ impl StackBuffer for AverageStackBuffer {
 
     fn add_other(&mut self, other: &Self) {
         self.buffer.add(&other.buffer);
-        self.divisor.add_mut(&other.divisor);
+        self.divisor.add(&other.divisor);
+    }
+
+    fn has_samples(&self, x: usize, y: usize, band: usize) -> bool {
+        band < self.num_bands && self.divisor.get_band(band).get(x, y) > 0.0
+    }
+
+    fn empty_like(&self) -> Result<Self> {
+        Self::new(self.buffer.width, self.buffer.height, self.num_bands)
     }
 }
 
+/// Number of bins of the per-pixel histograms of the median
+const MEDIAN_HISTOGRAM_BINS: usize = 16;
+
+/// Number of passes which narrow the histograms down on the median, following the one which
+/// finds the range of each pixel. Each narrows the bin width by a factor of the bin count.
+pub const MEDIAN_REFINE_PASSES: usize = 3;
+
+/// Per-pixel histograms of one band, spanning a window of values which is narrowed down to the
+/// bin holding the median after each pass
 #[derive(Debug, Clone)]
-struct VectorMatrix {
-    width: usize,
-    height: usize,
-    matrix: Vec<Vec<f32>>,
+struct MedianHistogram {
+    lo: Vec<f32>,
+    hi: Vec<f32>,
+    count: Vec<u32>, // Total samples, from the range pass
+    below: Vec<u32>, // Samples below the window in the current pass
+    bins: Vec<u32>,
 }
 
-impl VectorMatrix {
-    pub fn new(width: usize, height: usize) -> Self {
-        let l = width * height;
-        let matrix = (0..l)
-            .map(|_| Vec::with_capacity(200))
-            .collect::<Vec<Vec<f32>>>();
-        VectorMatrix {
-            width,
-            height,
-            matrix,
+impl MedianHistogram {
+    fn new(len: usize) -> Self {
+        MedianHistogram {
+            lo: vec![f32::MAX; len],
+            hi: vec![f32::MIN; len],
+            count: vec![0; len],
+            below: vec![],
+            bins: vec![],
         }
     }
 
-    pub fn put(&mut self, x: usize, y: usize, value: f32) {
-        let idx = y * self.width + x;
-        if idx < self.matrix.len() {
-            self.matrix[idx].push(value);
-        }
+    fn clear_bins(&mut self) {
+        self.below = vec![0; self.count.len()];
+        self.bins = vec![0; self.count.len() * MEDIAN_HISTOGRAM_BINS];
     }
 
-    pub fn get_median(&self, x: usize, y: usize) -> f32 {
-        let idx = y * self.width + x;
-        let mut s = self.matrix[idx].clone();
-        s.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        s[s.len() / 2]
+    fn put_range(&mut self, idx: usize, value: f32) {
+        self.lo[idx] = self.lo[idx].min(value);
+        self.hi[idx] = self.hi[idx].max(value);
+        self.count[idx] += 1;
     }
 
-    pub fn extend(&mut self, other: &VectorMatrix) {
-        for i in 0..self.matrix.len() {
-            self.matrix[i].extend(&other.matrix[i]);
+    // Windows are closed on both ends, with the last bin including the upper bound
+    fn put_bin(&mut self, idx: usize, value: f32) {
+        let (lo, hi) = (self.lo[idx], self.hi[idx]);
+        if value < lo {
+            self.below[idx] += 1;
+        } else if value <= hi {
+            let bin = if hi > lo {
+                (((value - lo) / (hi - lo) * MEDIAN_HISTOGRAM_BINS as f32) as usize)
+                    .min(MEDIAN_HISTOGRAM_BINS - 1)
+            } else {
+                0
+            };
+            self.bins[idx * MEDIAN_HISTOGRAM_BINS + bin] += 1;
         }
     }
 
-    pub fn to_median_image(&self) -> ImageBuffer {
-        let mut buffer =
-            ImageBuffer::new_as_mode(self.width, self.height, ImageMode::U16BIT).unwrap();
+    fn merge(&mut self, other: &MedianHistogram, range_pass: bool) {
+        if range_pass {
+            for idx in 0..self.count.len() {
+                self.lo[idx] = self.lo[idx].min(other.lo[idx]);
+                self.hi[idx] = self.hi[idx].max(other.hi[idx]);
+                self.count[idx] += other.count[idx];
+            }
+        } else {
+            self.below
+                .iter_mut()
+                .zip(other.below.iter())
+                .for_each(|(a, b)| *a += b);
+            self.bins
+                .iter_mut()
+                .zip(other.bins.iter())
+                .for_each(|(a, b)| *a += b);
+        }
+    }
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                buffer.put(x, y, self.get_median(x, y));
+    /// The bin holding the median sample, the number of samples below that bin, and the bin width
+    fn median_bin(&self, idx: usize) -> Option<(usize, u32, f32)> {
+        let rank = self.count[idx] / 2;
+        let mut cumulative = self.below[idx];
+        let width = (self.hi[idx] - self.lo[idx]) / MEDIAN_HISTOGRAM_BINS as f32;
+        for bin in 0..MEDIAN_HISTOGRAM_BINS {
+            let n = self.bins[idx * MEDIAN_HISTOGRAM_BINS + bin];
+            if rank < cumulative + n {
+                return Some((bin, cumulative, width));
             }
+            cumulative += n;
+        }
+        None
+    }
+
+    fn narrow(&mut self, idx: usize) {
+        if let Some((bin, _, width)) = self.median_bin(idx) {
+            let lo = self.lo[idx] + bin as f32 * width;
+            self.lo[idx] = lo;
+            self.hi[idx] = lo + width;
+        }
+    }
+
+    // Interpolates within the median bin, taking its samples to be evenly spread. Rounding when
+    // narrowing may leave a sample at the bin edge outside the window, so more samples can be
+    // counted below the window than the rank of the median.
+    fn estimate(&self, idx: usize) -> f32 {
+        if self.count[idx] == 0 {
+            0.0
+        } else if self.bins.is_empty() || self.hi[idx] <= self.lo[idx] {
+            self.lo[idx]
+        } else if let Some((bin, cumulative, width)) = self.median_bin(idx) {
+            let n = self.bins[idx * MEDIAN_HISTOGRAM_BINS + bin] as f32;
+            let rank = self.count[idx] / 2;
+            let fraction = ((rank.saturating_sub(cumulative) as f32 + 0.5) / n).clamp(0.0, 1.0);
+            self.lo[idx] + (bin as f32 + fraction) * width
+        } else {
+            (self.lo[idx] + self.hi[idx]) / 2.0
         }
-        buffer
     }
 }
 
+/// Approximate median, bounded in memory regardless of the number of frames. The first pass
+/// finds the range of each pixel, and each following pass histograms the samples within the
+/// window holding the median, narrowing it for the next. Counts merge across buffers, so
+/// frames may be stacked in parallel.
 #[derive(Debug, Clone)]
 pub struct MedianStackBuffer {
     num_bands: usize,
     width: usize,
     height: usize,
-    matrix: Vec<VectorMatrix>,
+    pass: usize,
+    histograms: Vec<MedianHistogram>,
 }
 
 impl StackBuffer for MedianStackBuffer {
-    fn new(width: usize, height: usize, num_bands: usize) -> Self {
-        let m = (0..num_bands)
-            .map(|_| VectorMatrix::new(width, height))
-            .collect();
-
-        MedianStackBuffer {
+    fn new(width: usize, height: usize, num_bands: usize) -> Result<Self> {
+        Ok(MedianStackBuffer {
             num_bands,
             width,
             height,
-            matrix: m,
-        }
+            pass: 0,
+            histograms: (0..num_bands)
+                .map(|_| MedianHistogram::new(width * height))
+                .collect(),
+        })
     }
 
     fn put(&mut self, x: usize, y: usize, values: &[f32; 3]) {
+        let idx = y * self.width + x;
         for (b, value) in values.iter().enumerate().take(self.num_bands) {
-            self.matrix[b].put(x, y, *value);
+            // NaN indicates no sample for the band
+            if value.is_nan() {
+                continue;
+            }
+            if self.pass == 0 {
+                self.histograms[b].put_range(idx, *value);
+            } else {
+                self.histograms[b].put_bin(idx, *value);
+            }
         }
     }
 
     fn get(&self, x: usize, y: usize, band: usize) -> f32 {
-        self.matrix[band].get_median(x, y)
+        self.histograms[band].estimate(y * self.width + x)
     }
 
     fn get_finalized(&self) -> Result<Image> {
         let mut image =
-            Image::new_with_bands(self.width, self.height, self.num_bands, ImageMode::U16BIT)
-                .unwrap();
+            Image::new_with_bands(self.width, self.height, self.num_bands, ImageMode::U16BIT)?;
+        iproduct!(0..self.num_bands, 0..self.height, 0..self.width).for_each(|(b, y, x)| {
+            image.put(x, y, self.get(x, y, b), b);
+        });
+        Ok(image)
+    }
+
+    fn num_bands(&self) -> usize {
+        self.num_bands
+    }
 
+    fn add_other(&mut self, other: &Self) {
         for b in 0..self.num_bands {
-            image.set_band(&self.matrix[b].to_median_image(), b);
+            self.histograms[b].merge(&other.histograms[b], self.pass == 0);
+        }
+    }
+
+    fn has_samples(&self, x: usize, y: usize, band: usize) -> bool {
+        band < self.num_bands && self.histograms[band].count[y * self.width + x] > 0
+    }
+
+    fn empty_like(&self) -> Result<Self> {
+        if self.pass == 0 {
+            Self::new(self.width, self.height, self.num_bands)
+        } else {
+            // Later passes count samples within the windows found so far
+            let mut other = self.clone();
+            other.histograms.iter_mut().for_each(|h| h.clear_bins());
+            Ok(other)
+        }
+    }
+
+    fn next_pass(&mut self) {
+        for h in self.histograms.iter_mut() {
+            if self.pass > 0 {
+                (0..h.count.len()).for_each(|idx| h.narrow(idx));
+            }
+            h.clear_bins();
+        }
+        self.pass += 1;
+    }
+}
+
+/// Per-pixel running statistics of one band, which can be merged across buffers
+#[derive(Debug, Clone)]
+struct RunningStats {
+    count: Vec<f32>,
+    mean: Vec<f32>,
+    m2: Vec<f32>, // Sum of squared differences from the mean
+}
+
+impl RunningStats {
+    fn new(len: usize) -> Self {
+        RunningStats {
+            count: vec![0.0; len],
+            mean: vec![0.0; len],
+            m2: vec![0.0; len],
         }
+    }
 
+    // Welford's online update
+    fn put(&mut self, idx: usize, value: f32) {
+        self.count[idx] += 1.0;
+        let delta = value - self.mean[idx];
+        self.mean[idx] += delta / self.count[idx];
+        self.m2[idx] += delta * (value - self.mean[idx]);
+    }
+
+    // Chan et al. pairwise combination
+    fn merge(&mut self, other: &RunningStats) {
+        for idx in 0..self.count.len() {
+            let (na, nb) = (self.count[idx], other.count[idx]);
+            if nb == 0.0 {
+                continue;
+            }
+            let n = na + nb;
+            let delta = other.mean[idx] - self.mean[idx];
+            self.mean[idx] += delta * nb / n;
+            self.m2[idx] += other.m2[idx] + delta * delta * na * nb / n;
+            self.count[idx] = n;
+        }
+    }
+
+    fn std_dev(&self, idx: usize) -> f32 {
+        if self.count[idx] > 1.0 {
+            (self.m2[idx] / self.count[idx]).sqrt()
+        } else {
+            0.0
+        }
+    }
+
+    /// Count, mean and standard deviation of the pixel's samples other than the given one,
+    /// which must be among them
+    fn without(&self, idx: usize, value: f32) -> (f32, f32, f32) {
+        let n = self.count[idx] - 1.0;
+        if n < 1.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let mean = self.mean[idx];
+        let others_mean = (self.count[idx] * mean - value) / n;
+        let m2 = (self.m2[idx] - (value - mean) * (value - others_mean)).max(0.0);
+        (n, others_mean, (m2 / n).sqrt())
+    }
+}
+
+/// Fewest other samples of a pixel to judge a sample against. Pixels with fewer aren't clipped.
+const MIN_CLIP_SAMPLES: f32 = 3.0;
+
+/// Three pass kappa-sigma clipped mean. Each sample is judged against the mean and standard
+/// deviation of the other samples of its pixel, as an outlier inflates the deviation of any
+/// sample including it enough to hide within kappa of it on small stacks. The first pass
+/// gathers the per-pixel statistics of all samples and the second those of the samples kept
+/// against them. In the third, samples further than kappa standard deviations from the
+/// refined mean are rejected, or when winsorizing, replaced by the value at that limit. Every
+/// pass merges across buffers, so frames may be stacked in parallel.
+#[derive(Debug, Clone)]
+pub struct SigmaClipStackBuffer {
+    num_bands: usize,
+    width: usize,
+    height: usize,
+    kappa: f32,
+    winsorize: bool,
+    pass: usize,
+    stats: Vec<RunningStats>,   // Of all samples
+    refined: Vec<RunningStats>, // Of the samples kept against `stats`
+    sum: Vec<Vec<f32>>,
+    divisor: Vec<Vec<f32>>,
+}
+
+impl SigmaClipStackBuffer {
+    pub fn with_clipping(mut self, kappa: f32, winsorize: bool) -> Self {
+        self.kappa = kappa;
+        self.winsorize = winsorize;
+        self
+    }
+
+    /// Clips the value against the statistics, leaving out `member`, the value the sample
+    /// contributed to them, if any
+    fn clip_against(
+        &self,
+        stats: &RunningStats,
+        idx: usize,
+        value: f32,
+        member: Option<f32>,
+    ) -> Option<f32> {
+        let (count, mean, std_dev) = match member {
+            Some(m) => stats.without(idx, m),
+            None => (stats.count[idx], stats.mean[idx], stats.std_dev(idx)),
+        };
+        if count < MIN_CLIP_SAMPLES {
+            return Some(value);
+        }
+        let limit = self.kappa * std_dev;
+        if (value - mean).abs() <= limit {
+            Some(value)
+        } else if self.winsorize {
+            Some(value.clamp(mean - limit, mean + limit))
+        } else {
+            None
+        }
+    }
+
+    /// The sample as kept against the statistics of all samples
+    fn clip_first(&self, idx: usize, band: usize, value: f32) -> Option<f32> {
+        self.clip_against(&self.stats[band], idx, value, Some(value))
+    }
+
+    /// The sample as kept against the refined statistics
+    fn clip(&self, idx: usize, band: usize, value: f32) -> Option<f32> {
+        let member = self.clip_first(idx, band, value);
+        self.clip_against(&self.refined[band], idx, value, member)
+    }
+}
+
+impl StackBuffer for SigmaClipStackBuffer {
+    fn new(width: usize, height: usize, num_bands: usize) -> Result<Self> {
+        Ok(SigmaClipStackBuffer {
+            num_bands,
+            width,
+            height,
+            kappa: 2.5,
+            winsorize: false,
+            pass: 0,
+            stats: (0..num_bands)
+                .map(|_| RunningStats::new(width * height))
+                .collect(),
+            refined: vec![],
+            sum: vec![],
+            divisor: vec![],
+        })
+    }
+
+    fn put(&mut self, x: usize, y: usize, values: &[f32; 3]) {
+        self.put_weighted(x, y, values, 1.0);
+    }
+
+    // Weights apply to the clipped mean, while the rejection statistics count every sample equally
+    fn put_weighted(&mut self, x: usize, y: usize, values: &[f32; 3], weight: f32) {
+        let idx = y * self.width + x;
+        for (band, value) in values.iter().enumerate().take(self.num_bands) {
+            // NaN indicates no sample for the band
+            if value.is_nan() {
+                continue;
+            }
+            if self.pass == 0 {
+                self.stats[band].put(idx, *value);
+            } else if self.pass == 1 {
+                if let Some(v) = self.clip_first(idx, band, *value) {
+                    self.refined[band].put(idx, v);
+                }
+            } else if let Some(v) = self.clip(idx, band, *value) {
+                self.sum[band][idx] += v * weight;
+                self.divisor[band][idx] += weight;
+            }
+        }
+    }
+
+    fn get(&self, x: usize, y: usize, band: usize) -> f32 {
+        if band >= self.num_bands || self.sum.is_empty() {
+            0.0
+        } else {
+            self.sum[band][y * self.width + x]
+        }
+    }
+
+    fn get_finalized(&self) -> Result<Image> {
+        let mut image =
+            Image::new_with_bands(self.width, self.height, self.num_bands, ImageMode::U16BIT)?;
+        iproduct!(0..self.num_bands, 0..self.height, 0..self.width).for_each(|(b, y, x)| {
+            let idx = y * self.width + x;
+            // Pixels where every sample was rejected fall back to the unclipped mean
+            let v = match self.divisor.get(b).map(|d| d[idx]) {
+                Some(d) if d > 0.0 => self.sum[b][idx] / d,
+                _ => self.stats[b].mean[idx],
+            };
+            image.put(x, y, v, b);
+        });
         Ok(image)
     }
 
@@ -302,13 +826,62 @@ impl StackBuffer for MedianStackBuffer {
 
     fn add_other(&mut self, other: &Self) {
         for b in 0..self.num_bands {
-            self.matrix[b].extend(&other.matrix[b]);
+            if self.pass == 0 {
+                self.stats[b].merge(&other.stats[b]);
+            } else if self.pass == 1 {
+                self.refined[b].merge(&other.refined[b]);
+            } else {
+                self.sum[b]
+                    .iter_mut()
+                    .zip(other.sum[b].iter())
+                    .for_each(|(s, o)| *s += o);
+                self.divisor[b]
+                    .iter_mut()
+                    .zip(other.divisor[b].iter())
+                    .for_each(|(d, o)| *d += o);
+            }
         }
     }
+
+    fn has_samples(&self, x: usize, y: usize, band: usize) -> bool {
+        band < self.num_bands && self.stats[band].count[y * self.width + x] > 0.0
+    }
+
+    fn empty_like(&self) -> Result<Self> {
+        if self.pass == 0 {
+            Ok(Self::new(self.width, self.height, self.num_bands)?
+                .with_clipping(self.kappa, self.winsorize))
+        } else {
+            // Later passes clip against the statistics of the earlier ones
+            let mut other = self.clone();
+            other.next_pass_accumulators();
+            Ok(other)
+        }
+    }
+
+    fn next_pass(&mut self) {
+        self.pass += 1;
+        self.next_pass_accumulators();
+    }
+}
+
+impl SigmaClipStackBuffer {
+    fn next_pass_accumulators(&mut self) {
+        let len = self.width * self.height;
+        if self.pass == 1 {
+            self.refined = (0..self.num_bands)
+                .map(|_| RunningStats::new(len))
+                .collect();
+        }
+        self.sum = (0..self.num_bands).map(|_| vec![0.0; len]).collect();
+        self.divisor = (0..self.num_bands).map(|_| vec![0.0; len]).collect();
+    }
 }
 
+/// Stacks frames onto an upscaled buffer, either spreading each input pixel over the output as
+/// a drop of the drizzle kernel, or by default, interpolating each output pixel from the frame
 #[derive(Debug, Clone)]
-pub struct BilinearDrizzle {
+pub struct Drizzle {
     in_width: usize,
     in_height: usize,
     out_width: usize,
@@ -317,9 +890,16 @@ pub struct BilinearDrizzle {
     frame_add_count: usize,
     horiz_offset: i32,
     vert_offset: i32,
+    cfa_pattern: Option<ColorFormatId>,
+    kernel: DrizzleKernel,
+    pixfrac: f32,
+    interpolation: InterpolationMethod,
+    weight_map: Vec<f32>,   // Total weight of the samples of each output pixel
+    coverage_map: Vec<u32>, // Number of frames sampled into each output pixel
+    coverage_tag: Vec<u32>, // Last frame, counting from one, to have covered each output pixel
 }
 
-impl BilinearDrizzle {
+impl Drizzle {
     pub fn new(
         in_width: usize,
         in_height: usize,
@@ -327,10 +907,10 @@ impl BilinearDrizzle {
         horiz_offset: i32,
         vert_offset: i32,
         stack_buffer: StackAlgorithmImpl,
-    ) -> BilinearDrizzle {
+    ) -> Drizzle {
         let out_width = (in_width as f32 * scale.value()).ceil() as usize;
         let out_height = (in_height as f32 * scale.value()).ceil() as usize;
-        BilinearDrizzle {
+        Drizzle {
             in_width,
             in_height,
             out_width,
@@ -339,19 +919,50 @@ impl BilinearDrizzle {
             horiz_offset,
             vert_offset,
             buffer: stack_buffer,
+            cfa_pattern: None,
+            kernel: DrizzleKernel::Interpolate,
+            pixfrac: 1.0,
+            interpolation: InterpolationMethod::default(),
+            weight_map: vec![0.0; out_width * out_height],
+            coverage_map: vec![0; out_width * out_height],
+            coverage_tag: vec![0; out_width * out_height],
         }
     }
 
-    /// Convert an x/y point on the drizzle buffer to the respective point on the input buffer
+    /// Sets the shape and size, as a fraction of an input pixel, of the drops frames are
+    /// spread over the output with. The interpolate kernel resamples the frame instead.
+    pub fn set_kernel(&mut self, kernel: DrizzleKernel, pixfrac: f32) {
+        self.kernel = kernel;
+        self.pixfrac = pixfrac;
+    }
+
+    /// Sets how frames are resampled by the interpolate kernel. The other kernels drop input
+    /// pixels without resampling, so ignore it.
+    pub fn set_interpolation(&mut self, interpolation: InterpolationMethod) {
+        self.interpolation = interpolation;
+    }
+
+    /// Puts the drizzle into raw color filter array mode. Input frames are expected to be the
+    /// undebayered mosaic, with each pixel sampled into only the band of its filter color. The
+    /// missing colors are interpolated from the stacked samples when finalized.
+    pub fn set_cfa_pattern(&mut self, pattern: Option<ColorFormatId>) {
+        self.cfa_pattern = pattern;
+    }
+
+    /// Convert an x/y point on the drizzle buffer to the respective point on the input buffer.
+    /// Pixel centers map onto each other, as with drops, and the fractional position is kept
+    /// for the interpolator.
     fn buffer_point_to_input_point(&self, out_x: usize, out_y: usize) -> Point {
         if out_x < self.out_width && out_y < self.out_height {
-            let x = round_f64((out_x as f64 / self.out_width as f64) * self.in_width as f64);
-            let y = round_f64((out_y as f64 / self.out_height as f64) * self.in_height as f64);
+            let scale_x = self.out_width as f32 / self.in_width as f32;
+            let scale_y = self.out_height as f32 / self.in_height as f32;
+            let x = (out_x as f32 + 0.5) / scale_x - 0.5;
+            let y = (out_y as f32 + 0.5) / scale_y - 0.5;
 
             Point {
-                x: x as f32,
-                y: y as f32,
-                valid: (x < self.in_width as f64 && y < self.in_height as f64),
+                x,
+                y,
+                valid: (x < self.in_width as f32 && y < self.in_height as f32),
             }
         } else {
             Point {
@@ -369,6 +980,20 @@ impl BilinearDrizzle {
         other: &Image,
         offset: &Offset,
         rotation: f64,
+    ) -> Result<()> {
+        self.add_with_transform_and_shift_map(other, offset, rotation, None, 1.0)
+    }
+
+    // Adds the image as with `add_with_transform`, additionally warping each pixel point by the local
+    // displacement field of the shift map. Pixels in tiles where the frame was not accepted are skipped.
+    // Samples count for the weight of the frame in buffers which support weighting.
+    pub fn add_with_transform_and_shift_map(
+        &mut self,
+        other: &Image,
+        offset: &Offset,
+        rotation: f64,
+        shift_map: Option<&ShiftMap>,
+        weight: f32,
     ) -> Result<()> {
         info!(
             "Adding drizzle frame of offset {:?} and rotation {}",
@@ -376,6 +1001,10 @@ impl BilinearDrizzle {
             rotation.to_degrees()
         );
 
+        if self.kernel != DrizzleKernel::Interpolate {
+            return self.add_drops(other, offset, rotation, shift_map, weight);
+        }
+
         //let mut mtx = Matrix::identity();
         let mtx = Matrix::rotate(rotation, Axis::ZAxis);
 
@@ -383,6 +1012,15 @@ impl BilinearDrizzle {
             for x in 0..self.out_width as i32 {
                 let mut in_pt = self.buffer_point_to_input_point(x as usize, y as usize);
 
+                if let Some(sm) = shift_map {
+                    if !sm.accepted_at(in_pt.x, in_pt.y) {
+                        continue;
+                    }
+                    let (dh, dv) = sm.displacement_at(in_pt.x, in_pt.y);
+                    in_pt.x += dh;
+                    in_pt.y += dv;
+                }
+
                 let mut pt_vec = Vector::new(
                     in_pt.x as f64 - (other.width / 2) as f64,
                     in_pt.y as f64 - (other.height / 2) as f64,
@@ -397,74 +1035,283 @@ impl BilinearDrizzle {
                 in_pt.x -= offset.h;
                 in_pt.y -= offset.v;
 
-                let mut abc: [f32; 3] = [0.0, 0.0, 0.0];
-
-                for (band, value) in abc.iter_mut().enumerate().take(other.num_bands()) {
-                    if let Some(v) = in_pt.get_interpolated_color(other.get_band(band)) {
-                        *value = v;
+                // Points falling outside of the frame are left unsampled, rather than stacked as
+                // zeros which would darken the edges of the output not covered by every frame
+                let mut abc: [f32; 3] = [f32::NAN; 3];
+
+                if let Some(pattern) = self.cfa_pattern {
+                    // Interpolating between mosaic pixels would mix colors, so the nearest
+                    // pixel is sampled into its own band and the others are left unsampled.
+                    let rx = in_pt.x.round();
+                    let ry = in_pt.y.round();
+                    if rx >= 0.0
+                        && ry >= 0.0
+                        && (rx as usize) < other.width
+                        && (ry as usize) < other.height
+                    {
+                        abc[demosaic::cfa_band(pattern, rx as usize, ry as usize)] =
+                            other.get_band(0).get(rx as usize, ry as usize);
+                    }
+                } else {
+                    for (band, value) in abc.iter_mut().enumerate().take(other.num_bands()) {
+                        if let Some(v) =
+                            in_pt.get_interpolated_color(other.get_band(band), self.interpolation)
+                        {
+                            *value = v;
+                        }
                     }
                 }
 
-                let x2 = x + self.horiz_offset;
-                let y2 = y + self.vert_offset;
+                self.put_sample(x, y, &mut abc, other.num_bands(), weight);
+            }
+        }
+        self.frame_add_count += 1;
+        Ok(())
+    }
 
-                if x2 >= self.out_width as i32 || y2 >= self.out_height as i32 || x2 < 0 || y2 < 0 {
-                    continue;
-                }
+    /// Adds a sample of the given weight to the output pixel, unless shifted outside of the
+    /// buffer by the horizontal and vertical offsets
+    fn put_sample(&mut self, x: i32, y: i32, abc: &mut [f32; 3], num_bands: usize, weight: f32) {
+        let x2 = x + self.horiz_offset;
+        let y2 = y + self.vert_offset;
 
-                match &mut self.buffer {
-                    StackAlgorithmImpl::Average(sai) => {
-                        if other.num_bands() == 1 && sai.num_bands() == 3 {
-                            abc[1] = abc[0];
-                            abc[2] = abc[0];
-                        }
+        if x2 >= self.out_width as i32 || y2 >= self.out_height as i32 || x2 < 0 || y2 < 0 {
+            return;
+        }
+
+        let duplicate_mono = num_bands == 1 && self.cfa_pattern.is_none();
+        if duplicate_mono {
+            abc[1] = abc[0];
+            abc[2] = abc[0];
+        }
+
+        if abc.iter().all(|v| v.is_nan()) {
+            return;
+        }
+
+        let (x, y) = (x as usize, y as usize);
+        match &mut self.buffer {
+            StackAlgorithmImpl::Average(sai) => sai.put_weighted(x, y, abc, weight),
+            StackAlgorithmImpl::SigmaClip(sai) => sai.put_weighted(x, y, abc, weight),
+            // Unweighted buffers take any sample the drop reaches
+            StackAlgorithmImpl::Median(sai) => {
+                if weight > 0.0 {
+                    sai.put(x, y, abc)
+                }
+            }
+            StackAlgorithmImpl::Minimum(sai) => {
+                if weight > 0.0 {
+                    sai.put(x, y, abc)
+                }
+            }
+        };
+        let idx = y * self.out_width + x;
+        self.weight_map[idx] += weight;
+
+        // Drops may reach a pixel several times per frame, but it's covered by the frame once
+        let frame_tag = self.frame_add_count as u32 + 1;
+        if self.coverage_tag[idx] != frame_tag {
+            self.coverage_tag[idx] = frame_tag;
+            self.coverage_map[idx] += 1;
+        }
+    }
 
-                        sai.put(x as usize, y as usize, &abc);
+    // Spreads each input pixel over the output as a drop of the kernel, following Fruchter &
+    // Hook (2002). The transform is the inverse of that of `add_with_transform_and_shift_map`
+    // when interpolating, so both place a frame identically.
+    fn add_drops(
+        &mut self,
+        other: &Image,
+        offset: &Offset,
+        rotation: f64,
+        shift_map: Option<&ShiftMap>,
+        weight: f32,
+    ) -> Result<()> {
+        let mtx = Matrix::rotate(-rotation, Axis::ZAxis);
+        let scale_x = self.out_width as f32 / self.in_width as f32;
+        let scale_y = self.out_height as f32 / self.in_height as f32;
+        let drop_size = self.pixfrac * scale_x;
+        let support = self.kernel.support(drop_size);
+        let num_bands = other.num_bands();
+
+        for in_y in 0..other.height {
+            for in_x in 0..other.width {
+                let mut abc: [f32; 3] = [0.0, 0.0, 0.0];
+                if let Some(pattern) = self.cfa_pattern {
+                    abc = [f32::NAN; 3];
+                    abc[demosaic::cfa_band(pattern, in_x, in_y)] =
+                        other.get_band(0).get(in_x, in_y);
+                } else {
+                    for (band, value) in abc.iter_mut().enumerate().take(num_bands) {
+                        *value = other.get_band(band).get(in_x, in_y);
                     }
-                    StackAlgorithmImpl::Median(sai) => {
-                        if other.num_bands() == 1 && sai.num_bands() == 3 {
-                            abc[1] = abc[0];
-                            abc[2] = abc[0];
-                        }
+                }
 
-                        sai.put(x as usize, y as usize, &abc);
+                let mut pt_vec = Vector::new(
+                    in_x as f64 + offset.h as f64 - (other.width / 2) as f64,
+                    in_y as f64 + offset.v as f64 - (other.height / 2) as f64,
+                    0.0,
+                );
+                pt_vec = mtx.multiply_vector(&pt_vec);
+
+                let mut ref_x = pt_vec.x as f32 + (other.width / 2) as f32;
+                let mut ref_y = pt_vec.y as f32 + (other.height / 2) as f32;
+
+                if let Some(sm) = shift_map {
+                    if !sm.accepted_at(ref_x, ref_y) {
+                        continue;
                     }
-                    StackAlgorithmImpl::Minimum(sai) => {
-                        if other.num_bands() == 1 && sai.num_bands() == 3 {
-                            abc[1] = abc[0];
-                            abc[2] = abc[0];
-                        }
+                    let (dh, dv) = sm.displacement_at(ref_x, ref_y);
+                    ref_x -= dh;
+                    ref_y -= dv;
+                }
 
-                        sai.put(x as usize, y as usize, &abc);
+                // Output pixel n spans [n, n + 1), so pixel centers sit half a pixel in
+                let cx = (ref_x + 0.5) * scale_x;
+                let cy = (ref_y + 0.5) * scale_y;
+                if cx + support < 0.0
+                    || cy + support < 0.0
+                    || cx - support >= self.out_width as f32
+                    || cy - support >= self.out_height as f32
+                {
+                    continue;
+                }
+
+                let norm = self.kernel.normalization(drop_size, cx, cy);
+                if norm == 0.0 {
+                    continue;
+                }
+
+                let x0 = (cx - support).floor().max(0.0) as i32;
+                let x1 = ((cx + support).floor() as i32).min(self.out_width as i32 - 1);
+                let y0 = (cy - support).floor().max(0.0) as i32;
+                let y1 = ((cy + support).floor() as i32).min(self.out_height as i32 - 1);
+
+                for y in y0..=y1 {
+                    for x in x0..=x1 {
+                        let w = norm * self.kernel.weight(drop_size, cx, cy, x as f32, y as f32);
+                        if w != 0.0 {
+                            let mut values = abc;
+                            self.put_sample(x, y, &mut values, num_bands, w * weight);
+                        }
                     }
-                };
+                }
             }
         }
         self.frame_add_count += 1;
         Ok(())
     }
 
+    /// A drizzle of the same geometry with an empty stack buffer, for stacking a share of the
+    /// frames of the current pass in parallel
+    pub fn empty_like(&self) -> Result<Drizzle> {
+        let buffer = match &self.buffer {
+            StackAlgorithmImpl::Average(sai) => StackAlgorithmImpl::Average(sai.empty_like()?),
+            StackAlgorithmImpl::Median(sai) => StackAlgorithmImpl::Median(sai.empty_like()?),
+            StackAlgorithmImpl::Minimum(sai) => StackAlgorithmImpl::Minimum(sai.empty_like()?),
+            StackAlgorithmImpl::SigmaClip(sai) => StackAlgorithmImpl::SigmaClip(sai.empty_like()?),
+        };
+        Ok(Drizzle {
+            in_width: self.in_width,
+            in_height: self.in_height,
+            out_width: self.out_width,
+            out_height: self.out_height,
+            buffer,
+            frame_add_count: 0,
+            horiz_offset: self.horiz_offset,
+            vert_offset: self.vert_offset,
+            cfa_pattern: self.cfa_pattern,
+            kernel: self.kernel,
+            pixfrac: self.pixfrac,
+            interpolation: self.interpolation,
+            weight_map: vec![0.0; self.weight_map.len()],
+            coverage_map: vec![0; self.coverage_map.len()],
+            coverage_tag: vec![0; self.coverage_tag.len()],
+        })
+    }
+
+    /// Ends a pass over the frames. Multiple pass algorithms expect every frame to be added
+    /// again after each call.
+    pub fn next_pass(&mut self) {
+        match &mut self.buffer {
+            StackAlgorithmImpl::Average(sai) => sai.next_pass(),
+            StackAlgorithmImpl::Median(sai) => sai.next_pass(),
+            StackAlgorithmImpl::Minimum(sai) => sai.next_pass(),
+            StackAlgorithmImpl::SigmaClip(sai) => sai.next_pass(),
+        };
+        self.frame_add_count = 0;
+        self.weight_map.iter_mut().for_each(|w| *w = 0.0);
+        self.coverage_map.iter_mut().for_each(|c| *c = 0);
+        self.coverage_tag.iter_mut().for_each(|t| *t = 0);
+    }
+
+    /// The total weight of samples stacked into each output pixel, as a single band image
+    pub fn get_weight_map(&self) -> Result<Image> {
+        let mut image =
+            Image::new_with_bands(self.out_width, self.out_height, 1, ImageMode::U16BIT)?;
+        iproduct!(0..self.out_height, 0..self.out_width).for_each(|(y, x)| {
+            // The negative lobes of Lanczos drops can leave a total below zero
+            image.put(x, y, self.weight_map[y * self.out_width + x].max(0.0), 0);
+        });
+        Ok(image)
+    }
+
+    /// The number of frames stacked into each output pixel, as a single band image. Pixels
+    /// short of the total frame count lie beyond the edge of some of the frames.
+    pub fn get_coverage_map(&self) -> Result<Image> {
+        let mut image =
+            Image::new_with_bands(self.out_width, self.out_height, 1, ImageMode::U16BIT)?;
+        iproduct!(0..self.out_height, 0..self.out_width).for_each(|(y, x)| {
+            image.put(x, y, self.coverage_map[y * self.out_width + x] as f32, 0);
+        });
+        Ok(image)
+    }
+
     pub fn get_finalized(&self) -> Result<Image> {
         if self.frame_add_count == 0 {
-            Err(anyhow!(
-                "No frames have been added, cannot divide mean by zero"
-            ))
+            Err(SolHatError::EmptyStack.into())
         } else {
             // let mut final_buffer = self.buffer.clone();
             // final_buffer.divide_from_each(&self.divisor);
             // Ok(final_buffer)
 
-            match &self.buffer {
+            let mut image = match &self.buffer {
                 StackAlgorithmImpl::Average(sai) => sai.get_finalized(),
                 StackAlgorithmImpl::Median(sai) => sai.get_finalized(),
                 StackAlgorithmImpl::Minimum(sai) => sai.get_finalized(),
+                StackAlgorithmImpl::SigmaClip(sai) => sai.get_finalized(),
+            }?;
+
+            if let Some(pattern) = self.cfa_pattern {
+                match &self.buffer {
+                    StackAlgorithmImpl::Average(sai) => {
+                        demosaic::fill_cfa_holes(&mut image, |x, y, b| sai.has_samples(x, y, b))
+                    }
+                    StackAlgorithmImpl::Median(sai) => {
+                        demosaic::fill_cfa_holes(&mut image, |x, y, b| sai.has_samples(x, y, b))
+                    }
+                    StackAlgorithmImpl::Minimum(sai) => {
+                        demosaic::fill_cfa_holes(&mut image, |x, y, b| sai.has_samples(x, y, b))
+                    }
+                    StackAlgorithmImpl::SigmaClip(sai) => {
+                        demosaic::fill_cfa_holes(&mut image, |x, y, b| sai.has_samples(x, y, b))
+                    }
+                };
+                if demosaic::is_cmy(pattern) {
+                    demosaic::cmy_to_rgb(&mut image);
+                }
             }
+
+            Ok(image)
         }
     }
 
-    pub fn add_drizzle(&mut self, other: &BilinearDrizzle) -> Result<()> {
+    pub fn add_drizzle(&mut self, other: &Drizzle) -> Result<()> {
         if other.out_width != self.out_width {
-            return Err(anyhow!("Buffer dimensions are different. Cannot merge"));
+            return Err(SolHatError::IncompatibleInputs(
+                "Buffer dimensions are different. Cannot merge".to_string(),
+            )
+            .into());
         }
         match &mut self.buffer {
             StackAlgorithmImpl::Average(sai) => {
@@ -482,10 +1329,23 @@ impl BilinearDrizzle {
                     sai.add_other(sai_other);
                 }
             }
+            StackAlgorithmImpl::SigmaClip(sai) => {
+                if let StackAlgorithmImpl::SigmaClip(sai_other) = &other.buffer {
+                    sai.add_other(sai_other);
+                }
+            }
         }
         // self.buffer.add_other(&other.buffer);
         // self.buffer.add(&other.buffer);
         // self.divisor.add_mut(&other.divisor);
+        self.weight_map
+            .iter_mut()
+            .zip(other.weight_map.iter())
+            .for_each(|(w, o)| *w += o);
+        self.coverage_map
+            .iter_mut()
+            .zip(other.coverage_map.iter())
+            .for_each(|(c, o)| *c += o);
         self.frame_add_count += other.frame_add_count;
 
         Ok(())
@@ -0,0 +1,65 @@
+use std::fmt;
+use std::io;
+
+/// Failures of the library that callers may want to tell apart. Functions still return
+/// `anyhow::Result`, with these as the underlying error, so they are recovered with
+/// `err.downcast_ref::<SolHatError>()`.
+#[derive(Debug)]
+pub enum SolHatError {
+    Io(io::Error),
+    Format(String), // Malformed or truncated input data
+    UnsupportedColorFormat(i32),
+    UnsupportedBitDepth(usize),
+    FrameOutOfRange { frame: usize, frame_count: usize },
+    CalibrationMismatch(String),
+    IncompatibleInputs(String), // Inputs or buffers that can't be combined
+    UnsupportedFileType(String),
+    UnknownSource(usize), // A frame refers to an input that isn't loaded
+    InvalidParameter(String),
+    FitFailed(String), // A disk or model fit that didn't converge on the data
+    EmptyStack,
+}
+
+impl fmt::Display for SolHatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolHatError::Io(e) => write!(f, "I/O error: {}", e),
+            SolHatError::Format(msg) => write!(f, "Invalid input format: {}", msg),
+            SolHatError::UnsupportedColorFormat(id) => {
+                write!(f, "Unsupported color format id: {}", id)
+            }
+            SolHatError::UnsupportedBitDepth(depth) => {
+                write!(f, "Unsupported bit depth: {}", depth)
+            }
+            SolHatError::FrameOutOfRange { frame, frame_count } => write!(
+                f,
+                "Frame number {} out of range, source has {} frames",
+                frame, frame_count
+            ),
+            SolHatError::CalibrationMismatch(msg) => {
+                write!(f, "Calibration frame does not match: {}", msg)
+            }
+            SolHatError::IncompatibleInputs(msg) => write!(f, "Incompatible inputs: {}", msg),
+            SolHatError::UnsupportedFileType(path) => write!(f, "Unsupported file type: {}", path),
+            SolHatError::UnknownSource(id) => write!(f, "No input loaded for frame source {}", id),
+            SolHatError::InvalidParameter(msg) => write!(f, "{}", msg),
+            SolHatError::FitFailed(msg) => write!(f, "Fit failed: {}", msg),
+            SolHatError::EmptyStack => write!(f, "No frames to stack"),
+        }
+    }
+}
+
+impl std::error::Error for SolHatError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SolHatError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SolHatError {
+    fn from(e: io::Error) -> Self {
+        SolHatError::Io(e)
+    }
+}
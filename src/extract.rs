@@ -0,0 +1,146 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use sciimg::image::Image;
+
+use crate::context::ProcessContext;
+use crate::datasource::{ColorFormatId, DataSource};
+use crate::error::SolHatError;
+use crate::framerecord::FrameRecord;
+use crate::serwriter::SerWriter;
+use crate::timestamp::TimeStamp;
+
+/// Limits the frame records to those with a frame number (within their input file) in
+/// `first_frame..=last_frame` and a timestamp in `start_time..=end_time`. Unset bounds are open.
+pub fn frame_window_filter<F: DataSource>(
+    context: &ProcessContext<F>,
+    first_frame: Option<usize>,
+    last_frame: Option<usize>,
+    start_time: Option<TimeStamp>,
+    end_time: Option<TimeStamp>,
+) -> Result<Vec<FrameRecord>> {
+    let mut frame_records = vec![];
+
+    for fr in context.frame_records.iter() {
+        let in_range = first_frame.map(|f| fr.frame_id >= f).unwrap_or(true)
+            && last_frame.map(|l| fr.frame_id <= l).unwrap_or(true);
+
+        let in_window = if start_time.is_some() || end_time.is_some() {
+            let ts = fr.get_timestamp(context)?.timestamp;
+            start_time.map(|s| ts >= s.timestamp).unwrap_or(true)
+                && end_time.map(|e| ts <= e.timestamp).unwrap_or(true)
+        } else {
+            true
+        };
+
+        if in_range && in_window {
+            frame_records.push(fr.to_owned());
+        }
+    }
+    Ok(frame_records)
+}
+
+/// Moves the frame by the registration offset, rounded to whole pixels so that no values are
+/// interpolated. Mosaic frames are moved by whole pattern cells to keep the color filter
+/// layout intact.
+pub fn shift_frame(buffer: &Image, offset_h: f32, offset_v: f32, mosaic: bool) -> Result<Image> {
+    let round = |v: f32| {
+        if mosaic {
+            (v / 2.0).round() as i64 * 2
+        } else {
+            v.round() as i64
+        }
+    };
+    let (dh, dv) = (round(offset_h), round(offset_v));
+
+    let mut shifted = Image::new_with_bands(
+        buffer.width,
+        buffer.height,
+        buffer.num_bands(),
+        buffer.get_mode(),
+    )?;
+    for y in 0..buffer.height as i64 {
+        for x in 0..buffer.width as i64 {
+            let (sx, sy) = (x - dh, y - dv);
+            if sx < 0 || sy < 0 || sx >= buffer.width as i64 || sy >= buffer.height as i64 {
+                continue;
+            }
+            for band in 0..buffer.num_bands() {
+                shifted.put(
+                    x as usize,
+                    y as usize,
+                    buffer.get_band(band).get(sx as usize, sy as usize),
+                    band,
+                );
+            }
+        }
+    }
+    Ok(shifted)
+}
+
+/// Writes the calibrated frames of the frame records, in capture order, to a new SER file.
+/// With `center`, each frame is moved by its registration offset. Returns the number of
+/// frames written.
+pub fn extract_frames<C, F>(
+    context: &mut ProcessContext<F>,
+    output_path: &str,
+    center: bool,
+    on_frame_written: C,
+) -> Result<usize>
+where
+    C: Fn(&FrameRecord),
+    F: DataSource,
+{
+    let t = Instant::now();
+
+    let mut frame_records = context.frame_records.clone();
+    frame_records
+        .sort_by(|a, b| (&a.source_file_id, a.frame_id).cmp(&(&b.source_file_id, b.frame_id)));
+
+    let first = frame_records
+        .first()
+        .ok_or_else(|| SolHatError::InvalidParameter("Zero frames to extract".to_string()))?;
+    let source = context
+        .fp_map
+        .get_map()
+        .get(&first.source_file_id)
+        .ok_or(SolHatError::UnknownSource(first.source_file_id))?;
+    let first_frame = first.get_calibrated_frame(context)?;
+
+    // Frames left as the mosaic keep the color filter layout of the input
+    let color_id = match first_frame.buffer.num_bands() {
+        1 if source.color_id().is_mosaic() => source.color_id(),
+        1 => ColorFormatId::Mono,
+        _ => ColorFormatId::Rgb,
+    };
+    let pixel_depth = if source.pixel_depth() <= 8 { 8 } else { 16 };
+
+    let mut writer = SerWriter::create(
+        output_path,
+        color_id,
+        first_frame.buffer.width,
+        first_frame.buffer.height,
+        pixel_depth,
+    )?;
+    writer.copy_metadata(source);
+
+    for fr in frame_records.iter() {
+        let frame = fr.get_calibrated_frame(context)?;
+        let buffer = if center {
+            shift_frame(
+                &frame.buffer,
+                fr.offset.h,
+                fr.offset.v,
+                color_id.is_mosaic(),
+            )?
+        } else {
+            frame.buffer
+        };
+        writer.write_frame(&buffer, &frame.timestamp)?;
+        on_frame_written(fr);
+    }
+
+    let frame_count = writer.finish()?;
+    context.stats.record_stage_timing("extract", t.elapsed());
+    Ok(frame_count)
+}
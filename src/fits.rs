@@ -0,0 +1,683 @@
+// Reader for FITS cubes and sequences of FITS images, and writer of single images. Only the
+// primary HDU is read or written.
+// Specification: https://fits.gsfc.nasa.gov/fits_standard.html
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use memmap::Mmap;
+use sciimg::{enums::ImageMode, image::Image, imagebuffer::ImageBuffer};
+use serde::{Deserialize, Serialize};
+
+use crate::datasource::{ColorFormatId, DataFrame, DataSource, PixelFormat};
+use crate::demosaic;
+use crate::demosaic::DemosaicMethod;
+use crate::error::SolHatError;
+use crate::timestamp::TimeStamp;
+
+const BLOCK_SIZE: usize = 2880;
+const CARD_SIZE: usize = 80;
+
+/// Parsed header of the primary HDU
+#[derive(Debug, Clone)]
+pub struct FitsHeader {
+    pub cards: HashMap<String, String>,
+    pub data_offset: usize,
+}
+
+impl FitsHeader {
+    fn parse(data: &[u8], file_path: &str) -> Result<FitsHeader> {
+        if data.len() < BLOCK_SIZE || !data.starts_with(b"SIMPLE") {
+            return Err(SolHatError::Format(format!("{} is not a FITS file", file_path)).into());
+        }
+
+        let mut cards = HashMap::new();
+        for (i, card) in data.chunks_exact(CARD_SIZE).enumerate() {
+            let key = String::from_utf8_lossy(&card[0..8]).trim().to_string();
+
+            if key == "END" {
+                let header_end = (i + 1) * CARD_SIZE;
+                let data_offset = header_end.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+                return Ok(FitsHeader { cards, data_offset });
+            }
+
+            if &card[8..10] == b"= " {
+                cards.insert(
+                    key,
+                    FitsHeader::parse_value(&String::from_utf8_lossy(&card[10..])),
+                );
+            }
+        }
+
+        Err(SolHatError::Format(format!("No END card found in header of {}", file_path)).into())
+    }
+
+    /// Strips the comment and any quotes from a card value
+    fn parse_value(value: &str) -> String {
+        let value = value.trim();
+        if let Some(quoted) = value.strip_prefix('\'') {
+            // Quotes within strings are escaped by doubling them
+            let mut s = String::new();
+            let mut chars = quoted.chars().peekable();
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                s.push(c);
+            }
+            s.trim_end().to_string()
+        } else {
+            value.split('/').next().unwrap_or("").trim().to_string()
+        }
+    }
+
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        self.cards.get(key).cloned()
+    }
+
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        self.cards.get(key).and_then(|v| v.parse::<f64>().ok())
+    }
+
+    pub fn get_usize(&self, key: &str) -> Option<usize> {
+        self.cards.get(key).and_then(|v| v.parse::<usize>().ok())
+    }
+
+    pub fn require_i64(&self, key: &str) -> Result<i64> {
+        self.cards
+            .get(key)
+            .and_then(|v| v.parse::<i64>().ok())
+            .ok_or_else(|| {
+                SolHatError::Format(format!("Missing or invalid FITS keyword {}", key)).into()
+            })
+    }
+
+    pub fn date_obs(&self) -> Option<TimeStamp> {
+        let date_obs = self.get_string("DATE-OBS")?;
+        ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S"]
+            .iter()
+            .find_map(|f| NaiveDateTime::parse_from_str(&date_obs, f).ok())
+            .map(|dt| TimeStamp::from_naive_datetime(&dt))
+    }
+}
+
+/// A single FITS file of one or more image planes
+struct FitsImage {
+    path: String,
+    header: FitsHeader,
+    bitpix: i64,
+    width: usize,
+    height: usize,
+    planes: usize,
+    bzero: f64,
+    bscale: f64,
+    bottom_up: bool,
+    data: Mmap,
+}
+
+impl FitsImage {
+    fn open(file_path: &str) -> Result<FitsImage> {
+        let data = unsafe { Mmap::map(&File::open(file_path).map_err(SolHatError::from)?) }
+            .map_err(SolHatError::from)?;
+        let header = FitsHeader::parse(&data, file_path)?;
+
+        let bitpix = header.require_i64("BITPIX")?;
+        if ![8, 16, 32, -32, -64].contains(&bitpix) {
+            return Err(SolHatError::Format(format!(
+                "Unsupported BITPIX of {} in {}",
+                bitpix, file_path
+            ))
+            .into());
+        }
+
+        let naxis = header.require_i64("NAXIS")?;
+        if !(2..=3).contains(&naxis) {
+            return Err(SolHatError::Format(format!(
+                "Unsupported NAXIS of {} in {}, expected 2 or 3",
+                naxis, file_path
+            ))
+            .into());
+        }
+
+        Ok(FitsImage {
+            path: file_path.to_string(),
+            bitpix,
+            width: header.require_i64("NAXIS1")? as usize,
+            height: header.require_i64("NAXIS2")? as usize,
+            planes: if naxis == 3 {
+                header.require_i64("NAXIS3")? as usize
+            } else {
+                1
+            },
+            bzero: header.get_f64("BZERO").unwrap_or(0.0),
+            bscale: header.get_f64("BSCALE").unwrap_or(1.0),
+            bottom_up: header.get_string("ROWORDER").as_deref() == Some("BOTTOM-UP"),
+            header,
+            data,
+        })
+    }
+
+    /// Whether the planes are the red, green, and blue bands of a single image. Planes are
+    /// otherwise taken to be frames, as a three frame cube is far from unheard of.
+    fn is_rgb(&self) -> bool {
+        self.planes == 3
+            && matches!(
+                self.header
+                    .get_string("CTYPE3")
+                    .map(|c| c.trim().to_uppercase())
+                    .as_deref(),
+                Some("RGB") | Some("COLOR") | Some("COLOUR")
+            )
+    }
+
+    fn bytes_per_value(&self) -> usize {
+        self.bitpix.unsigned_abs() as usize / 8
+    }
+
+    fn plane_size_bytes(&self) -> usize {
+        self.width * self.height * self.bytes_per_value()
+    }
+
+    fn data_size_bytes(&self) -> usize {
+        self.plane_size_bytes() * self.planes
+    }
+
+    /// Decodes a plane to physical values. Data is always big endian.
+    fn read_plane(&self, plane: usize) -> Result<Vec<f32>> {
+        let start = self.header.data_offset + plane * self.plane_size_bytes();
+        let bytes = self
+            .data
+            .get(start..start + self.plane_size_bytes())
+            .ok_or_else(|| {
+                SolHatError::Format(format!(
+                    "Plane {} extends past the end of {}",
+                    plane, self.path
+                ))
+            })?;
+
+        let raw: Vec<f64> = match self.bitpix {
+            8 => bytes.iter().map(|b| *b as f64).collect(),
+            16 => bytes
+                .chunks_exact(2)
+                .map(|b| i16::from_be_bytes([b[0], b[1]]) as f64)
+                .collect(),
+            32 => bytes
+                .chunks_exact(4)
+                .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                .collect(),
+            -32 => bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                .collect(),
+            _ => bytes
+                .chunks_exact(8)
+                .map(|b| f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+                .collect(),
+        };
+
+        let mut values: Vec<f32> = raw
+            .iter()
+            .map(|v| (self.bzero + self.bscale * v) as f32)
+            .collect();
+
+        if self.bottom_up {
+            values = values
+                .chunks_exact(self.width)
+                .rev()
+                .flatten()
+                .copied()
+                .collect();
+        }
+
+        Ok(values)
+    }
+}
+
+/// A FITS cube, where each plane along the third axis is a frame, or a sequence of FITS
+/// files of one frame each. A three plane file with a CTYPE3 of RGB is a single RGB frame.
+pub struct FitsSource {
+    images: Vec<FitsImage>,
+    frames: Vec<(usize, usize)>, // Image index and first plane of each frame
+    bands_per_frame: usize,
+    value_scale: f32,
+    color_id: ColorFormatId,
+    demosaic_method: DemosaicMethod,
+    source_file: String,
+}
+
+impl FitsSource {
+    pub fn load_fits(paths: &[String]) -> Result<FitsSource> {
+        if paths.is_empty() {
+            return Err(
+                SolHatError::InvalidParameter("No FITS files specified".to_string()).into(),
+            );
+        }
+
+        let images = paths
+            .iter()
+            .map(|p| FitsImage::open(p))
+            .collect::<Result<Vec<FitsImage>>>()?;
+
+        let first = &images[0];
+        let bands_per_frame = if first.is_rgb() { 3 } else { 1 };
+
+        let frames: Vec<(usize, usize)> = if images.len() == 1 && bands_per_frame == 1 {
+            (0..first.planes).map(|p| (0, p)).collect()
+        } else {
+            (0..images.len()).map(|i| (i, 0)).collect()
+        };
+
+        let color_id = match first.header.get_string("BAYERPAT").as_deref() {
+            Some("RGGB") => ColorFormatId::BayerRggb,
+            Some("GRBG") => ColorFormatId::BayerGrbg,
+            Some("GBRG") => ColorFormatId::BayerGbrg,
+            Some("BGGR") => ColorFormatId::BayerBggr,
+            _ if bands_per_frame == 3 => ColorFormatId::Rgb,
+            _ => ColorFormatId::Mono,
+        };
+
+        let mut source = FitsSource {
+            images,
+            frames,
+            bands_per_frame,
+            value_scale: 1.0,
+            color_id,
+            demosaic_method: DemosaicMethod::default(),
+            source_file: paths[0].clone(),
+        };
+
+        // Floating point data is commonly normalized to 0-1, which is scaled up to the 16-bit range
+        if source.images[0].bitpix < 0 {
+            let max = source.images[0]
+                .read_plane(0)?
+                .iter()
+                .fold(f32::MIN, |m, v| m.max(*v));
+            if max <= 1.0 {
+                source.value_scale = 65535.0;
+            }
+        }
+
+        if stump::is_verbose() {
+            source.print_header_details();
+        }
+
+        Ok(source)
+    }
+
+    pub fn print_fits_header_details(&self) {
+        let first = &self.images[0];
+        println!("FITS Header Values:");
+        println!("Source File: {}", self.source_file);
+        println!("Number of Files: {}", self.images.len());
+        println!("BITPIX: {}", first.bitpix);
+        println!("Image Width: {}", first.width);
+        println!("Image Height: {}", first.height);
+        println!("Planes per File: {}", first.planes);
+        println!("Color Id: {:?}", self.color_id);
+        println!("BZERO: {}", first.bzero);
+        println!("BSCALE: {}", first.bscale);
+        println!("Frame Count: {}", self.frames.len());
+        println!("Observer: {}", self.observer());
+        println!("Instrument: {}", self.instrument());
+        println!("Telescope: {}", self.telescope());
+        println!("Date/Time: {:?}", self.date_time());
+    }
+
+    fn mode(&self) -> ImageMode {
+        if self.images[0].bitpix == 8 {
+            ImageMode::U8BIT
+        } else {
+            ImageMode::U16BIT
+        }
+    }
+
+    fn header_string(&self, key: &str) -> String {
+        self.images[0].header.get_string(key).unwrap_or_default()
+    }
+}
+
+impl DataSource for FitsSource {
+    fn color_id(&self) -> ColorFormatId {
+        self.color_id
+    }
+
+    fn file_id(&self) -> String {
+        "FITS".to_string()
+    }
+
+    fn image_width(&self) -> usize {
+        self.images[0].width
+    }
+
+    fn image_height(&self) -> usize {
+        self.images[0].height
+    }
+
+    fn pixel_depth(&self) -> usize {
+        if self.images[0].bitpix == 8 {
+            8
+        } else {
+            16
+        }
+    }
+
+    fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn observer(&self) -> String {
+        self.header_string("OBSERVER")
+    }
+
+    fn instrument(&self) -> String {
+        self.header_string("INSTRUME")
+    }
+
+    fn telescope(&self) -> String {
+        self.header_string("TELESCOP")
+    }
+
+    fn date_time(&self) -> TimeStamp {
+        self.images[0]
+            .header
+            .date_obs()
+            .unwrap_or_else(|| TimeStamp::from_u64(0))
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        self.date_time()
+    }
+
+    fn total_file_size(&self) -> usize {
+        self.images.iter().map(|i| i.data.len()).sum()
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        let (image_idx, first_plane) =
+            *self
+                .frames
+                .get(frame_num)
+                .ok_or_else(|| SolHatError::FrameOutOfRange {
+                    frame: frame_num,
+                    frame_count: self.frames.len(),
+                })?;
+        let image = &self.images[image_idx];
+        let mode = self.mode();
+
+        let buffers = (first_plane..first_plane + self.bands_per_frame)
+            .map(|p| {
+                let values: Vec<f32> = image
+                    .read_plane(p)?
+                    .iter()
+                    .map(|v| (v * self.value_scale).clamp(0.0, 65535.0))
+                    .collect();
+                ImageBuffer::from_vec_as_mode(&values, image.width, image.height, mode)
+            })
+            .collect::<Result<Vec<ImageBuffer>>>()?;
+
+        let buffer = if self.bands_per_frame == 3 {
+            Image::new_from_buffers_rgb(&buffers[0], &buffers[1], &buffers[2], mode)?
+        } else if self.color_id.is_mosaic() {
+            demosaic::demosaic(&buffers[0], self.color_id, self.demosaic_method)?
+        } else {
+            Image::new_from_buffer_mono(&buffers[0])?
+        };
+
+        Ok(DataFrame {
+            buffer,
+            timestamp: self.get_frame_timestamp(frame_num)?,
+        })
+    }
+
+    fn get_frame_timestamp(&self, frame_num: usize) -> Result<TimeStamp> {
+        let (image_idx, _) =
+            *self
+                .frames
+                .get(frame_num)
+                .ok_or_else(|| SolHatError::FrameOutOfRange {
+                    frame: frame_num,
+                    frame_count: self.frames.len(),
+                })?;
+        Ok(self.images[image_idx]
+            .header
+            .date_obs()
+            .unwrap_or_else(|| TimeStamp::from_file_modified(&self.images[image_idx].path)))
+    }
+
+    fn source_file(&self) -> String {
+        self.source_file.clone()
+    }
+
+    fn open(path: &[String]) -> Result<Self> {
+        FitsSource::load_fits(path)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let first = &self.images[0];
+        self.images.iter().try_for_each(|image| {
+            if image.width != first.width
+                || image.height != first.height
+                || image.bitpix != first.bitpix
+                || image.planes != first.planes
+            {
+                Err(SolHatError::IncompatibleInputs(format!(
+                    "{} ({}x{}x{}, BITPIX {}) does not match {} ({}x{}x{}, BITPIX {})",
+                    image.path,
+                    image.width,
+                    image.height,
+                    image.planes,
+                    image.bitpix,
+                    first.path,
+                    first.width,
+                    first.height,
+                    first.planes,
+                    first.bitpix
+                ))
+                .into())
+            } else if image.data.len() < image.header.data_offset + image.data_size_bytes() {
+                Err(SolHatError::Format(format!("{} is truncated", image.path)).into())
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    fn set_demosaic_method(&mut self, method: DemosaicMethod) {
+        self.demosaic_method = method;
+    }
+
+    // FITS data is always big endian and scaled by BZERO/BSCALE
+    fn set_pixel_format(&mut self, _pixel_format: &PixelFormat) -> Result<()> {
+        Ok(())
+    }
+
+    fn print_header_details(&self) {
+        self.print_fits_header_details();
+    }
+
+    fn file_hash(&self) -> String {
+        self.source_file.clone()
+    }
+}
+
+/// Storage type of the data written to a FITS file
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum FitsDataType {
+    #[default]
+    Float32,
+    UInt16, // Stored as signed 16-bit integers offset by BZERO
+}
+
+impl FitsDataType {
+    pub fn from(s: &str) -> Result<FitsDataType> {
+        match s.to_uppercase().as_str() {
+            "FLOAT" | "FLOAT32" | "F32" => Ok(FitsDataType::Float32),
+            "INT" | "UINT16" | "U16" => Ok(FitsDataType::UInt16),
+            _ => Err(SolHatError::InvalidParameter(format!(
+                "Invalid FITS data type: {}. Valid options: float32, uint16",
+                s
+            ))
+            .into()),
+        }
+    }
+
+    fn bitpix(&self) -> i64 {
+        match self {
+            FitsDataType::Float32 => -32,
+            FitsDataType::UInt16 => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FitsValue {
+    Logical(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl FitsValue {
+    /// Formats the value as it appears in a card, with numbers right justified to column 30
+    fn to_card_value(&self) -> String {
+        match self {
+            FitsValue::Logical(b) => format!("{:>20}", if *b { "T" } else { "F" }),
+            FitsValue::Integer(i) => format!("{:>20}", i),
+            FitsValue::Float(f) => {
+                let s = if *f == 0.0 || (1e-4..1e15).contains(&f.abs()) {
+                    let s = format!("{}", f);
+                    if s.contains('.') {
+                        s
+                    } else {
+                        format!("{}.0", s)
+                    }
+                } else {
+                    format!("{:E}", f)
+                };
+                format!("{:>20}", s)
+            }
+            // Strings are padded to at least eight characters within the quotes
+            FitsValue::String(s) => format!("'{:<8}'", s.replace('\'', "''")),
+        }
+    }
+}
+
+/// A header keyword card
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitsKeyword {
+    pub key: String,
+    pub value: FitsValue,
+    pub comment: String,
+}
+
+impl FitsKeyword {
+    pub fn new(key: &str, value: FitsValue, comment: &str) -> FitsKeyword {
+        FitsKeyword {
+            key: key.to_uppercase(),
+            value,
+            comment: comment.to_string(),
+        }
+    }
+
+    fn to_card(&self) -> Vec<u8> {
+        let mut card = format!("{:<8}= {}", self.key, self.value.to_card_value());
+        if !self.comment.is_empty() {
+            card = format!("{} / {}", card, self.comment);
+        }
+        card_bytes(&card)
+    }
+}
+
+/// A card of exactly 80 ASCII characters
+fn card_bytes(text: &str) -> Vec<u8> {
+    let mut bytes: Vec<u8> = text
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .take(CARD_SIZE)
+        .collect();
+    bytes.resize(CARD_SIZE, b' ');
+    bytes
+}
+
+/// Writes the image to a single HDU FITS file, with the supplied keywords following the
+/// structural ones. Multi-band images are written as a cube of one plane per band. Rows are
+/// written bottom-up per the standard, which is recorded with ROWORDER.
+pub fn save_fits(
+    image: &Image,
+    output_path: &str,
+    data_type: FitsDataType,
+    keywords: &[FitsKeyword],
+) -> Result<()> {
+    let num_bands = image.num_bands();
+    let mut cards = vec![
+        FitsKeyword::new(
+            "SIMPLE",
+            FitsValue::Logical(true),
+            "Conforms to the FITS standard",
+        ),
+        FitsKeyword::new("BITPIX", FitsValue::Integer(data_type.bitpix()), ""),
+        FitsKeyword::new(
+            "NAXIS",
+            FitsValue::Integer(if num_bands > 1 { 3 } else { 2 }),
+            "",
+        ),
+        FitsKeyword::new("NAXIS1", FitsValue::Integer(image.width as i64), ""),
+        FitsKeyword::new("NAXIS2", FitsValue::Integer(image.height as i64), ""),
+    ];
+    if num_bands > 1 {
+        cards.push(FitsKeyword::new(
+            "NAXIS3",
+            FitsValue::Integer(num_bands as i64),
+            "",
+        ));
+    }
+    if num_bands == 3 {
+        cards.push(FitsKeyword::new(
+            "CTYPE3",
+            FitsValue::String("RGB".to_string()),
+            "Planes are color bands",
+        ));
+    }
+    if data_type == FitsDataType::UInt16 {
+        cards.push(FitsKeyword::new("BZERO", FitsValue::Float(32768.0), ""));
+        cards.push(FitsKeyword::new("BSCALE", FitsValue::Float(1.0), ""));
+    }
+    cards.push(FitsKeyword::new(
+        "ROWORDER",
+        FitsValue::String("BOTTOM-UP".to_string()),
+        "Order of the rows",
+    ));
+    cards.extend_from_slice(keywords);
+
+    let mut bytes: Vec<u8> = cards.iter().flat_map(|c| c.to_card()).collect();
+    bytes.extend(card_bytes("END"));
+    bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, b' ');
+
+    for band in 0..num_bands {
+        let buffer = image.get_band(band);
+        for y in (0..image.height).rev() {
+            for x in 0..image.width {
+                let v = buffer.get(x, y);
+                match data_type {
+                    FitsDataType::Float32 => bytes.extend_from_slice(&v.to_be_bytes()),
+                    FitsDataType::UInt16 => {
+                        let v = (v.round().clamp(0.0, 65535.0) as i32 - 32768) as i16;
+                        bytes.extend_from_slice(&v.to_be_bytes());
+                    }
+                }
+            }
+        }
+    }
+    bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&bytes)?;
+    Ok(())
+}
@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 
-use anyhow::{Error, Result};
+use anyhow::Result;
 
-use crate::datasource::DataSource;
+use crate::datasource::{DataSource, ImageDataSource, PixelFormat};
+use crate::demosaic::DemosaicMethod;
+use crate::error::SolHatError;
 
 /** file pointer map */
-pub struct FpMap<F: DataSource> {
+pub struct FpMap<F: DataSource = Box<ImageDataSource>> {
     pub map: HashMap<String, F>,
 }
 
@@ -47,15 +49,22 @@ impl<F: DataSource> FpMap<F> {
     //     self.map.get(path)
     // }
 
-    pub fn open(&mut self, paths: &[String]) -> Result<()> {
-        let ser_file = F::open(paths)?;
+    pub fn open(
+        &mut self,
+        paths: &[String],
+        demosaic_method: DemosaicMethod,
+        pixel_format: &PixelFormat,
+    ) -> Result<()> {
+        let mut ser_file = F::open(paths)?;
+        ser_file.set_pixel_format(pixel_format)?;
         ser_file.validate()?;
+        ser_file.set_demosaic_method(demosaic_method);
 
         if !self.contains(&ser_file.file_hash()) {
             self.map.insert(ser_file.file_hash(), ser_file);
             Ok(())
         } else {
-            Err(Error::msg("File already opened"))
+            Err(SolHatError::InvalidParameter("File already opened".to_string()).into())
         }
     }
 }
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::datasource::DataFrame;
+
+type FrameKey = (String, usize);
+
+struct CacheEntry {
+    frame: Arc<DataFrame>,
+    size_bytes: usize,
+}
+
+/// Cache of decoded frames keyed by source file id and frame number, bounded by the
+/// approximate memory used by the cached frame buffers. Saves re-decoding and re-debayering a
+/// frame each time a pipeline stage visits it.
+///
+/// Every stage sweeps the whole sequence in order, under which evicting the least recently
+/// used frame would drop each frame just before it is read again. Frames are instead held from
+/// their first read until dropped by `retain`, and frames read once the cache is full are not
+/// stored. After frame limiting, the frames read first are the reference and highest quality
+/// frames, so those are the ones held when the sequence doesn't fit.
+pub struct FrameCache {
+    capacity_bytes: usize,
+    used_bytes: usize,
+    entries: HashMap<FrameKey, CacheEntry>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// Approximate memory held by the frame's buffer
+fn frame_size_bytes(frame: &DataFrame) -> usize {
+    frame.buffer.width * frame.buffer.height * frame.buffer.num_bands() * std::mem::size_of::<f32>()
+}
+
+impl FrameCache {
+    pub fn new(capacity_mb: usize) -> Self {
+        FrameCache {
+            capacity_bytes: capacity_mb * 1024 * 1024,
+            used_bytes: 0,
+            entries: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity_bytes > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    pub fn get(&mut self, source_file_id: &str, frame_id: usize) -> Option<Arc<DataFrame>> {
+        match self.entries.get(&(source_file_id.to_owned(), frame_id)) {
+            Some(entry) => {
+                self.hits += 1;
+                Some(entry.frame.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Adds the frame if it fits within the remaining capacity
+    pub fn put(&mut self, source_file_id: &str, frame_id: usize, frame: Arc<DataFrame>) {
+        let key = (source_file_id.to_owned(), frame_id);
+        let size_bytes = frame_size_bytes(&frame);
+        if self.entries.contains_key(&key) || self.used_bytes + size_bytes > self.capacity_bytes {
+            return;
+        }
+
+        self.entries.insert(key, CacheEntry { frame, size_bytes });
+        self.used_bytes += size_bytes;
+    }
+
+    /// Drops the frames for which `keep` is false, making room for others
+    pub fn retain<P: Fn(&str, usize) -> bool>(&mut self, keep: P) {
+        self.entries
+            .retain(|(source_file_id, frame_id), _| keep(source_file_id, *frame_id));
+        self.used_bytes = self.entries.values().map(|e| e.size_bytes).sum();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used_bytes = 0;
+    }
+}
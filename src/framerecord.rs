@@ -1,34 +1,54 @@
 use std::cmp::Ordering;
+use std::sync::Arc;
 
 use anyhow::Result;
 use sciimg::imagebuffer::Offset;
 
+use crate::alignmentpoints::ShiftMap;
 use crate::context::ProcessContext;
 use crate::datasource::{DataFrame, DataSource};
+use crate::error::SolHatError;
 use crate::hotpixel;
 use crate::target::TargetPosition;
 use crate::timestamp::TimeStamp;
 
 #[derive(Debug, Clone)]
 pub struct FrameRecord {
-    pub source_file_id: String, // The input filename of the ser file
-    pub frame_id: usize,        // The index of the frame within the ser file
-    pub frame_width: usize,     // The width, in pixels, of the frame
-    pub frame_height: usize,    // The height, in pixels, of the frame
-    pub sigma: f64,             // The computed quality (sigma) value of the raw image
-    pub computed_rotation: f64, // The parallactic angle of rotation, in radians
-    pub offset: Offset,         // The center-of-mass offset needed to center the target
+    pub source_file_id: String,      // The input filename of the ser file
+    pub frame_id: usize,             // The index of the frame within the ser file
+    pub frame_width: usize,          // The width, in pixels, of the frame
+    pub frame_height: usize,         // The height, in pixels, of the frame
+    pub sigma: f64,                  // The computed quality (sigma) value of the raw image
+    pub computed_rotation: f64,      // The parallactic angle of rotation, in radians
+    pub offset: Offset,              // The center-of-mass offset needed to center the target
+    pub shift_map: Option<ShiftMap>, // Local per-tile shifts relative to the reference frame
+    pub weight: f32,                 // Contribution of the frame to the stack, from its quality
 }
 
 impl FrameRecord {
-    pub fn get_frame<F: DataSource>(&self, context: &ProcessContext<F>) -> Result<DataFrame> {
+    /// The decoded frame, shared with the frame cache
+    pub fn get_frame<F: DataSource>(&self, context: &ProcessContext<F>) -> Result<Arc<DataFrame>> {
+        if let Some(frame) = context
+            .lock_frame_cache()
+            .get(&self.source_file_id, self.frame_id)
+        {
+            return Ok(frame);
+        }
+
         let (_, ser) = context
             .fp_map
             .get_map()
             .iter()
             .find(|(id, _)| **id == self.source_file_id)
-            .unwrap();
-        ser.get_frame(self.frame_id)
+            .ok_or(SolHatError::UnknownSource(self.source_file_id))?;
+        let frame = Arc::new(ser.get_frame(self.frame_id)?);
+
+        // Decoding happens outside of the lock so other threads aren't held up
+        let mut cache = context.lock_frame_cache();
+        if cache.is_enabled() {
+            cache.put(&self.source_file_id, self.frame_id, frame.clone());
+        }
+        Ok(frame)
     }
 
     pub fn num_bands<F: DataSource>(&self, context: &ProcessContext<F>) -> Result<usize> {
@@ -40,7 +60,9 @@ impl FrameRecord {
         &self,
         context: &ProcessContext<F>,
     ) -> Result<DataFrame> {
-        let mut frame_buffer = self.get_frame(context)?;
+        // Calibration works on a copy unless the frame isn't held by the cache
+        let mut frame_buffer =
+            Arc::try_unwrap(self.get_frame(context)?).unwrap_or_else(|f| (*f).clone());
 
         frame_buffer.buffer.calibrate2(
             &context.master_flat.image,
@@ -62,7 +84,7 @@ impl FrameRecord {
             .get_map()
             .iter()
             .find(|(id, _)| **id == self.source_file_id)
-            .unwrap();
+            .ok_or(SolHatError::UnknownSource(self.source_file_id))?;
         ser.get_frame_timestamp(self.frame_id)
     }
 
@@ -1,9 +1,12 @@
-use anyhow::anyhow;
+use std::io;
+
 use anyhow::Result;
 use sciimg::path;
 use sciimg::prelude::*;
 use serde::Deserialize;
 
+use crate::error::SolHatError;
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct HotPixelMap {
     pub hotpixels: Vec<Vec<usize>>,
@@ -13,7 +16,11 @@ pub struct HotPixelMap {
 
 pub fn load_hotpixel_map(file_path: &str) -> Result<HotPixelMap> {
     if !path::file_exists(file_path) {
-        Err(anyhow!("File not found: {}", file_path))
+        Err(SolHatError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("File not found: {}", file_path),
+        ))
+        .into())
     } else {
         let t = std::fs::read_to_string(file_path)?;
         Ok(toml::from_str(&t)?)
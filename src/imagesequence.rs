@@ -0,0 +1,218 @@
+// Data source of a directory or list of individual image files, such as the TIFF and PNG
+// sequences written by SharpCap and FireCapture. Each image is one frame.
+
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
+use sciimg::{enums::ImageMode, image::Image};
+
+use crate::datasource::{ColorFormatId, DataFrame, DataSource, PixelFormat};
+use crate::demosaic::DemosaicMethod;
+use crate::error::SolHatError;
+use crate::timestamp::TimeStamp;
+
+pub const IMAGE_EXTENSIONS: [&str; 6] = ["TIF", "TIFF", "PNG", "JPG", "JPEG", "BMP"];
+
+/// Whether the path has one of the extensions in the list, ignoring case
+pub fn has_extension(path: &str, extensions: &[&str]) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| extensions.contains(&e.to_uppercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Lists the files within a directory having any of the extensions, sorted by name
+pub fn list_directory(dir_path: &str, extensions: &[&str]) -> Result<Vec<String>> {
+    let mut files: Vec<String> = std::fs::read_dir(dir_path)
+        .map_err(SolHatError::from)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().to_string_lossy().to_string())
+        .filter(|p| has_extension(p, extensions))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+pub struct ImageSequence {
+    pub files: Vec<String>,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub num_bands: usize,
+    pub mode: ImageMode,
+    source_file: String,
+}
+
+impl ImageSequence {
+    /// Opens a sequence from a list of image files, or from a single directory containing them
+    pub fn load_sequence(paths: &[String]) -> Result<ImageSequence> {
+        let files = if paths.len() == 1 && Path::new(&paths[0]).is_dir() {
+            list_directory(&paths[0], &IMAGE_EXTENSIONS)?
+        } else {
+            paths.to_vec()
+        };
+
+        if files.is_empty() {
+            return Err(SolHatError::Format(format!("No image files found in {:?}", paths)).into());
+        }
+
+        // The shape of the sequence is taken from the first image
+        let first = Image::open(&files[0])?;
+
+        let sequence = ImageSequence {
+            files,
+            image_width: first.width,
+            image_height: first.height,
+            num_bands: first.num_bands(),
+            mode: first.get_mode(),
+            source_file: paths[0].clone(),
+        };
+
+        if stump::is_verbose() {
+            sequence.print_header_details();
+        }
+
+        Ok(sequence)
+    }
+
+    pub fn print_sequence_details(&self) {
+        println!("Image Sequence Values:");
+        println!("Source: {}", self.source_file);
+        println!("First File: {}", self.files[0]);
+        println!("Image Width: {}", self.image_width);
+        println!("Image Height: {}", self.image_height);
+        println!("Bands: {}", self.num_bands);
+        println!("Pixel Depth: {}", self.pixel_depth());
+        println!("Frame Count: {}", self.files.len());
+    }
+}
+
+impl DataSource for ImageSequence {
+    fn color_id(&self) -> ColorFormatId {
+        if self.num_bands >= 3 {
+            ColorFormatId::Rgb
+        } else {
+            ColorFormatId::Mono
+        }
+    }
+
+    fn file_id(&self) -> String {
+        "IMAGES".to_string()
+    }
+
+    fn image_width(&self) -> usize {
+        self.image_width
+    }
+
+    fn image_height(&self) -> usize {
+        self.image_height
+    }
+
+    fn pixel_depth(&self) -> usize {
+        match self.mode {
+            ImageMode::U8BIT => 8,
+            _ => 16,
+        }
+    }
+
+    fn frame_count(&self) -> usize {
+        self.files.len()
+    }
+
+    fn observer(&self) -> String {
+        "".to_string()
+    }
+
+    fn instrument(&self) -> String {
+        "".to_string()
+    }
+
+    fn telescope(&self) -> String {
+        "".to_string()
+    }
+
+    fn date_time(&self) -> TimeStamp {
+        TimeStamp::from_file_modified(&self.files[0])
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        self.date_time()
+    }
+
+    fn total_file_size(&self) -> usize {
+        self.files
+            .iter()
+            .map(|f| std::fs::metadata(f).map(|m| m.len() as usize).unwrap_or(0))
+            .sum()
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        let file = self
+            .files
+            .get(frame_num)
+            .ok_or_else(|| SolHatError::FrameOutOfRange {
+                frame: frame_num,
+                frame_count: self.files.len(),
+            })?;
+
+        let buffer = Image::open(file)?;
+        if buffer.width != self.image_width || buffer.height != self.image_height {
+            return Err(SolHatError::IncompatibleInputs(format!(
+                "{} is {}x{}, expected {}x{}",
+                file, buffer.width, buffer.height, self.image_width, self.image_height
+            ))
+            .into());
+        }
+
+        Ok(DataFrame {
+            buffer,
+            timestamp: self.get_frame_timestamp(frame_num)?,
+        })
+    }
+
+    fn get_frame_timestamp(&self, frame_num: usize) -> Result<TimeStamp> {
+        let file = self
+            .files
+            .get(frame_num)
+            .ok_or_else(|| SolHatError::FrameOutOfRange {
+                frame: frame_num,
+                frame_count: self.files.len(),
+            })?;
+        Ok(TimeStamp::from_file_modified(file))
+    }
+
+    fn source_file(&self) -> String {
+        self.source_file.clone()
+    }
+
+    fn open(path: &[String]) -> Result<Self> {
+        ImageSequence::load_sequence(path)
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self.files.iter().find(|f| !Path::new(f).is_file()) {
+            Some(f) => Err(SolHatError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist", f),
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    // Images are read already in their final color form
+    fn set_demosaic_method(&mut self, _method: DemosaicMethod) {}
+
+    fn set_pixel_format(&mut self, _pixel_format: &PixelFormat) -> Result<()> {
+        Ok(())
+    }
+
+    fn print_header_details(&self) {
+        self.print_sequence_details();
+    }
+
+    fn file_hash(&self) -> String {
+        self.source_file.clone()
+    }
+}
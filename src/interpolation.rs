@@ -0,0 +1,155 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use sciimg::Dn;
+use serde::{Deserialize, Serialize};
+
+use crate::error::SolHatError;
+
+/// Samples a buffer at fractional pixel coordinates
+pub trait Interpolator: Send + Sync {
+    /// The value at the x/y point, or None if the point lies outside of the buffer. Neighbors
+    /// beyond the edges of the buffer take the value of the nearest edge pixel.
+    fn interpolate(&self, buffer: &ImageBuffer, x: f32, y: f32) -> Option<Dn>;
+}
+
+fn is_inside(buffer: &ImageBuffer, x: f32, y: f32) -> bool {
+    x >= 0.0 && y >= 0.0 && x < buffer.width as f32 && y < buffer.height as f32
+}
+
+fn get_clamped(buffer: &ImageBuffer, x: i32, y: i32) -> Dn {
+    let x = x.clamp(0, buffer.width as i32 - 1) as usize;
+    let y = y.clamp(0, buffer.height as i32 - 1) as usize;
+    buffer.get(x, y)
+}
+
+/// Separable interpolation over the square of neighbors within `radius` pixels of the point,
+/// normalized by the total weight
+fn convolve<K: Fn(f32) -> f32>(buffer: &ImageBuffer, x: f32, y: f32, radius: i32, kernel: K) -> Dn {
+    let (x_fl, y_fl) = (x.floor(), y.floor());
+    let (x_frac, y_frac) = (x - x_fl, y - y_fl);
+
+    let mut ttl = 0.0;
+    let mut ttl_weight = 0.0;
+    for j in (1 - radius)..=radius {
+        let wy = kernel(j as f32 - y_frac);
+        for i in (1 - radius)..=radius {
+            let w = kernel(i as f32 - x_frac) * wy;
+            ttl += w * get_clamped(buffer, x_fl as i32 + i, y_fl as i32 + j);
+            ttl_weight += w;
+        }
+    }
+    ttl / ttl_weight
+}
+
+/// Value of the nearest pixel
+pub struct NearestNeighbor;
+
+impl Interpolator for NearestNeighbor {
+    fn interpolate(&self, buffer: &ImageBuffer, x: f32, y: f32) -> Option<Dn> {
+        if !is_inside(buffer, x, y) {
+            return None;
+        }
+        Some(get_clamped(buffer, x.round() as i32, y.round() as i32))
+    }
+}
+
+/// Linear interpolation between the four surrounding pixels
+pub struct Bilinear;
+
+impl Interpolator for Bilinear {
+    fn interpolate(&self, buffer: &ImageBuffer, x: f32, y: f32) -> Option<Dn> {
+        if !is_inside(buffer, x, y) {
+            return None;
+        }
+        Some(convolve(buffer, x, y, 1, |d| 1.0 - d.abs()))
+    }
+}
+
+/// Catmull-Rom cubic convolution (Keys, 1981, with a = -0.5) over the 4x4 surrounding pixels
+pub struct Bicubic;
+
+fn cubic(d: f32) -> f32 {
+    const A: f32 = -0.5;
+    let d = d.abs();
+    if d <= 1.0 {
+        (A + 2.0) * d * d * d - (A + 3.0) * d * d + 1.0
+    } else if d < 2.0 {
+        A * d * d * d - 5.0 * A * d * d + 8.0 * A * d - 4.0 * A
+    } else {
+        0.0
+    }
+}
+
+impl Interpolator for Bicubic {
+    fn interpolate(&self, buffer: &ImageBuffer, x: f32, y: f32) -> Option<Dn> {
+        if !is_inside(buffer, x, y) {
+            return None;
+        }
+        Some(convolve(buffer, x, y, 2, cubic))
+    }
+}
+
+/// Windowed sinc over the 6x6 surrounding pixels
+pub struct Lanczos3;
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let px = std::f32::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+pub fn lanczos3(x: f32) -> f32 {
+    if x.abs() < 3.0 {
+        sinc(x) * sinc(x / 3.0)
+    } else {
+        0.0
+    }
+}
+
+impl Interpolator for Lanczos3 {
+    fn interpolate(&self, buffer: &ImageBuffer, x: f32, y: f32) -> Option<Dn> {
+        if !is_inside(buffer, x, y) {
+            return None;
+        }
+        Some(convolve(buffer, x, y, 3, lanczos3))
+    }
+}
+
+/// Interpolator used where frames are resampled, as when registering and rotating them, and
+/// where the limb is sampled to fit the disk
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum InterpolationMethod {
+    Nearest,
+    #[default]
+    Bilinear,
+    Bicubic,
+    Lanczos3,
+}
+
+impl InterpolationMethod {
+    pub fn from(s: &str) -> Result<InterpolationMethod> {
+        match s.to_uppercase().as_str() {
+            "NEAREST" => Ok(InterpolationMethod::Nearest),
+            "BILINEAR" => Ok(InterpolationMethod::Bilinear),
+            "BICUBIC" => Ok(InterpolationMethod::Bicubic),
+            "LANCZOS" | "LANCZOS3" => Ok(InterpolationMethod::Lanczos3),
+            _ => Err(SolHatError::InvalidParameter(format!(
+                "Invalid interpolation method: {}. Valid options: nearest, bilinear, bicubic, lanczos3",
+                s
+            ))
+            .into()),
+        }
+    }
+
+    pub fn interpolator(self) -> &'static dyn Interpolator {
+        match self {
+            InterpolationMethod::Nearest => &NearestNeighbor,
+            InterpolationMethod::Bilinear => &Bilinear,
+            InterpolationMethod::Bicubic => &Bicubic,
+            InterpolationMethod::Lanczos3 => &Lanczos3,
+        }
+    }
+}
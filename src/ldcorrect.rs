@@ -1,205 +1,190 @@
-use crate::point::Point;
-use anyhow::{anyhow, Result};
-use sciimg::matrix::Matrix;
+use crate::disk::{fit_disk, Disk};
+use crate::error::SolHatError;
+use crate::interpolation::InterpolationMethod;
+use crate::limbdarkening::{self, LimbDarkeningFit, LimbDarkeningModel};
+use crate::radialprofile::RadialProfile;
+use anyhow::Result;
 use sciimg::path;
 use sciimg::prelude::*;
 use sciimg::vector::Vector;
 use sciimg::Dn;
-use std::process;
-
-trait AvgValueAtRadius {
-    fn avg_value_at_radius(&self, radius: f64, band: usize) -> Dn;
-}
-
-impl AvgValueAtRadius for Image {
-    fn avg_value_at_radius(&self, radius: f64, band: usize) -> Dn {
-        let middle_x = self.width / 2;
-        let middle_y = self.height / 2;
-
-        let mut ttl: Dn = 0.0;
-        let mut cnt = 0;
-
-        for d in 0..360 {
-            let mtx = Matrix::rotate((d as f64).to_radians(), Axis::ZAxis);
-            let mut pt_vec = Vector::new(radius, 0.0, 0.0);
-            pt_vec = mtx.multiply_vector(&pt_vec);
-            let pt = Point {
-                x: pt_vec.x as f32 + middle_x as f32,
-                y: pt_vec.y as f32 + middle_y as f32,
-                valid: true,
-            };
-
-            if let Some(v) = pt.get_interpolated_color(self.get_band(band)) {
-                ttl += v;
-                cnt += 1;
-            }
-        }
-        ttl / cnt as Dn
-    }
-}
-
-pub fn print_radial_intensities(image_file: &String, radius_pixels: usize) {
-    if !path::file_exists(image_file) {
-        error!("ERROR: File not found: {}", image_file);
-        process::exit(1);
-    }
-
-    let img = Image::open(image_file).unwrap();
-
-    let middle_x = img.width / 2;
-    let middle_y = img.height / 2;
-
-    if middle_x + radius_pixels > img.width {
-        error!(
-            "ERROR: Radius {} exceeds image bounds. {}",
-            radius_pixels, img.width
-        );
-        process::exit(3);
-    }
-
-    for r_u in 0..radius_pixels {
-        let r = r_u as f64;
-
-        let mut ttl: Dn = 0.0;
-        let mut cnt = 0;
-
-        let c = r * r * std::f64::consts::PI;
-
-        for d in (0..360).step_by((360.0 / c.floor()) as usize) {
-            let mtx = Matrix::rotate((d as f64).to_radians(), Axis::ZAxis);
-            let mut pt_vec = Vector::new(r, 0.0, 0.0);
-            pt_vec = mtx.multiply_vector(&pt_vec);
-            let pt = Point {
-                x: pt_vec.x as f32 + middle_x as f32,
-                y: pt_vec.y as f32 + middle_y as f32,
-                valid: true,
-            };
-
-            if let Some(v) = pt.get_interpolated_color(img.get_band(0)) {
-                ttl += v;
-                cnt += 1;
-            }
-        }
-        let value_at_r = ttl / cnt as Dn;
-
-        println!("{},{}", r, value_at_r);
-    }
-}
+use std::io;
 
 pub fn limb_darkening_correction(
     image_file: &String,
     output_file: &String,
-    radius_pixels: usize,
+    center: Option<(f64, f64)>,
+    radius_pixels: Option<f64>,
+    model: LimbDarkeningModel,
     ld_coefficients: &[f64],
     composite_gradient_margin: f64,
     inverted_chromosphere: bool,
-) -> Result<()> {
+    interpolation: InterpolationMethod,
+) -> Result<Vec<LimbDarkeningFit>> {
     if !path::file_exists(image_file) {
-        error!("ERROR: File not found: {}", image_file);
-        process::exit(1);
+        return Err(SolHatError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("File not found: {}", image_file),
+        ))
+        .into());
     }
 
     if !path::parent_exists_and_writable(output_file) {
-        error!("ERROR: Output directory not found or is not writable");
-        process::exit(2);
+        return Err(SolHatError::Io(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Output directory not found or is not writable: {}",
+                path::get_parent(output_file)
+            ),
+        ))
+        .into());
     }
 
     vprintln!("Opening input file: {}", image_file);
-    let img = Image::open(image_file).unwrap();
+    let img = Image::open(image_file)?;
 
-    match limb_darkening_correction_on_image(
+    let correction = limb_darkening_correction_on_image(
         &img,
+        center,
         radius_pixels,
+        model,
         ld_coefficients,
         composite_gradient_margin,
         inverted_chromosphere,
-    ) {
-        Ok(corrected_output) => {
-            vprintln!("Writing corrected image to {}", output_file);
-            corrected_output
-                .save(output_file)
-                .expect("Failed to save image");
-            Ok(())
-        }
-        Err(why) => Err(why),
-    }
+        interpolation,
+    )?;
+
+    vprintln!("Writing corrected image to {}", output_file);
+    correction.image.save(output_file)?;
+    Ok(correction.fits)
+}
+
+/// Fits the disk to the limb of the image, reporting the result
+pub fn detect_disk(img: &Image, interpolation: InterpolationMethod) -> Result<Disk> {
+    let disk = fit_disk(img.get_band(0), None, interpolation)?;
+    info!(
+        "Detected disk at {:.2} ± {:.2}, {:.2} ± {:.2} with radius {:.2} ± {:.2} pixels ({} of {} edge points)",
+        disk.x,
+        disk.x_err,
+        disk.y,
+        disk.y_err,
+        disk.radius,
+        disk.radius_err,
+        disk.inliers,
+        disk.edge_points
+    );
+    Ok(disk)
+}
+
+/// The limb darkening corrected image, with the model fitted to each band
+pub struct LimbDarkeningCorrection {
+    pub image: Image,
+    pub fits: Vec<LimbDarkeningFit>,
+}
+
+/// Fraction of the radius out to which the profile is fitted, short of the blurred limb
+const PROFILE_FIT_EXTENT: f64 = 0.98;
+
+/// The radial profile of the disk as (mu, mean intensity) of each pixel wide annulus
+fn mu_profile(profile: &RadialProfile, radius: f64, band: usize) -> Vec<(f64, f64)> {
+    profile.bands[band]
+        .iter()
+        .map(|b| {
+            (
+                (1.0 - (b.radius * b.radius) / (radius * radius)).sqrt(),
+                b.mean,
+            )
+        })
+        .collect()
 }
 
+/// Corrects the limb darkening of the disk of the given center and radius, those not specified
+/// being fitted to the limb. The disk may lie partially outside of the image. The model is
+/// fitted by least squares to the radial profile of each band, or with coefficients given,
+/// only its center intensity. The modeled darkening is then added back to each pixel. The
+/// interpolation is that used to sample the limb when fitting the disk.
 pub fn limb_darkening_correction_on_image(
     img: &Image,
-    radius_pixels: usize,
+    center: Option<(f64, f64)>,
+    radius_pixels: Option<f64>,
+    model: LimbDarkeningModel,
     ld_coefficients: &[f64],
     composite_gradient_margin: f64,
     inverted_chromosphere: bool,
-) -> Result<Image> {
+    interpolation: InterpolationMethod,
+) -> Result<LimbDarkeningCorrection> {
     info!(
         "Generating output buffer of size {}x{}",
         img.width, img.height
     );
-    //let mut corrected_output = ImageBuffer::new(img.width, img.height).unwrap();
 
-    if ld_coefficients.is_empty()
-        || (ld_coefficients.len() > 1 && ld_coefficients.len() != img.num_bands())
-    {
+    // All zero coefficients (0.0) leave them to be fitted
+    let num_coefficients = model.num_coefficients();
+    let fixed_coefficients: Option<Vec<&[f64]>> = if ld_coefficients.iter().all(|c| *c == 0.0) {
+        None
+    } else if ld_coefficients.len() == num_coefficients {
+        Some(vec![ld_coefficients; img.num_bands()])
+    } else if ld_coefficients.len() == num_coefficients * img.num_bands() {
+        Some(ld_coefficients.chunks(num_coefficients).collect())
+    } else {
         error!("Invalid number of limb darkening coefficients");
-        return Err(anyhow!("Invalid number of limb darkening coefficients"));
-    }
+        return Err(SolHatError::InvalidParameter(format!(
+            "Invalid number of limb darkening coefficients. The {} model takes {}, or {} per band",
+            model, num_coefficients, num_coefficients
+        ))
+        .into());
+    };
 
     let mut corrected_output =
-        Image::new_with_bands(img.width, img.height, img.num_bands(), img.get_mode()).unwrap();
-
-    let middle_x = img.width / 2;
-    let middle_y = img.height / 2;
-    if middle_x + radius_pixels > img.width {
-        error!(
-            "ERROR: Radius {} exceeds image bounds. {}",
-            radius_pixels, img.width
-        );
-        return Err(anyhow!("Radius exceeds image bounds"));
-    }
-
-    let mid_vec = Vector::new(middle_x as f64, middle_y as f64, 0.0);
-    let a = radius_pixels as f64;
-
-    let mut center_intensities = [0.0, 0.0, 0.0];
-    let mut coefficients = if ld_coefficients.len() == 1 {
-        let mut ld_coefficients_tmp = ld_coefficients.to_owned();
-        while ld_coefficients_tmp.len() < img.num_bands() {
-            ld_coefficients_tmp.push(ld_coefficients[0]);
-        }
-        ld_coefficients_tmp
-    } else {
-        ld_coefficients.to_owned()
+        Image::new_with_bands(img.width, img.height, img.num_bands(), img.get_mode())?;
+
+    let ((center_x, center_y), radius_pixels) = match (center, radius_pixels) {
+        (Some(c), Some(r)) => (c, r),
+        (center, radius_pixels) => match detect_disk(img, interpolation) {
+            Ok(disk) => (
+                center.unwrap_or((disk.x, disk.y)),
+                radius_pixels.unwrap_or(disk.radius),
+            ),
+            Err(why) => match radius_pixels {
+                Some(r) => {
+                    warn!(
+                        "Disk detection failed, assuming the disk is centered: {}",
+                        why
+                    );
+                    (
+                        center.unwrap_or(((img.width / 2) as f64, (img.height / 2) as f64)),
+                        r,
+                    )
+                }
+                None => return Err(why),
+            },
+        },
     };
+    info!(
+        "Correcting disk centered at {:.2}, {:.2} with radius {:.2}",
+        center_x, center_y, radius_pixels
+    );
 
-    // Pixel value at the center
-    // Taking the average of intensities out to radius 'n' to
-    // compensate for filaments or spicules
-    let center_value_radius = 10;
-
-    //for band in 0..img.num_bands() {
-    (0..img.num_bands()).for_each(|band| {
-        for r in 0..center_value_radius {
-            center_intensities[band] += img.avg_value_at_radius(r as f64, band) as f64;
-        }
-        center_intensities[band] /= center_value_radius as f64;
-        info!(
-            "Center intensity for band #{}: {}",
-            band, center_intensities[band]
-        );
-
-        let limb_intensity = img.avg_value_at_radius(radius_pixels as f64, band) as Dn;
-        info!("Limb intensity for band #{}: {}", band, limb_intensity);
-        info!(
-            "Computed limb darkening coefficient: {}",
-            ((center_intensities[band] as Dn - limb_intensity) / center_intensities[band] as Dn)
-        );
-
-        if coefficients[band] == 0.0 {
-            coefficients[band] = ((center_intensities[band] as Dn - limb_intensity)
-                / center_intensities[band] as Dn) as f64;
-        }
-    });
+    let center_vec = Vector::new(center_x, center_y, 0.0);
+    let a = radius_pixels;
+
+    let radial_profile = RadialProfile::compute(
+        img,
+        (center_x, center_y),
+        Some((radius_pixels * PROFILE_FIT_EXTENT).floor()),
+        1.0,
+    )?;
+    let fits = (0..img.num_bands())
+        .map(|band| {
+            let profile = mu_profile(&radial_profile, radius_pixels, band);
+            let fit = match &fixed_coefficients {
+                Some(c) => limbdarkening::fit_center_intensity(model, c[band], &profile)?,
+                None => limbdarkening::fit_limb_darkening(model, &profile)?,
+            };
+            info!("Limb darkening of band #{}: {}", band, fit);
+            Ok(fit)
+        })
+        .collect::<Result<Vec<LimbDarkeningFit>>>()?;
 
     let (_, data_max) = img.get_min_max_all_channel();
 
@@ -211,17 +196,15 @@ pub fn limb_darkening_correction_on_image(
         let p = Vector::new(x as f64, y as f64, 0.0);
 
         // Radial distance from the center of the disc at the pixel
-        let r = mid_vec.distance_to(&p);
+        let r = center_vec.distance_to(&p);
 
         // Observed value of the pixel
         let i = img.get_band(band).get(x, y);
 
-        let model_intensity = center_intensities[band]
-            * (1.0 - coefficients[band] * (1.0 - ((a * a - r * r) / (a * a)).sqrt()));
-        let corrected_u = (center_intensities[band] - model_intensity) / center_intensities[band];
-        let corrected = center_intensities[band]
-            * (corrected_u * (1.0 - ((a * a - r * r) / (a * a)).sqrt()))
-            + i as f64;
+        // The modeled darkening, relative to the center, at the pixel
+        let mu = (1.0 - (r * r) / (a * a)).max(0.0).sqrt();
+        let fit = &fits[band];
+        let corrected = i as f64 + fit.center_intensity - fit.intensity(mu);
 
         let corrected_adjusted = if inverted_chromosphere {
             data_max - corrected as Dn
@@ -229,21 +212,23 @@ pub fn limb_darkening_correction_on_image(
             corrected as Dn
         };
 
-        let final_value = if r > radius_pixels as f64 {
+        let final_value = if r > radius_pixels {
             // If the pixel is outside of the solar radius, we just use
             // the uncorrected pixel value
             i as Dn
-        } else if radius_pixels as f64 - r <= composite_gradient_margin {
+        } else if radius_pixels - r <= composite_gradient_margin {
             // A linear-interpolated gradiant transition
-            let frac = ((radius_pixels as Dn - r as Dn) / composite_gradient_margin as Dn) as Dn;
+            let frac = ((radius_pixels - r) / composite_gradient_margin) as Dn;
             corrected_adjusted as Dn * frac + (1.0 - frac) * i
         } else {
-            // Using the same coefficient for multiple wavelengths is incorrect.
             corrected_adjusted as Dn
         };
 
         corrected_output.put(x, y, final_value, band);
     });
 
-    Ok(corrected_output)
+    Ok(LimbDarkeningCorrection {
+        image: corrected_output,
+        fits,
+    })
 }
@@ -3,28 +3,49 @@ extern crate itertools;
 #[macro_use]
 extern crate stump;
 
+pub mod alignmentpoints;
 pub mod anaysis;
+pub mod avi;
 pub mod calibrationframe;
 pub mod centerofmass;
 pub mod context;
+pub mod coverage;
 pub mod datasource;
+pub mod demosaic;
+pub mod disk;
 pub mod drizzle;
+pub mod error;
+pub mod extract;
+pub mod fits;
 pub mod fpmap;
+pub mod framecache;
 pub mod framerecord;
 pub mod hotpixel;
+pub mod imagesequence;
+pub mod interpolation;
 pub mod ldcorrect;
+pub mod limbdarkening;
 pub mod limiting;
 pub mod lunar;
 pub mod mean;
 pub mod median;
+pub mod multisource;
 pub mod offsetting;
+pub mod output;
 pub mod parallacticangle;
+pub mod phasecorrelation;
 pub mod point;
+pub mod provenance;
+pub mod radialprofile;
 pub mod rotation;
 pub mod ser;
+pub mod serwriter;
+pub mod sharpen;
 pub mod solar;
 pub mod stacking;
 pub mod stats;
 pub mod target;
 pub mod threshtest;
+pub mod tiff;
 pub mod timestamp;
+pub mod weighting;
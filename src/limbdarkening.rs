@@ -0,0 +1,260 @@
+use std::fmt;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::error::SolHatError;
+
+/// Laws of the intensity across the disk relative to its center, as a function of mu, the
+/// cosine of the angle between the line of sight and the surface normal
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum LimbDarkeningModel {
+    #[default]
+    Linear, // 1 - u(1 - mu)
+    Quadratic,   // 1 - a(1 - mu) - b(1 - mu)^2
+    SquareRoot,  // 1 - c(1 - mu) - d(1 - sqrt(mu))
+    Logarithmic, // 1 - e(1 - mu) - f mu ln(mu)
+    NeckelLabs,  // Fifth degree polynomial in mu (Neckel & Labs, 1994)
+}
+
+impl LimbDarkeningModel {
+    pub fn from(s: &str) -> Result<LimbDarkeningModel> {
+        match s.to_uppercase().as_str() {
+            "LINEAR" => Ok(LimbDarkeningModel::Linear),
+            "QUADRATIC" => Ok(LimbDarkeningModel::Quadratic),
+            "SQRT" | "SQUAREROOT" => Ok(LimbDarkeningModel::SquareRoot),
+            "LOG" | "LOGARITHMIC" => Ok(LimbDarkeningModel::Logarithmic),
+            "NECKEL" | "NECKELLABS" => Ok(LimbDarkeningModel::NeckelLabs),
+            _ => Err(SolHatError::InvalidParameter(format!(
+                "Invalid limb darkening model: {}. Valid options: linear, quadratic, sqrt, log, neckel",
+                s
+            ))
+            .into()),
+        }
+    }
+
+    pub fn num_coefficients(self) -> usize {
+        match self {
+            LimbDarkeningModel::Linear => 1,
+            LimbDarkeningModel::Quadratic
+            | LimbDarkeningModel::SquareRoot
+            | LimbDarkeningModel::Logarithmic => 2,
+            LimbDarkeningModel::NeckelLabs => 6,
+        }
+    }
+
+    /// The terms subtracted from unity, each scaled by its coefficient. The Neckel-Labs
+    /// polynomial is instead the sum of its terms.
+    fn terms(self, mu: f64) -> Vec<f64> {
+        let mu = mu.clamp(0.0, 1.0);
+        let mu_ln_mu = if mu > 0.0 { mu * mu.ln() } else { 0.0 };
+        match self {
+            LimbDarkeningModel::Linear => vec![1.0 - mu],
+            LimbDarkeningModel::Quadratic => vec![1.0 - mu, (1.0 - mu).powi(2)],
+            LimbDarkeningModel::SquareRoot => vec![1.0 - mu, 1.0 - mu.sqrt()],
+            LimbDarkeningModel::Logarithmic => vec![1.0 - mu, mu_ln_mu],
+            LimbDarkeningModel::NeckelLabs => (0..6).map(|k| mu.powi(k)).collect(),
+        }
+    }
+
+    /// Intensity at mu relative to the disk center for the given coefficients
+    pub fn relative_intensity(self, coefficients: &[f64], mu: f64) -> f64 {
+        let terms = self.terms(mu);
+        let sum: f64 = terms.iter().zip(coefficients).map(|(t, c)| t * c).sum();
+        match self {
+            LimbDarkeningModel::NeckelLabs => sum,
+            _ => 1.0 - sum,
+        }
+    }
+}
+
+impl fmt::Display for LimbDarkeningModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LimbDarkeningModel::Linear => "linear",
+            LimbDarkeningModel::Quadratic => "quadratic",
+            LimbDarkeningModel::SquareRoot => "square root",
+            LimbDarkeningModel::Logarithmic => "logarithmic",
+            LimbDarkeningModel::NeckelLabs => "Neckel-Labs",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A limb darkening model fitted to a measured radial profile
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LimbDarkeningFit {
+    pub model: LimbDarkeningModel,
+    pub center_intensity: f64,
+    pub coefficients: Vec<f64>,
+    pub rms: f64, // Root mean square residual of the profile from the fitted model
+}
+
+impl LimbDarkeningFit {
+    /// Modeled intensity at mu
+    pub fn intensity(&self, mu: f64) -> f64 {
+        self.center_intensity * self.model.relative_intensity(&self.coefficients, mu)
+    }
+}
+
+impl fmt::Display for LimbDarkeningFit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} model, center intensity {:.2}, coefficients [{}], rms residual {:.2}",
+            self.model,
+            self.center_intensity,
+            self.coefficients
+                .iter()
+                .map(|c| format!("{:.5}", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            self.rms
+        )
+    }
+}
+
+/// Solves the linear least squares problem of the rows against the values by its normal
+/// equations, with Gaussian elimination and partial pivoting
+fn solve_least_squares(rows: &[Vec<f64>], values: &[f64]) -> Option<Vec<f64>> {
+    let n = rows.first()?.len();
+    let mut m = vec![vec![0.0; n + 1]; n];
+    for (row, v) in rows.iter().zip(values) {
+        for a in 0..n {
+            for b in 0..n {
+                m[a][b] += row[a] * row[b];
+            }
+            m[a][n] += row[a] * v;
+        }
+    }
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|a, b| m[*a][col].abs().total_cmp(&m[*b][col].abs()))?;
+        if m[pivot][col].abs() < 1.0e-12 || m[pivot][col].is_nan() {
+            return None;
+        }
+        m.swap(col, pivot);
+        for r in 0..n {
+            if r != col {
+                let f = m[r][col] / m[col][col];
+                for c in col..=n {
+                    m[r][c] -= f * m[col][c];
+                }
+            }
+        }
+    }
+    Some((0..n).map(|i| m[i][n] / m[i][i]).collect())
+}
+
+fn rms_residual(fit: &LimbDarkeningFit, profile: &[(f64, f64)]) -> f64 {
+    (profile
+        .iter()
+        .map(|(mu, v)| (v - fit.intensity(*mu)).powi(2))
+        .sum::<f64>()
+        / profile.len() as f64)
+        .sqrt()
+}
+
+/// Fits the model, center intensity and coefficients both, by least squares to the profile of
+/// (mu, intensity) samples
+pub fn fit_limb_darkening(
+    model: LimbDarkeningModel,
+    profile: &[(f64, f64)],
+) -> Result<LimbDarkeningFit> {
+    if profile.len() <= model.num_coefficients() + 1 {
+        return Err(SolHatError::FitFailed(format!(
+            "Too few profile samples ({}) to fit the {} limb darkening model",
+            profile.len(),
+            model
+        ))
+        .into());
+    }
+
+    if profile
+        .iter()
+        .any(|(mu, v)| !mu.is_finite() || !v.is_finite())
+    {
+        return Err(
+            SolHatError::Format("Limb darkening profile has non-finite samples".into()).into(),
+        );
+    }
+
+    // Intensity is linear in the center intensity and its products with the coefficients
+    let rows: Vec<Vec<f64>> = profile
+        .iter()
+        .map(|(mu, _)| match model {
+            LimbDarkeningModel::NeckelLabs => model.terms(*mu),
+            _ => std::iter::once(1.0)
+                .chain(model.terms(*mu).iter().map(|t| -t))
+                .collect(),
+        })
+        .collect();
+    let values: Vec<f64> = profile.iter().map(|(_, v)| *v).collect();
+    let solution = solve_least_squares(&rows, &values).ok_or_else(|| {
+        SolHatError::FitFailed(format!(
+            "Limb darkening fit of the {} model is singular",
+            model
+        ))
+    })?;
+
+    let center_intensity = match model {
+        LimbDarkeningModel::NeckelLabs => solution.iter().sum(),
+        _ => solution[0],
+    };
+    if center_intensity <= 0.0 {
+        return Err(SolHatError::FitFailed(format!(
+            "Fitted center intensity of {} is not positive",
+            center_intensity
+        ))
+        .into());
+    }
+    let coefficients = match model {
+        LimbDarkeningModel::NeckelLabs => solution.iter().map(|c| c / center_intensity).collect(),
+        _ => solution[1..].iter().map(|c| c / center_intensity).collect(),
+    };
+
+    let mut fit = LimbDarkeningFit {
+        model,
+        center_intensity,
+        coefficients,
+        rms: 0.0,
+    };
+    fit.rms = rms_residual(&fit, profile);
+    Ok(fit)
+}
+
+/// Fits only the center intensity to the profile, holding the coefficients fixed
+pub fn fit_center_intensity(
+    model: LimbDarkeningModel,
+    coefficients: &[f64],
+    profile: &[(f64, f64)],
+) -> Result<LimbDarkeningFit> {
+    if coefficients.len() != model.num_coefficients() {
+        return Err(SolHatError::InvalidParameter(format!(
+            "The {} limb darkening model takes {} coefficients, {} given",
+            model,
+            model.num_coefficients(),
+            coefficients.len()
+        ))
+        .into());
+    }
+    let (ttl_vf, ttl_ff) = profile.iter().fold((0.0, 0.0), |(vf, ff), (mu, v)| {
+        let f = model.relative_intensity(coefficients, *mu);
+        (vf + v * f, ff + f * f)
+    });
+    if ttl_ff <= 0.0 {
+        return Err(SolHatError::FitFailed(
+            "No profile samples to fit the center intensity to".to_string(),
+        )
+        .into());
+    }
+
+    let mut fit = LimbDarkeningFit {
+        model,
+        center_intensity: ttl_vf / ttl_ff,
+        coefficients: coefficients.to_vec(),
+        rms: 0.0,
+    };
+    fit.rms = rms_residual(&fit, profile);
+    Ok(fit)
+}
@@ -1,38 +1,47 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
 use anyhow::Result;
 
 use crate::context::ProcessContext;
 use crate::datasource::DataSource;
 use crate::framerecord::FrameRecord;
+use crate::stats::DiscardReason;
 
 pub fn frame_limit_determinate<C, F>(
-    context: &ProcessContext<F>,
+    context: &mut ProcessContext<F>,
     on_frame_checked: C,
 ) -> Result<Vec<FrameRecord>>
 where
     C: Fn(&FrameRecord) + Send + Sync + 'static,
     F: DataSource,
 {
-    let mut frame_records: Vec<FrameRecord> = context
-        .frame_records
-        .iter()
-        .filter(|fr| {
-            let mn_s = if let Some(minsigma) = &context.parameters.min_sigma {
-                fr.sigma >= *minsigma
-            } else {
-                true
-            };
-            let mx_s = if let Some(maxsigma) = &context.parameters.max_sigma {
-                fr.sigma <= *maxsigma
-            } else {
-                true
-            };
-
-            on_frame_checked(fr);
-
-            mn_s && mx_s
-        })
-        .map(|fr| fr.to_owned())
-        .collect();
+    let t = Instant::now();
+    let mut discarded: Vec<(FrameRecord, DiscardReason)> = vec![];
+
+    let mut frame_records: Vec<FrameRecord> = vec![];
+    context.frame_records.iter().for_each(|fr| {
+        let mn_s = if let Some(minsigma) = &context.parameters.min_sigma {
+            fr.sigma >= *minsigma
+        } else {
+            true
+        };
+        let mx_s = if let Some(maxsigma) = &context.parameters.max_sigma {
+            fr.sigma <= *maxsigma
+        } else {
+            true
+        };
+
+        on_frame_checked(fr);
+
+        if !mn_s {
+            discarded.push((fr.to_owned(), DiscardReason::MinSigma));
+        } else if !mx_s {
+            discarded.push((fr.to_owned(), DiscardReason::MaxSigma));
+        } else {
+            frame_records.push(fr.to_owned());
+        }
+    });
 
     frame_records.sort();
     frame_records.reverse();
@@ -40,6 +49,9 @@ where
     let frame_records = if let Some(limit_top_pct) = &context.parameters.top_percentage {
         let max_frame =
             ((*limit_top_pct as f32 / 100.0) * frame_records.len() as f32).round() as usize;
+        frame_records[max_frame..]
+            .iter()
+            .for_each(|fr| discarded.push((fr.to_owned(), DiscardReason::TopPercentage)));
         frame_records[0..max_frame].to_vec()
     } else {
         frame_records
@@ -51,6 +63,9 @@ where
     // ser files as inputs, but what's some technical debt between friends, amiright?
     let frame_records = if let Some(max_frames) = &context.parameters.max_frames {
         if *max_frames <= frame_records.len() {
+            frame_records[*max_frames..]
+                .iter()
+                .for_each(|fr| discarded.push((fr.to_owned(), DiscardReason::MaxFrames)));
             frame_records[0..*max_frames].to_vec()
         } else {
             frame_records
@@ -59,5 +74,52 @@ where
         frame_records
     };
 
+    let stats = &mut context.stats;
+    stats.num_frames_used = frame_records.len();
+    stats.num_frames_discarded = discarded.len();
+    stats.update_frames(&frame_records, |fs, _| {
+        fs.used = true;
+        fs.discard_reason = None;
+    });
+
+    [
+        DiscardReason::MinSigma,
+        DiscardReason::MaxSigma,
+        DiscardReason::TopPercentage,
+        DiscardReason::MaxFrames,
+    ]
+    .iter()
+    .for_each(|reason| {
+        let records: Vec<FrameRecord> = discarded
+            .iter()
+            .filter(|(_, r)| r == reason)
+            .map(|(fr, _)| fr.to_owned())
+            .collect();
+
+        match reason {
+            DiscardReason::MinSigma => stats.num_frames_discarded_min_sigma = records.len(),
+            DiscardReason::MaxSigma => stats.num_frames_discarded_max_sigma = records.len(),
+            DiscardReason::TopPercentage => {
+                stats.num_frames_discarded_top_percentage = records.len()
+            }
+            DiscardReason::MaxFrames => stats.num_frames_discarded_max_frames = records.len(),
+        }
+
+        stats.update_frames(&records, |fs, _| {
+            fs.used = false;
+            fs.discard_reason = Some(*reason);
+        });
+    });
+    stats.record_stage_timing("limiting", t.elapsed());
+
+    // Frees the cached discarded frames for the highest quality frames read by later stages
+    let kept: HashSet<(String, usize)> = frame_records
+        .iter()
+        .map(|fr| (fr.source_file_id.clone(), fr.frame_id))
+        .collect();
+    context
+        .lock_frame_cache()
+        .retain(|source_file_id, frame_id| kept.contains(&(source_file_id.to_owned(), frame_id)));
+
     Ok(frame_records)
 }
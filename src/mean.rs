@@ -1,26 +1,27 @@
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use rayon::prelude::*;
 use sciimg::{enums::ImageMode, image};
 
-use crate::datasource::{ColorFormatId, DataSource};
+use crate::datasource::DataSource;
+use crate::error::SolHatError;
 
 pub fn build_mean_buffer<F: DataSource + Send + Sync + 'static>(
     ser_file: &F,
 ) -> Result<image::Image> {
-    let num_bands = match ser_file.color_id() {
-        ColorFormatId::Mono => 1,
-        _ => 3,
-    };
+    // The shape of the frames depends on the demosaic method, so take it from the first frame
+    // rather than the header
+    let first_frame = ser_file.get_frame(0)?;
 
     image::Image::new_with_bands(
-        ser_file.image_width(),
-        ser_file.image_height(),
-        num_bands,
-        match ser_file.pixel_depth() {
-            8 => ImageMode::U8BIT,
-            _ => ImageMode::U16BIT,
+        first_frame.buffer.width,
+        first_frame.buffer.height,
+        first_frame.buffer.num_bands(),
+        if ser_file.pixel_depth() <= 8 {
+            ImageMode::U8BIT
+        } else {
+            ImageMode::U16BIT
         },
     )
 }
@@ -30,22 +31,32 @@ pub fn compute_mean<F: DataSource + Send + Sync + 'static>(
     ser_file: &F,
     _skip_glitch_frames: bool,
 ) -> Result<image::Image> {
-    let mut mean_buffer = build_mean_buffer(ser_file).unwrap();
+    if ser_file.frame_count() == 0 {
+        return Err(SolHatError::EmptyStack.into());
+    }
+
+    let mut mean_buffer = build_mean_buffer(ser_file)?;
     let buffer_mtx = Arc::new(Mutex::new(&mut mean_buffer));
 
     let cnt_mtx = Arc::new(Mutex::new(0));
 
-    (0..ser_file.frame_count()).into_par_iter().for_each(|i| {
-        let frame = ser_file.get_frame(i).expect("Failed to load image frame");
-        // TODO: Add glitch frame detection
+    (0..ser_file.frame_count())
+        .into_par_iter()
+        .try_for_each(|i| -> Result<()> {
+            let frame = ser_file.get_frame(i)?;
+            // TODO: Add glitch frame detection
 
-        buffer_mtx.lock().unwrap().add(&frame.buffer);
+            buffer_mtx
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .add(&frame.buffer);
 
-        let mut count = cnt_mtx.lock().unwrap();
-        *count += 1;
-    });
+            let mut count = cnt_mtx.lock().unwrap_or_else(|e| e.into_inner());
+            *count += 1;
+            Ok(())
+        })?;
 
-    let cnt = cnt_mtx.lock().unwrap();
+    let cnt = cnt_mtx.lock().unwrap_or_else(|e| e.into_inner());
 
     if *cnt > 0 {
         for band in 0..mean_buffer.num_bands() {
@@ -60,7 +71,6 @@ pub fn compute_mean<F: DataSource + Send + Sync + 'static>(
         );
         Ok(mean_buffer)
     } else {
-        eprintln!("No files used");
-        Err(anyhow!("No files used"))
+        Err(SolHatError::EmptyStack.into())
     }
 }
@@ -1,8 +1,8 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use sciimg::prelude::*;
 
-use crate::datasource::ColorFormatId;
 use crate::datasource::DataSource;
+use crate::error::SolHatError;
 
 #[derive(Debug, Default)]
 struct MedianBuffer {
@@ -20,11 +20,12 @@ impl MedianBuffer {
     }
     pub fn add_buffer(&mut self, other: &[f32]) -> Result<()> {
         if other.len() != self.buffer.len() {
-            Err(anyhow!(
+            Err(SolHatError::IncompatibleInputs(format!(
                 "Other buffer is of a non-matching length: {} != {}",
                 other.len(),
                 self.buffer.len()
             ))
+            .into())
         } else {
             (0..self.buffer.len()).for_each(|i| {
                 self.buffer[i].push(other[i]);
@@ -32,59 +33,52 @@ impl MedianBuffer {
             Ok(())
         }
     }
-    pub fn get_median_vector(&mut self) -> Vec<f32> {
+    /// Median of each pixel, leaving out samples which aren't a number
+    pub fn get_median_vector(&mut self) -> Result<Vec<f32>> {
         let mut v: Vec<f32> = Vec::with_capacity(self.buffer.len());
-        (0..self.buffer.len()).for_each(|i| {
-            self.buffer[i].sort_by(|a, b| a.partial_cmp(b).unwrap());
-            let mv = self.buffer[i][self.buffer[i].len() / 2];
-            v.push(mv);
-        });
-        v
+        for (i, samples) in self.buffer.iter_mut().enumerate() {
+            samples.retain(|s| !s.is_nan());
+            if samples.is_empty() {
+                return Err(
+                    SolHatError::Format(format!("No valid samples for pixel {}", i)).into(),
+                );
+            }
+            samples.sort_by(|a, b| a.total_cmp(b));
+            v.push(samples[samples.len() / 2]);
+        }
+        Ok(v)
     }
 }
 
 pub fn compute_mean<F: DataSource>(ser_file: &F) -> Result<Image> {
-    let mut median_buffers = match ser_file.color_id() {
-        ColorFormatId::Mono => vec![MedianBuffer::new(
-            ser_file.image_width() * ser_file.image_height(),
-        )],
-        _ => vec![
-            MedianBuffer::new(ser_file.image_width() * ser_file.image_height()),
-            MedianBuffer::new(ser_file.image_width() * ser_file.image_height()),
-            MedianBuffer::new(ser_file.image_width() * ser_file.image_height()),
-        ],
-    };
+    if ser_file.frame_count() == 0 {
+        return Err(SolHatError::EmptyStack.into());
+    }
+
+    // The shape of the frames depends on the demosaic method, so take it from the first frame
+    // rather than the header
+    let first_frame = ser_file.get_frame(0)?;
+    let width = first_frame.buffer.width;
+    let height = first_frame.buffer.height;
+
+    let mut median_buffers = (0..first_frame.buffer.num_bands())
+        .map(|_| MedianBuffer::new(width * height))
+        .collect::<Vec<MedianBuffer>>();
+
+    for f in 0..ser_file.frame_count() {
+        let frame = ser_file.get_frame(f)?;
+        for (b, mb) in median_buffers.iter_mut().enumerate() {
+            mb.add_buffer(&frame.buffer.get_band(b).buffer.to_vector())?;
+        }
+    }
 
-    iproduct!(0..ser_file.frame_count(), 0..median_buffers.len()).for_each(|(f, b)| {
-        let frame = ser_file.get_frame(f).expect("Failed to load image frame");
-        median_buffers[b]
-            .add_buffer(&frame.buffer.get_band(0).buffer.to_vector())
-            .expect("Failed to add band buffer to median");
-    });
+    let bands = median_buffers
+        .iter_mut()
+        .map(|mb| ImageBuffer::from_vec(&mb.get_median_vector()?, width, height))
+        .collect::<Result<Vec<ImageBuffer>>>()?;
 
-    Ok(match ser_file.color_id() {
-        ColorFormatId::Mono => Image::new_from_buffer_mono(&ImageBuffer::from_vec(
-            &median_buffers[0].get_median_vector(),
-            ser_file.image_width(),
-            ser_file.image_height(),
-        )?)?,
-        _ => Image::new_from_buffers_rgb(
-            &ImageBuffer::from_vec(
-                &median_buffers[0].get_median_vector(),
-                ser_file.image_width(),
-                ser_file.image_height(),
-            )?,
-            &ImageBuffer::from_vec(
-                &median_buffers[0].get_median_vector(),
-                ser_file.image_width(),
-                ser_file.image_height(),
-            )?,
-            &ImageBuffer::from_vec(
-                &median_buffers[0].get_median_vector(),
-                ser_file.image_width(),
-                ser_file.image_height(),
-            )?,
-            ImageMode::U16BIT,
-        )?,
+    Ok(match bands.len() {
+        1 => Image::new_from_buffer_mono(&bands[0])?,
+        _ => Image::new_from_buffers_rgb(&bands[0], &bands[1], &bands[2], ImageMode::U16BIT)?,
     })
 }
@@ -0,0 +1,142 @@
+use anyhow::Result;
+
+use crate::datasource::{self, ColorFormatId, DataFrame, DataSource, ImageDataSource, PixelFormat};
+use crate::demosaic::DemosaicMethod;
+use crate::error::SolHatError;
+use crate::timestamp::TimeStamp;
+
+/// Joins several inputs, possibly of different formats, into a single source with the frames
+/// of each following those of the previous.
+pub struct MultiSource {
+    sources: Vec<Box<ImageDataSource>>,
+}
+
+impl MultiSource {
+    pub fn load_multi(paths: &[String]) -> Result<MultiSource> {
+        if paths.is_empty() {
+            return Err(
+                SolHatError::InvalidParameter("No input files specified".to_string()).into(),
+            );
+        }
+
+        let sources = paths
+            .iter()
+            .map(|p| datasource::open_data_source(&[p.to_owned()]))
+            .collect::<Result<Vec<Box<ImageDataSource>>>>()?;
+
+        Ok(MultiSource { sources })
+    }
+
+    /// The source holding the frame and the frame number within it
+    fn locate(&self, frame_num: usize) -> Result<(&ImageDataSource, usize)> {
+        let mut remaining = frame_num;
+        for source in self.sources.iter() {
+            if remaining < source.frame_count() {
+                return Ok((source.as_ref(), remaining));
+            }
+            remaining -= source.frame_count();
+        }
+        Err(SolHatError::FrameOutOfRange {
+            frame: frame_num,
+            frame_count: self.frame_count(),
+        }
+        .into())
+    }
+}
+
+impl DataSource for MultiSource {
+    fn color_id(&self) -> ColorFormatId {
+        self.sources[0].color_id()
+    }
+
+    fn file_id(&self) -> String {
+        "MULTI".to_string()
+    }
+
+    fn image_width(&self) -> usize {
+        self.sources[0].image_width()
+    }
+
+    fn image_height(&self) -> usize {
+        self.sources[0].image_height()
+    }
+
+    fn pixel_depth(&self) -> usize {
+        self.sources[0].pixel_depth()
+    }
+
+    fn frame_count(&self) -> usize {
+        self.sources.iter().map(|s| s.frame_count()).sum()
+    }
+
+    fn observer(&self) -> String {
+        self.sources[0].observer()
+    }
+
+    fn instrument(&self) -> String {
+        self.sources[0].instrument()
+    }
+
+    fn telescope(&self) -> String {
+        self.sources[0].telescope()
+    }
+
+    fn date_time(&self) -> TimeStamp {
+        self.sources[0].date_time()
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        self.sources[0].date_time_utc()
+    }
+
+    fn total_file_size(&self) -> usize {
+        self.sources.iter().map(|s| s.total_file_size()).sum()
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        let (source, n) = self.locate(frame_num)?;
+        source.get_frame(n)
+    }
+
+    fn get_frame_timestamp(&self, frame_num: usize) -> Result<TimeStamp> {
+        let (source, n) = self.locate(frame_num)?;
+        source.get_frame_timestamp(n)
+    }
+
+    fn source_file(&self) -> String {
+        self.sources[0].source_file()
+    }
+
+    fn open(path: &[String]) -> Result<Self> {
+        MultiSource::load_multi(path)
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.sources.iter().try_for_each(|s| s.validate())?;
+        datasource::check_compatible(self.sources.iter())
+    }
+
+    fn set_demosaic_method(&mut self, method: DemosaicMethod) {
+        self.sources
+            .iter_mut()
+            .for_each(|s| s.set_demosaic_method(method));
+    }
+
+    fn set_pixel_format(&mut self, pixel_format: &PixelFormat) -> Result<()> {
+        self.sources
+            .iter_mut()
+            .try_for_each(|s| s.set_pixel_format(pixel_format))
+    }
+
+    fn print_header_details(&self) {
+        self.sources.iter().for_each(|s| s.print_header_details());
+    }
+
+    fn file_hash(&self) -> String {
+        self.sources
+            .iter()
+            .map(|s| s.file_hash())
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+}
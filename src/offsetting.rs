@@ -1,11 +1,68 @@
+use std::time::Instant;
+
 use anyhow::Result;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::context::ProcessContext;
 use crate::datasource::DataSource;
+use crate::disk::fit_disk;
+use crate::error::SolHatError;
 use crate::framerecord::FrameRecord;
+use crate::phasecorrelation::PhaseCorrelator;
+
+/// Supported methods of determining the per-frame registration offset
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum RegistrationMethod {
+    #[default]
+    CenterOfMass,
+    PhaseCorrelation,
+    DiskFit, // Center of a circle fitted to the limb
+}
+
+impl RegistrationMethod {
+    pub fn from(s: &str) -> Result<RegistrationMethod> {
+        match s.to_uppercase().as_str() {
+            "COM" | "CENTEROFMASS" => Ok(RegistrationMethod::CenterOfMass),
+            "PHASE" | "PHASECORRELATION" => Ok(RegistrationMethod::PhaseCorrelation),
+            "DISK" | "DISKFIT" => Ok(RegistrationMethod::DiskFit),
+            _ => Err(SolHatError::InvalidParameter(format!(
+                "Invalid registration method: {}. Valid options: com, phase, disk",
+                s
+            ))
+            .into()),
+        }
+    }
+}
 
 pub fn frame_offset_analysis<C, F>(
+    context: &mut ProcessContext<F>,
+    on_frame_checked: C,
+) -> Result<Vec<FrameRecord>>
+where
+    C: Fn(&FrameRecord) + Send + Sync + 'static,
+    F: DataSource + Send + Sync + 'static,
+{
+    let t = Instant::now();
+    let frame_records = match context.parameters.registration {
+        RegistrationMethod::CenterOfMass => {
+            frame_center_of_mass_offset_analysis(context, on_frame_checked)?
+        }
+        RegistrationMethod::PhaseCorrelation => {
+            frame_phase_correlation_offset_analysis(context, on_frame_checked)?
+        }
+        RegistrationMethod::DiskFit => frame_disk_fit_offset_analysis(context, on_frame_checked)?,
+    };
+
+    context.stats.update_frames(&frame_records, |fs, fr| {
+        fs.offset_h = fr.offset.h;
+        fs.offset_v = fr.offset.v;
+    });
+    context.stats.record_stage_timing("offset", t.elapsed());
+    Ok(frame_records)
+}
+
+fn frame_center_of_mass_offset_analysis<C, F>(
     context: &ProcessContext<F>,
     on_frame_checked: C,
 ) -> Result<Vec<FrameRecord>>
@@ -13,12 +70,12 @@ where
     C: Fn(&FrameRecord) + Send + Sync + 'static,
     F: DataSource + Send + Sync + 'static,
 {
-    let frame_records: Vec<FrameRecord> = context
+    context
         .frame_records
         .par_iter()
         .map(|fr| {
             let mut fr_copy = fr.clone();
-            let frame = fr.get_frame(context).expect("");
+            let frame = fr.get_frame(context)?;
 
             fr_copy.offset = frame
                 .buffer
@@ -28,8 +85,77 @@ where
             fr_copy.offset.v += context.parameters.vert_offset as f32;
 
             on_frame_checked(&fr_copy);
-            fr_copy
+            Ok(fr_copy)
         })
-        .collect();
-    Ok(frame_records)
+        .collect()
+}
+
+/// Centers each frame on a circle fitted to the limb of the target, which unlike the center
+/// of mass isn't pulled aside by a disk partially outside of the frame
+fn frame_disk_fit_offset_analysis<C, F>(
+    context: &ProcessContext<F>,
+    on_frame_checked: C,
+) -> Result<Vec<FrameRecord>>
+where
+    C: Fn(&FrameRecord) + Send + Sync + 'static,
+    F: DataSource + Send + Sync + 'static,
+{
+    context
+        .frame_records
+        .par_iter()
+        .map(|fr| {
+            let mut fr_copy = fr.clone();
+            let frame = fr.get_frame(context)?;
+
+            let disk = fit_disk(
+                frame.buffer.get_band(0),
+                Some(context.parameters.obj_detection_threshold as f32),
+                context.parameters.interpolation,
+            )?;
+            fr_copy.offset = disk.offset(frame.buffer.width, frame.buffer.height);
+
+            fr_copy.offset.h += context.parameters.horiz_offset as f32;
+            fr_copy.offset.v += context.parameters.vert_offset as f32;
+
+            on_frame_checked(&fr_copy);
+            Ok(fr_copy)
+        })
+        .collect()
+}
+
+/// Registers each frame against the highest quality frame. Assumes the frame records have
+/// been sorted by descending sigma, as done by frame limiting.
+fn frame_phase_correlation_offset_analysis<C, F>(
+    context: &ProcessContext<F>,
+    on_frame_checked: C,
+) -> Result<Vec<FrameRecord>>
+where
+    C: Fn(&FrameRecord) + Send + Sync + 'static,
+    F: DataSource + Send + Sync + 'static,
+{
+    if context.frame_records.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Calibrated frames are used so that dust and other fixed-pattern features of the optics
+    // don't pull the correlation toward zero shift.
+    let reference = context.frame_records[0].get_calibrated_frame(context)?;
+    let correlator = PhaseCorrelator::new(&reference.buffer);
+
+    context
+        .frame_records
+        .par_iter()
+        .map(|fr| {
+            let mut fr_copy = fr.clone();
+            let frame = fr.get_calibrated_frame(context)?;
+
+            fr_copy.offset = correlator.offset(&frame.buffer)?;
+
+            fr_copy.offset.h += context.parameters.horiz_offset as f32;
+            fr_copy.offset.v += context.parameters.vert_offset as f32;
+
+            on_frame_checked(&fr_copy);
+            Ok(fr_copy)
+        })
+        .collect()
 }
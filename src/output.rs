@@ -0,0 +1,119 @@
+use anyhow::Result;
+use sciimg::image::Image;
+use serde::{Deserialize, Serialize};
+
+use crate::datasource::FITS_EXTENSIONS;
+use crate::error::SolHatError;
+use crate::fits::{self, FitsDataType, FitsKeyword};
+use crate::imagesequence::{has_extension, IMAGE_EXTENSIONS};
+use crate::tiff;
+
+const TIFF_EXTENSIONS: [&str; 2] = ["TIF", "TIFF"];
+
+/// How values of the final stack are mapped to the output image
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum OutputScaling {
+    #[default]
+    Normalize, // Stretched between the stack minimum and maximum to the full 16-bit range
+    Linear,      // Calibrated values are written unchanged, requiring floating point output
+    Factor(f32), // Multiplied by a fixed factor, then clipped to the 16-bit range
+}
+
+impl OutputScaling {
+    pub fn from(s: &str) -> Result<OutputScaling> {
+        match s.to_uppercase().as_str() {
+            "NORMALIZE" => Ok(OutputScaling::Normalize),
+            "LINEAR" => Ok(OutputScaling::Linear),
+            _ => match s.parse::<f32>() {
+                Ok(f) if f > 0.0 => Ok(OutputScaling::Factor(f)),
+                _ => Err(SolHatError::InvalidParameter(format!(
+                    "Invalid output scaling: {}. Valid options: normalize, linear, or a positive factor",
+                    s
+                ))
+                .into()),
+            },
+        }
+    }
+}
+
+/// Applies the output scaling to the stacked image
+pub fn scale_for_output(image: &mut Image, scaling: OutputScaling) {
+    match scaling {
+        OutputScaling::Normalize => {
+            image.normalize_to_16bit();
+        }
+        OutputScaling::Linear => {}
+        OutputScaling::Factor(factor) => {
+            for band in 0..image.num_bands() {
+                image.apply_weight_on_band(factor, band);
+                for y in 0..image.height {
+                    for x in 0..image.width {
+                        let v = image.get_band(band).get(x, y);
+                        image.put(x, y, v.clamp(0.0, 65535.0), band);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether the file format of the output path can hold floating point values
+pub fn is_float_format(output_path: &str) -> bool {
+    has_extension(output_path, &FITS_EXTENSIONS) || has_extension(output_path, &TIFF_EXTENSIONS)
+}
+
+/// Checks that the output can be written with the scaling and FITS data type, so that a bad
+/// combination fails before any frames are stacked. Linear values can only be written to
+/// floating point FITS or TIFF.
+pub fn validate_output(
+    output_path: &str,
+    scaling: OutputScaling,
+    fits_data_type: FitsDataType,
+) -> Result<()> {
+    let linear = scaling == OutputScaling::Linear;
+
+    if has_extension(output_path, &FITS_EXTENSIONS) {
+        if linear && fits_data_type != FitsDataType::Float32 {
+            return Err(SolHatError::InvalidParameter(
+                "Linear output requires the float32 FITS data type".to_string(),
+            )
+            .into());
+        }
+    } else if !has_extension(output_path, &IMAGE_EXTENSIONS) {
+        return Err(SolHatError::UnsupportedFileType(format!(
+            "{}. Valid types: {}, {}",
+            output_path,
+            FITS_EXTENSIONS.join(", "),
+            IMAGE_EXTENSIONS.join(", ")
+        ))
+        .into());
+    } else if linear && !is_float_format(output_path) {
+        return Err(SolHatError::InvalidParameter(format!(
+            "Linear output requires a TIFF or FITS output file, not {}",
+            output_path
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Saves the scaled stack, choosing the writer by the file extension. FITS output carries the
+/// supplied header keywords.
+pub fn save_output(
+    image: &Image,
+    output_path: &str,
+    scaling: OutputScaling,
+    fits_data_type: FitsDataType,
+    fits_keywords: &[FitsKeyword],
+) -> Result<()> {
+    validate_output(output_path, scaling, fits_data_type)?;
+
+    if has_extension(output_path, &FITS_EXTENSIONS) {
+        fits::save_fits(image, output_path, fits_data_type, fits_keywords)
+    } else if scaling == OutputScaling::Linear {
+        tiff::save_float_tiff(image, output_path)
+    } else {
+        image.save(output_path)?;
+        Ok(())
+    }
+}
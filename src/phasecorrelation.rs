@@ -0,0 +1,191 @@
+use anyhow::Result;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use sciimg::imagebuffer::Offset;
+use sciimg::prelude::*;
+
+use crate::error::SolHatError;
+
+/// Registers frames against a fixed reference frame by FFT phase correlation. Unlike
+/// center-of-mass, this doesn't depend on the target's limb being visible and isn't thrown
+/// off by prominences or clouds changing the shape of the bright area.
+pub struct PhaseCorrelator {
+    width: usize,
+    height: usize,
+    reference: Vec<Complex<f64>>,
+}
+
+/// Averages all bands into a single luminance band so mono and RGB frames are handled alike
+fn luminance(image: &Image) -> Vec<f64> {
+    let mut values = vec![0.0; image.width * image.height];
+    (0..image.num_bands()).for_each(|b| {
+        let band = image.get_band(b);
+        iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+            values[y * image.width + x] += band.get(x, y) as f64;
+        });
+    });
+    values
+        .iter_mut()
+        .for_each(|v| *v /= image.num_bands() as f64);
+    values
+}
+
+/// Hann window coefficient, used to suppress the edge discontinuities of the implied periodic
+/// image which would otherwise dominate the correlation
+fn hann(i: usize, n: usize) -> f64 {
+    if n <= 1 {
+        1.0
+    } else {
+        0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (n - 1) as f64).cos()
+    }
+}
+
+fn transpose(data: &[Complex<f64>], width: usize, height: usize) -> Vec<Complex<f64>> {
+    let mut t = vec![Complex::new(0.0, 0.0); data.len()];
+    iproduct!(0..height, 0..width).for_each(|(y, x)| {
+        t[x * height + y] = data[y * width + x];
+    });
+    t
+}
+
+/// In-place two dimensional FFT of a row-major buffer. The inverse is unnormalized.
+pub(crate) fn fft2d(data: &mut [Complex<f64>], width: usize, height: usize, inverse: bool) {
+    let mut planner = FftPlanner::<f64>::new();
+
+    let (row_fft, col_fft) = if inverse {
+        (
+            planner.plan_fft_inverse(width),
+            planner.plan_fft_inverse(height),
+        )
+    } else {
+        (
+            planner.plan_fft_forward(width),
+            planner.plan_fft_forward(height),
+        )
+    };
+
+    row_fft.process(data);
+    let mut t = transpose(data, width, height);
+    col_fft.process(&mut t);
+    data.copy_from_slice(&transpose(&t, height, width));
+}
+
+fn forward_spectrum(image: &Image) -> Vec<Complex<f64>> {
+    let values = luminance(image);
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+
+    let mut data: Vec<Complex<f64>> = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let x = i % image.width;
+            let y = i / image.width;
+            Complex::new(
+                (v - mean) * hann(x, image.width) * hann(y, image.height),
+                0.0,
+            )
+        })
+        .collect();
+
+    fft2d(&mut data, image.width, image.height, false);
+    data
+}
+
+/// Sub-pixel peak position from three samples of the correlation surface
+pub(crate) fn parabolic_peak(cm: f64, c0: f64, cp: f64) -> f64 {
+    let denom = cm - 2.0 * c0 + cp;
+    if denom.abs() < f64::EPSILON {
+        0.0
+    } else {
+        ((cm - cp) / (2.0 * denom)).clamp(-0.5, 0.5)
+    }
+}
+
+impl PhaseCorrelator {
+    pub fn new(reference: &Image) -> Self {
+        PhaseCorrelator {
+            width: reference.width,
+            height: reference.height,
+            reference: forward_spectrum(reference),
+        }
+    }
+
+    /// Computes the displacement of the frame contents relative to the reference, in pixels
+    pub fn displacement(&self, frame: &Image) -> Result<(f64, f64)> {
+        if frame.width != self.width || frame.height != self.height {
+            return Err(SolHatError::IncompatibleInputs(format!(
+                "Frame dimensions {}x{} do not match reference {}x{}",
+                frame.width, frame.height, self.width, self.height
+            ))
+            .into());
+        }
+
+        let spectrum = forward_spectrum(frame);
+
+        // Normalized cross-power spectrum
+        let mut cross: Vec<Complex<f64>> = spectrum
+            .iter()
+            .zip(self.reference.iter())
+            .map(|(f, r)| {
+                let c = f * r.conj();
+                let m = c.norm();
+                if m > 0.0 {
+                    c / m
+                } else {
+                    Complex::new(0.0, 0.0)
+                }
+            })
+            .collect();
+
+        fft2d(&mut cross, self.width, self.height, true);
+
+        let (peak_idx, _) = cross
+            .iter()
+            .enumerate()
+            .fold(
+                (0, f64::MIN),
+                |(bi, bv), (i, v)| {
+                    if v.re > bv {
+                        (i, v.re)
+                    } else {
+                        (bi, bv)
+                    }
+                },
+            );
+
+        let px = peak_idx % self.width;
+        let py = peak_idx / self.width;
+
+        let at = |x: usize, y: usize| cross[y * self.width + x].re;
+        let xm = (px + self.width - 1) % self.width;
+        let xp = (px + 1) % self.width;
+        let ym = (py + self.height - 1) % self.height;
+        let yp = (py + 1) % self.height;
+
+        let sub_x = parabolic_peak(at(xm, py), at(px, py), at(xp, py));
+        let sub_y = parabolic_peak(at(px, ym), at(px, py), at(px, yp));
+
+        // Peaks past the midpoint represent negative shifts
+        let dx = if px > self.width / 2 {
+            px as f64 - self.width as f64
+        } else {
+            px as f64
+        };
+        let dy = if py > self.height / 2 {
+            py as f64 - self.height as f64
+        } else {
+            py as f64
+        };
+
+        Ok((dx + sub_x, dy + sub_y))
+    }
+
+    /// Computes the offset needed to bring the frame into alignment with the reference
+    pub fn offset(&self, frame: &Image) -> Result<Offset> {
+        let (dx, dy) = self.displacement(frame)?;
+        Ok(Offset {
+            h: -dx as f32,
+            v: -dy as f32,
+        })
+    }
+}
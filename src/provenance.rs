@@ -0,0 +1,233 @@
+// FITS header keywords describing how a stack was captured and processed, following the
+// conventions read by SunPy where one exists.
+
+use anyhow::Result;
+use sciimg::image::Image;
+
+use crate::context::ProcessContext;
+use crate::datasource::DataSource;
+use crate::disk::{fit_disk, Disk};
+use crate::fits::{FitsKeyword, FitsValue};
+use crate::solar;
+use crate::target::Target;
+use crate::timestamp::TimeStamp;
+
+fn iso8601(ts: &TimeStamp) -> String {
+    ts.to_chrono_utc()
+        .format("%Y-%m-%dT%H:%M:%S%.3f")
+        .to_string()
+}
+
+fn string_keyword(key: &str, value: &str, comment: &str) -> FitsKeyword {
+    FitsKeyword::new(
+        key,
+        FitsValue::String(value.trim_end_matches('\0').trim().to_string()),
+        comment,
+    )
+}
+
+fn float_keyword(key: &str, value: f64, comment: &str) -> FitsKeyword {
+    FitsKeyword::new(key, FitsValue::Float(value), comment)
+}
+
+fn integer_keyword(key: &str, value: i64, comment: &str) -> FitsKeyword {
+    FitsKeyword::new(key, FitsValue::Integer(value), comment)
+}
+
+/// Builds the keywords describing the stacked image: capture times, observer and equipment,
+/// frame selection, and for the Sun, the disk orientation. The image is expected to be the
+/// final stack, prior to any normalization, so that the detection threshold applies.
+pub fn stack_keywords<F: DataSource>(
+    context: &ProcessContext<F>,
+    image: &Image,
+) -> Result<Vec<FitsKeyword>> {
+    let params = &context.parameters;
+    let mut keywords = vec![];
+
+    // The earliest capture stands for the observation as a whole
+    let source = context
+        .fp_map
+        .get_map()
+        .values()
+        .min_by_key(|s| s.date_time_utc().timestamp);
+
+    // Frames lacking capture times have timestamps of zero
+    let mut frame_times = context
+        .frame_records
+        .iter()
+        .map(|fr| fr.get_timestamp(context).map(|ts| ts.timestamp))
+        .collect::<Result<Vec<u64>>>()?;
+    frame_times.retain(|t| *t > 0);
+
+    let mid_exposure = if frame_times.is_empty() {
+        None
+    } else {
+        let mean = frame_times.iter().map(|t| *t as u128).sum::<u128>() / frame_times.len() as u128;
+        Some(TimeStamp::from_u64(mean as u64))
+    };
+
+    let date_obs = source
+        .map(|s| s.date_time_utc())
+        .filter(|ts| ts.timestamp > 0)
+        .or_else(|| frame_times.iter().min().map(|t| TimeStamp::from_u64(*t)));
+
+    if let Some(ts) = date_obs {
+        keywords.push(string_keyword(
+            "DATE-OBS",
+            &iso8601(&ts),
+            "Start of observation (UTC)",
+        ));
+    }
+    if let (Some(first), Some(last)) = (frame_times.iter().min(), frame_times.iter().max()) {
+        keywords.push(string_keyword(
+            "DATE-BEG",
+            &iso8601(&TimeStamp::from_u64(*first)),
+            "Capture time of first stacked frame (UTC)",
+        ));
+        keywords.push(string_keyword(
+            "DATE-END",
+            &iso8601(&TimeStamp::from_u64(*last)),
+            "Capture time of last stacked frame (UTC)",
+        ));
+    }
+    if let Some(ts) = mid_exposure {
+        keywords.push(string_keyword(
+            "DATE-AVG",
+            &iso8601(&ts),
+            "Mean capture time of stacked frames (UTC)",
+        ));
+    }
+    keywords.push(string_keyword("TIMESYS", "UTC", ""));
+
+    if let Some(s) = source {
+        keywords.push(string_keyword("OBSERVER", &s.observer(), ""));
+        keywords.push(string_keyword("INSTRUME", &s.instrument(), ""));
+        keywords.push(string_keyword("TELESCOP", &s.telescope(), ""));
+    }
+    keywords.push(float_keyword(
+        "OBSGEO-B",
+        params.obs_latitude,
+        "Observer latitude (deg)",
+    ));
+    keywords.push(float_keyword(
+        "OBSGEO-L",
+        params.obs_longitude,
+        "Observer longitude (deg)",
+    ));
+    keywords.push(string_keyword(
+        "OBJECT",
+        &format!("{:?}", params.target),
+        "",
+    ));
+
+    keywords.push(integer_keyword(
+        "NFRAMES",
+        context.stats.total_frames as i64,
+        "Number of frames analyzed",
+    ));
+    keywords.push(integer_keyword(
+        "NCOMBINE",
+        context.frame_records.len() as i64,
+        "Number of frames stacked",
+    ));
+    if let Some(min_sigma) = params.min_sigma {
+        keywords.push(float_keyword("MINSIGMA", min_sigma, "Minimum frame sigma"));
+    }
+    if let Some(max_sigma) = params.max_sigma {
+        keywords.push(float_keyword("MAXSIGMA", max_sigma, "Maximum frame sigma"));
+    }
+    if let Some(top_percentage) = params.top_percentage {
+        keywords.push(float_keyword(
+            "TOPPCT",
+            top_percentage,
+            "Percentage of best frames used",
+        ));
+    }
+    keywords.push(string_keyword(
+        "OUTSCALE",
+        &format!("{:?}", params.output_scaling),
+        "Scaling of values from the stack",
+    ));
+    keywords.push(string_keyword(
+        "STACKALG",
+        &format!("{:?}", params.algorithm),
+        "Stacking algorithm",
+    ));
+    keywords.push(string_keyword(
+        "WEIGHTNG",
+        &format!("{:?}", params.frame_weighting),
+        "Weighting of frames by quality",
+    ));
+    keywords.push(float_keyword(
+        "DRIZZLE",
+        params.drizzle_scale.value() as f64,
+        "Drizzle upscale factor",
+    ));
+    keywords.push(string_keyword(
+        "KERNEL",
+        &format!("{:?}", params.drizzle_kernel),
+        "Drizzle kernel",
+    ));
+    keywords.push(float_keyword(
+        "PIXFRAC",
+        params.drizzle_pixfrac as f64,
+        "Drizzle drop size (input pixels)",
+    ));
+
+    if params.target == Target::Sun {
+        if let Some(ts) = mid_exposure.or(date_obs) {
+            let orientation = solar::orientation_from_julian_day(ts.to_julian_day());
+            keywords.push(float_keyword(
+                "SOLAR_P",
+                orientation.p,
+                "Solar P angle (deg)",
+            ));
+            keywords.push(float_keyword(
+                "SOLAR_B0",
+                orientation.b0,
+                "Solar B0 angle (deg)",
+            ));
+            keywords.push(float_keyword(
+                "SOLAR_L0",
+                orientation.l0,
+                "Carrington longitude L0 (deg)",
+            ));
+            keywords.push(float_keyword(
+                "CRLT_OBS",
+                orientation.b0,
+                "Carrington latitude of observer (deg)",
+            ));
+            keywords.push(float_keyword(
+                "CRLN_OBS",
+                orientation.l0,
+                "Carrington longitude of observer (deg)",
+            ));
+        }
+    }
+
+    if params.target != Target::None {
+        if let Ok(Disk { x, y, radius, .. }) = fit_disk(
+            image.get_band(0),
+            Some(params.obj_detection_threshold as f32),
+            params.interpolation,
+        ) {
+            // FITS pixel coordinates are 1-based and counted from the bottom row. These aren't
+            // written as CRPIX, as the pixel scale and orientation for a full WCS are unknown.
+            keywords.push(float_keyword("DISK_X", x + 1.0, "Disk center X (pixels)"));
+            keywords.push(float_keyword(
+                "DISK_Y",
+                image.height as f64 - y,
+                "Disk center Y (pixels)",
+            ));
+            keywords.push(float_keyword("RSUN_PIX", radius, "Disk radius (pixels)"));
+        }
+    }
+
+    keywords.push(string_keyword(
+        "CREATOR",
+        &format!("solhat {}", env!("CARGO_PKG_VERSION")),
+        "Processing software",
+    ));
+
+    Ok(keywords)
+}
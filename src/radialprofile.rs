@@ -0,0 +1,131 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use serde::Serialize;
+
+use crate::error::SolHatError;
+use crate::imagesequence::has_extension;
+
+/// Statistics of the pixels within one annulus of the profile
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct RadialBin {
+    pub radius: f64, // Middle of the annulus, in pixels from the center
+    pub mean: f64,
+    pub median: f64,
+    pub std_dev: f64,
+    pub count: usize,
+}
+
+/// Pixel statistics in concentric annuli about a center, for each band. Pixels are binned by
+/// their distance from the center, so that parts of the annuli off of the image simply don't
+/// contribute, and annuli without any pixels are left out. Pixels which aren't a number don't
+/// contribute either.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RadialProfile {
+    pub center_x: f64,
+    pub center_y: f64,
+    pub bin_width: f64,
+    pub bands: Vec<Vec<RadialBin>>,
+}
+
+fn bin_statistics(radius: f64, values: &mut [f32]) -> RadialBin {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let n = values.len();
+    let mean = values.iter().map(|v| *v as f64).sum::<f64>() / n as f64;
+    let variance = values
+        .iter()
+        .map(|v| (*v as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n as f64;
+    let median = if n % 2 == 0 {
+        (values[n / 2 - 1] as f64 + values[n / 2] as f64) / 2.0
+    } else {
+        values[n / 2] as f64
+    };
+    RadialBin {
+        radius,
+        mean,
+        median,
+        std_dev: variance.sqrt(),
+        count: n,
+    }
+}
+
+impl RadialProfile {
+    /// Profiles the image about the center out to the maximum radius, or to its farthest
+    /// corner if not given, in annuli of the bin width
+    pub fn compute(
+        image: &Image,
+        center: (f64, f64),
+        max_radius: Option<f64>,
+        bin_width: f64,
+    ) -> Result<RadialProfile> {
+        if bin_width <= 0.0 {
+            return Err(SolHatError::InvalidParameter(format!(
+                "Invalid bin width: {}. Must be positive",
+                bin_width
+            ))
+            .into());
+        }
+        let (cx, cy) = center;
+        let max_radius = max_radius.unwrap_or_else(|| {
+            let (w, h) = (image.width as f64, image.height as f64);
+            [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)]
+                .iter()
+                .map(|(x, y)| ((x - cx).powi(2) + (y - cy).powi(2)).sqrt())
+                .fold(0.0, f64::max)
+        });
+        let num_bins = (max_radius / bin_width).ceil() as usize;
+
+        let bands = (0..image.num_bands())
+            .map(|band| {
+                let buffer = image.get_band(band);
+                let mut bins: Vec<Vec<f32>> = vec![vec![]; num_bins];
+                for y in 0..image.height {
+                    for x in 0..image.width {
+                        let r = ((x as f64 - cx).powi(2) + (y as f64 - cy).powi(2)).sqrt();
+                        if r < max_radius && !buffer.get(x, y).is_nan() {
+                            bins[(r / bin_width) as usize].push(buffer.get(x, y));
+                        }
+                    }
+                }
+                bins.iter_mut()
+                    .enumerate()
+                    .filter(|(_, values)| !values.is_empty())
+                    .map(|(i, values)| bin_statistics((i as f64 + 0.5) * bin_width, values))
+                    .collect()
+            })
+            .collect();
+
+        Ok(RadialProfile {
+            center_x: cx,
+            center_y: cy,
+            bin_width,
+            bands,
+        })
+    }
+
+    /// One line per bin and band, following a header line
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("band,radius,mean,median,std_dev,count\n");
+        self.bands.iter().enumerate().for_each(|(band, bins)| {
+            bins.iter().for_each(|b| {
+                csv += &format!(
+                    "{},{},{},{},{},{}\n",
+                    band, b.radius, b.mean, b.median, b.std_dev, b.count
+                );
+            })
+        });
+        csv
+    }
+
+    /// Writes the profile as JSON if the path ends with .json, otherwise as CSV
+    pub fn save(&self, path: &str) -> Result<()> {
+        let contents = if has_extension(path, &["JSON"]) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            self.to_csv()
+        };
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+}
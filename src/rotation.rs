@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 use anyhow::Result;
 use rayon::prelude::*;
 
@@ -7,24 +9,32 @@ use crate::framerecord::FrameRecord;
 
 /// Determines the parallactic angle of rotation for each frame
 pub fn frame_rotation_analysis<C, F>(
-    context: &ProcessContext<F>,
+    context: &mut ProcessContext<F>,
     on_frame_checked: C,
 ) -> Result<Vec<FrameRecord>>
 where
     C: Fn(&FrameRecord) + Send + Sync + 'static,
     F: DataSource + Send + Sync + 'static,
 {
-    let frame_records: Vec<FrameRecord> = context
+    let t = Instant::now();
+    let ctx: &ProcessContext<F> = context;
+    let frame_records = ctx
         .frame_records
         .par_iter()
         .map(|fr| {
             let mut fr_copy = fr.clone();
-            fr_copy.computed_rotation = (context.parameters.initial_rotation
-                - fr.get_rotation_for_time(context).unwrap().rotation)
+            fr_copy.computed_rotation = (ctx.parameters.initial_rotation
+                - fr.get_rotation_for_time(ctx)?.rotation)
                 .to_radians();
             on_frame_checked(&fr_copy);
-            fr_copy
+            Ok(fr_copy)
         })
-        .collect();
+        .collect::<Result<Vec<FrameRecord>>>()?;
+
+    context.stats.initial_rotation = context.parameters.initial_rotation as f32;
+    context.stats.update_frames(&frame_records, |fs, fr| {
+        fs.computed_rotation = fr.computed_rotation;
+    });
+    context.stats.record_stage_timing("rotation", t.elapsed());
     Ok(frame_records)
 }
@@ -1,15 +1,24 @@
 // Technical specification: http://www.grischa-hahn.homepage.t-online.de/astro/ser/SER%20Doc%20V3b.pdf
 
-use anyhow::{Error, Result};
-use sciimg::{binfilereader::*, debayer, enums::ImageMode, image, imagebuffer};
+use std::fs::File;
 
-use crate::datasource::{ColorFormatId, DataFrame, DataSource};
+use anyhow::Result;
+use memmap::Mmap;
+use sciimg::{binfilereader::*, enums::ImageMode, image, imagebuffer};
+
+use crate::datasource::{ByteOrder, ColorFormatId, DataFrame, DataSource, PixelFormat};
+use crate::demosaic;
+use crate::demosaic::DemosaicMethod;
+use crate::error::SolHatError;
 use crate::timestamp;
 use crate::timestamp::TimeStamp;
 
 const HEADER_SIZE_BYTES: usize = 178;
 const TIMESTAMP_SIZE_BYTES: usize = 8;
 
+// Number of samples from the first frame inspected when detecting the pixel byte order and alignment
+const DETECTION_SAMPLE_COUNT: usize = 65536;
+
 // Variable size of pixel_depth * image_width * image_height
 // Frames block is frame_size * num_images
 // Frames block starts off at byte 178
@@ -30,6 +39,7 @@ pub struct SerFile {
     pub image_height: usize,                 // 4 bytes
     pub pixel_depth: usize,                  // 4 bytes
     pub frame_count: usize,                  // 4 bytes
+    pub little_endian_flag: i32,             // 4 bytes
     pub observer: String,                    // 40 bytes
     pub instrument: String,                  // 40 bytes
     pub telescope: String,                   // 40 bytes
@@ -37,24 +47,85 @@ pub struct SerFile {
     pub date_time_utc: timestamp::TimeStamp, // 8 bytes,
     pub total_size: usize,                   // Total file size (used for validation)
     file_reader: BinFileReader,
+    frame_data: Mmap,
     pub source_file: String,
+    pub demosaic_method: DemosaicMethod,
+    pub byte_order: ByteOrder, // Resolved byte order of 16-bit pixel data, never Auto
+    pub significant_bits: usize, // Number of meaningful bits per sample
+    pub sample_shift: u32,     // Left-shift applied to each sample to fill the 16-bit range
+}
+
+/// Computes the roughness of a run of 16-bit samples, taken as the sum of absolute differences
+/// between neighboring samples. Reading with the wrong byte order puts the noisy low byte in
+/// the high position, which makes the data far rougher than it should be.
+fn roughness(bytes: &[u8], little_endian: bool) -> f64 {
+    let values: Vec<f64> = bytes
+        .chunks_exact(2)
+        .map(|b| {
+            if little_endian {
+                u16::from_le_bytes([b[0], b[1]]) as f64
+            } else {
+                u16::from_be_bytes([b[0], b[1]]) as f64
+            }
+        })
+        .collect();
+    values.windows(2).map(|w| (w[1] - w[0]).abs()).sum()
+}
+
+/// Converts a block of raw samples to values, splitting interleaved samples out into a
+/// separate plane for each. Each plane is filled by a single straight pass over the input,
+/// which the compiler can vectorize.
+fn decode_planes(
+    bytes: &[u8],
+    bytes_per_sample: usize,
+    samples_per_pixel: usize,
+    byte_order: ByteOrder,
+    shift: u32,
+) -> Vec<Vec<f32>> {
+    let stride = bytes_per_sample * samples_per_pixel;
+    (0..samples_per_pixel)
+        .map(|s| {
+            let samples = bytes[s * bytes_per_sample..].chunks(stride);
+            match (bytes_per_sample, byte_order) {
+                (1, _) => samples.map(|b| b[0] as f32).collect(),
+                (_, ByteOrder::BigEndian) => samples
+                    .map(|b| ((u16::from_be_bytes([b[0], b[1]]) as u32) << shift).min(65535) as f32)
+                    .collect(),
+                _ => samples
+                    .map(|b| ((u16::from_le_bytes([b[0], b[1]]) as u32) << shift).min(65535) as f32)
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Guesses the byte order of 16-bit pixel data. Returns None if the data doesn't favor either.
+fn detect_byte_order(bytes: &[u8]) -> Option<ByteOrder> {
+    let le = roughness(bytes, true);
+    let be = roughness(bytes, false);
+    if le < be {
+        Some(ByteOrder::LittleEndian)
+    } else if be < le {
+        Some(ByteOrder::BigEndian)
+    } else {
+        None
+    }
 }
 
 impl SerFrame {
-    pub fn new(single_band_buffer: &imagebuffer::ImageBuffer, timestamp: u64) -> SerFrame {
+    pub fn new(single_band_buffer: &imagebuffer::ImageBuffer, timestamp: u64) -> Result<SerFrame> {
         let mut buffer = image::Image::new_with_bands(
             single_band_buffer.width,
             single_band_buffer.height,
             1,
             single_band_buffer.mode,
-        )
-        .unwrap();
+        )?;
         buffer.add_to_each(single_band_buffer);
 
-        SerFrame {
+        Ok(SerFrame {
             buffer,
             timestamp: timestamp::TimeStamp::from_u64(timestamp),
-        }
+        })
     }
 
     pub fn new_three_channel(
@@ -62,13 +133,13 @@ impl SerFrame {
         g: &imagebuffer::ImageBuffer,
         b: &imagebuffer::ImageBuffer,
         timestamp: u64,
-    ) -> SerFrame {
-        let buffer = image::Image::new_from_buffers_rgb(r, g, b, r.mode).unwrap();
+    ) -> Result<SerFrame> {
+        let buffer = image::Image::new_from_buffers_rgb(r, g, b, r.mode)?;
 
-        SerFrame {
+        Ok(SerFrame {
             buffer,
             timestamp: timestamp::TimeStamp::from_u64(timestamp),
-        }
+        })
     }
 
     pub fn new_rgb(rgb: image::Image, timestamp: u64) -> SerFrame {
@@ -100,33 +171,39 @@ impl SerFile {
         println!("Image Height: {}", self.image_height);
         println!("Pixel Depth: {}", self.pixel_depth);
         println!("Frame Count: {}", self.frame_count);
+        println!("Little Endian Flag: {}", self.little_endian_flag);
+        println!("Pixel Byte Order: {:?}", self.byte_order);
+        println!("Significant Bits: {}", self.significant_bits);
+        println!("Sample Shift: {}", self.sample_shift);
         println!("Observer: {}", self.observer);
         println!("Instrument: {}", self.instrument);
         println!("Telescope: {}", self.telescope);
         println!("Date/Time: {:?}", self.date_time);
         println!("Date/Time UTC: {:?}", self.date_time_utc);
         println!("Total File Size: {}", self.total_size);
-        println!(
-            "Bytes per image: {}",
-            self.image_width * self.image_height * (self.pixel_depth / 8)
-        );
+        println!("Bytes per image: {}", self.image_frame_size_bytes());
     }
 
     pub fn load_ser(file_path: &str) -> Result<SerFile> {
-        let mut file_reader =
+        // Frames are decoded in bulk directly from the mapped file rather than value by value
+        // through the reader
+        let frame_data = unsafe { Mmap::map(&File::open(file_path).map_err(SolHatError::from)?) }
+            .map_err(SolHatError::from)?;
+
+        // Header values are always little endian. The endian flag only describes the pixel data.
+        let file_reader =
             BinFileReader::new_as_endiness(&file_path.to_string(), Endian::LittleEndian);
-        let endiness = Endian::from_i32(file_reader.read_i32(22)?)?; // 4 bytes, start at 22
-        file_reader.set_endiness(endiness);
 
         // Some values are ok to default out, others need to propogate their errors
-        let ser = SerFile {
+        let mut ser = SerFile {
             file_id: file_reader.read_string(0, 14).unwrap_or_default(), // 14 bytes
             camera_series_id: file_reader.read_i32(14).unwrap_or(0),     // 4 bytes, start at 14
-            color_id: ColorFormatId::from_i32(file_reader.read_i32(18).unwrap_or(0)), // 4 bytes, start at 18
+            color_id: ColorFormatId::from_i32(file_reader.read_i32(18).unwrap_or(0))?, // 4 bytes, start at 18
             image_width: file_reader.read_i32(26)? as usize, // 4 bytes, start at 26
             image_height: file_reader.read_i32(30)? as usize, // 4 bytes, start at 30
             pixel_depth: file_reader.read_i32(34)? as usize, // 4 bytes, start at 34
             frame_count: file_reader.read_i32(38)? as usize, // 4 bytes, start at 38
+            little_endian_flag: file_reader.read_i32(22).unwrap_or(0), // 4 bytes, start at 22
             observer: file_reader.read_string(42, 40).unwrap_or_default(), // 40 bytes, start at 42
             instrument: file_reader.read_string(82, 40).unwrap_or_default(), // 40 bytes, start at 82
             telescope: file_reader.read_string(122, 40).unwrap_or_default(), // 40 bytes, start at 122
@@ -134,9 +211,16 @@ impl SerFile {
             date_time_utc: timestamp::TimeStamp::from_u64(file_reader.read_u64(170)?), // 8 bytes, start at 170
             total_size: file_reader.len(),
             file_reader,
+            frame_data,
             source_file: file_path.to_string(),
+            demosaic_method: DemosaicMethod::default(),
+            byte_order: ByteOrder::LittleEndian,
+            significant_bits: 8,
+            sample_shift: 0,
         };
 
+        ser.configure_pixel_format(&PixelFormat::default())?;
+
         if stump::is_verbose() {
             ser.print_header_details();
         }
@@ -144,8 +228,113 @@ impl SerFile {
         Ok(ser)
     }
 
+    /// Samples of up to 8 bits are stored in a single byte, anything deeper in two
+    pub fn bytes_per_sample(&self) -> usize {
+        if self.pixel_depth <= 8 {
+            1
+        } else {
+            2
+        }
+    }
+
     pub fn image_frame_size_bytes(&self) -> usize {
-        self.image_width * self.image_height * (self.pixel_depth / 8)
+        self.image_width
+            * self.image_height
+            * self.bytes_per_sample()
+            * self.color_id.samples_per_pixel()
+    }
+
+    /// Byte order of the pixel data as given by the header flag, or None if the flag can't be
+    /// trusted. The SER specification sets the flag to 1 for little endian data and 0 for big
+    /// endian, but much capture software writes 0 for little endian data, leaving 0 ambiguous.
+    pub fn header_byte_order(&self) -> Option<ByteOrder> {
+        match self.little_endian_flag {
+            1 => Some(ByteOrder::LittleEndian),
+            _ => None,
+        }
+    }
+
+    /// Resolves how pixel values are decoded. With `ByteOrder::Auto` the header flag is used
+    /// where it can be trusted, otherwise the order is detected from the first frame. Likewise,
+    /// 10, 12, and 14 bit cameras are written both with the samples in the low bits of the
+    /// 16-bit container and already shifted into the high bits; a shift is only applied when
+    /// the samples fit within the significant bits.
+    pub fn configure_pixel_format(&mut self, pixel_format: &PixelFormat) -> Result<()> {
+        self.significant_bits = pixel_format.bit_depth.unwrap_or(self.pixel_depth);
+
+        if self.significant_bits == 0 || self.significant_bits > self.bytes_per_sample() * 8 {
+            return Err(SolHatError::Format(format!(
+                "Bit depth of {} not possible with pixel depth of {}",
+                self.significant_bits, self.pixel_depth
+            ))
+            .into());
+        }
+
+        if self.bytes_per_sample() == 1 {
+            self.byte_order = ByteOrder::LittleEndian;
+            self.sample_shift = 0;
+            return Ok(());
+        }
+
+        // Don't go probing frame data of a file whose header doesn't describe it
+        self.validate_ser()?;
+
+        let sample = if self.frame_count > 0 {
+            let frame = self.frame_bytes(0)?;
+            frame[..(frame.len() / 2).min(DETECTION_SAMPLE_COUNT) * 2].to_vec()
+        } else {
+            vec![]
+        };
+
+        self.byte_order = match pixel_format.byte_order {
+            // Featureless frames give no hint either way. Little endian is the overwhelmingly
+            // common layout regardless of what the flag claims.
+            ByteOrder::Auto => self
+                .header_byte_order()
+                .or_else(|| detect_byte_order(&sample))
+                .unwrap_or(ByteOrder::LittleEndian),
+            bo => bo,
+        };
+
+        self.sample_shift = 0;
+        if pixel_format.shift_to_16bit && self.significant_bits < 16 {
+            let max_value = sample
+                .chunks_exact(2)
+                .map(|b| self.decode_u16(b[0], b[1]))
+                .max()
+                .unwrap_or(0);
+            if (max_value as usize) < (1 << self.significant_bits) {
+                self.sample_shift = (16 - self.significant_bits) as u32;
+            }
+        }
+
+        info!(
+            "Pixel data of {}: byte order {:?}, {} significant bits, shift {}",
+            self.source_file, self.byte_order, self.significant_bits, self.sample_shift
+        );
+
+        Ok(())
+    }
+
+    fn decode_u16(&self, b0: u8, b1: u8) -> u16 {
+        match self.byte_order {
+            ByteOrder::BigEndian => u16::from_be_bytes([b0, b1]),
+            _ => u16::from_le_bytes([b0, b1]),
+        }
+    }
+
+    /// The raw bytes of a frame within the mapped file
+    fn frame_bytes(&self, frame_num: usize) -> Result<&[u8]> {
+        let start = self.image_frame_start_index(frame_num);
+        let end = start + self.image_frame_size_bytes();
+        if end > self.frame_data.len() {
+            return Err(SolHatError::Format(format!(
+                "Frame #{} extends past the end of the file",
+                frame_num
+            ))
+            .into());
+        }
+        Ok(&self.frame_data[start..end])
     }
 
     pub fn image_frame_start_index(&self, frame_num: usize) -> usize {
@@ -179,16 +368,21 @@ impl SerFile {
         if self.total_size == expected_size {
             Ok(())
         } else {
-            Err(Error::msg(format!(
-                "Size mismatch: {} != {}",
+            Err(SolHatError::Format(format!(
+                "SER size mismatch: {} != {}",
                 self.total_size, expected_size
-            )))
+            ))
+            .into())
         }
     }
 
     pub fn get_ser_frame_timestamp(&self, frame_num: usize) -> Result<u64> {
         if frame_num >= self.frame_count {
-            return Err(Error::msg("Frame number out of range"));
+            return Err(SolHatError::FrameOutOfRange {
+                frame: frame_num,
+                frame_count: self.frame_count,
+            }
+            .into());
         }
 
         if !self.has_timestamps() {
@@ -202,7 +396,11 @@ impl SerFile {
 
     pub fn get_ser_frame(&self, frame_num: usize) -> Result<SerFrame> {
         if frame_num >= self.frame_count {
-            return Err(Error::msg("Frame number out of range"));
+            return Err(SolHatError::FrameOutOfRange {
+                frame: frame_num,
+                frame_count: self.frame_count,
+            }
+            .into());
         }
 
         let image_frame_size_bytes = self.image_frame_size_bytes();
@@ -217,55 +415,59 @@ impl SerFile {
             image_frame_start_index
         );
 
-        let mut values: Vec<f32> = Vec::with_capacity(self.image_width * self.image_height);
-        // values.resize(self.image_width * self.image_height, 0.0);
+        if self.pixel_depth == 0 || self.pixel_depth > 16 {
+            return Err(SolHatError::UnsupportedBitDepth(self.pixel_depth).into());
+        }
 
-        let bytes_per_pixel = self.pixel_depth / 8;
+        let bytes_per_sample = self.bytes_per_sample();
 
-        for y in 0..self.image_height {
-            for x in 0..self.image_width {
-                let pixel_start =
-                    (x + (y * self.image_width)) * bytes_per_pixel + image_frame_start_index;
+        // Packed RGB/BGR frames store the color samples of each pixel adjacent to each other.
+        // These are split out into a separate plane for each.
+        let planes = decode_planes(
+            self.frame_bytes(frame_num)?,
+            bytes_per_sample,
+            self.color_id.samples_per_pixel(),
+            self.byte_order,
+            self.sample_shift,
+        );
 
-                values.push(if self.pixel_depth == 8 {
-                    self.file_reader.read_u8(pixel_start)? as f32
-                } else if self.pixel_depth == 16 {
-                    self.file_reader.read_u16(pixel_start)? as f32
-                } else {
-                    panic!("Encountered unsupported pixel depth: {}", self.pixel_depth);
-                });
-            }
-        }
+        let mode = match bytes_per_sample {
+            1 => ImageMode::U8BIT,
+            _ => ImageMode::U16BIT,
+        };
 
-        let frame_buffer = imagebuffer::ImageBuffer::from_vec_as_mode(
-            &values,
-            self.image_width,
-            self.image_height,
-            match self.pixel_depth {
-                8 => ImageMode::U8BIT,
-                _ => ImageMode::U16BIT,
-            },
-        )
-        .expect("Failed to allocate image buffer");
+        let plane_buffers = planes
+            .iter()
+            .map(|values| {
+                imagebuffer::ImageBuffer::from_vec_as_mode(
+                    values,
+                    self.image_width,
+                    self.image_height,
+                    mode,
+                )
+            })
+            .collect::<Result<Vec<imagebuffer::ImageBuffer>>>()?;
+
+        let timestamp = self.get_ser_frame_timestamp(frame_num)?;
 
         match self.color_id {
-            ColorFormatId::Mono => Ok(SerFrame::new(
-                &frame_buffer,
-                self.get_ser_frame_timestamp(frame_num)
-                    .expect("Failed to extract frame timestamp"),
+            ColorFormatId::Mono => SerFrame::new(&plane_buffers[0], timestamp),
+            ColorFormatId::Rgb => SerFrame::new_three_channel(
+                &plane_buffers[0],
+                &plane_buffers[1],
+                &plane_buffers[2],
+                timestamp,
+            ),
+            ColorFormatId::Bgr => SerFrame::new_three_channel(
+                &plane_buffers[2],
+                &plane_buffers[1],
+                &plane_buffers[0],
+                timestamp,
+            ),
+            _ => Ok(SerFrame::new_rgb(
+                demosaic::demosaic(&plane_buffers[0], self.color_id, self.demosaic_method)?,
+                timestamp,
             )),
-            ColorFormatId::BayerRggb => {
-                let debayered =
-                    debayer::debayer(&frame_buffer, debayer::DebayerMethod::Malvar).unwrap();
-                Ok(SerFrame::new_rgb(
-                    debayered,
-                    self.get_ser_frame_timestamp(frame_num)
-                        .expect("Failed to extract frame timestamp"),
-                ))
-            }
-            _ => {
-                panic!("Unsupported color mode: {:?}", self.color_id);
-            }
         }
     }
 }
@@ -333,7 +535,10 @@ impl DataSource for SerFile {
 
     fn open(path: &[String]) -> Result<Self> {
         if path.len() != 1 {
-            Err(Error::msg("Only one ser file supported at this time"))
+            Err(SolHatError::InvalidParameter(
+                "Only one ser file supported at this time".to_string(),
+            )
+            .into())
         } else {
             SerFile::load_ser(&path[0])
         }
@@ -343,6 +548,14 @@ impl DataSource for SerFile {
         self.validate_ser()
     }
 
+    fn set_demosaic_method(&mut self, method: DemosaicMethod) {
+        self.demosaic_method = method;
+    }
+
+    fn set_pixel_format(&mut self, pixel_format: &PixelFormat) -> Result<()> {
+        self.configure_pixel_format(pixel_format)
+    }
+
     fn print_header_details(&self) {
         self.print_ser_header_details();
     }
@@ -0,0 +1,187 @@
+// Writes SER v3 files. See ser.rs for the layout of the header, frames, and trailer.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use anyhow::Result;
+use sciimg::image::Image;
+
+use crate::datasource::{ColorFormatId, DataSource};
+use crate::error::SolHatError;
+use crate::timestamp::TimeStamp;
+
+const FILE_ID: &[u8; 14] = b"LUCAM-RECORDER";
+const HEADER_STRING_BYTES: usize = 40;
+
+/// Streams frames out to a SER file. The header is written last by `finish`, once the number
+/// of frames is known, so the metadata fields may be changed at any point before then.
+pub struct SerWriter {
+    pub color_id: ColorFormatId,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub pixel_depth: usize,
+    pub observer: String,
+    pub instrument: String,
+    pub telescope: String,
+    pub date_time: TimeStamp,
+    pub date_time_utc: TimeStamp,
+    output_path: String,
+    writer: BufWriter<File>,
+    timestamps: Vec<u64>,
+}
+
+/// Fixed width, zero padded header string
+fn header_string(s: &str) -> [u8; HEADER_STRING_BYTES] {
+    let mut bytes = [0; HEADER_STRING_BYTES];
+    s.bytes()
+        .take(HEADER_STRING_BYTES)
+        .enumerate()
+        .for_each(|(i, b)| bytes[i] = b);
+    bytes
+}
+
+impl SerWriter {
+    pub fn create(
+        output_path: &str,
+        color_id: ColorFormatId,
+        image_width: usize,
+        image_height: usize,
+        pixel_depth: usize,
+    ) -> Result<SerWriter> {
+        if pixel_depth == 0 || pixel_depth > 16 {
+            return Err(SolHatError::UnsupportedBitDepth(pixel_depth).into());
+        }
+
+        let mut writer = SerWriter {
+            color_id,
+            image_width,
+            image_height,
+            pixel_depth,
+            observer: "".to_string(),
+            instrument: "".to_string(),
+            telescope: "".to_string(),
+            date_time: TimeStamp::from_u64(0),
+            date_time_utc: TimeStamp::from_u64(0),
+            output_path: output_path.to_string(),
+            writer: BufWriter::new(File::create(output_path)?),
+            timestamps: vec![],
+        };
+
+        // Reserves the space of the header, which is filled in once all frames are written
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    /// Copies the observer, instrument, telescope, and capture time from a data source
+    pub fn copy_metadata<F: DataSource>(&mut self, source: &F) {
+        self.observer = source.observer();
+        self.instrument = source.instrument();
+        self.telescope = source.telescope();
+        self.date_time = source.date_time();
+        self.date_time_utc = source.date_time_utc();
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        if self.pixel_depth <= 8 {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let w = &mut self.writer;
+        w.write_all(FILE_ID)?;
+        w.write_all(&0_i32.to_le_bytes())?; // Camera series id
+        w.write_all(&(self.color_id as i32).to_le_bytes())?;
+
+        // Pixel data is always written little endian, which the specification flags with 1
+        w.write_all(&1_i32.to_le_bytes())?;
+        w.write_all(&(self.image_width as i32).to_le_bytes())?;
+        w.write_all(&(self.image_height as i32).to_le_bytes())?;
+        w.write_all(&(self.pixel_depth as i32).to_le_bytes())?;
+        w.write_all(&(self.timestamps.len() as i32).to_le_bytes())?;
+        w.write_all(&header_string(&self.observer))?;
+        w.write_all(&header_string(&self.instrument))?;
+        w.write_all(&header_string(&self.telescope))?;
+        w.write_all(&self.date_time.timestamp.to_le_bytes())?;
+        w.write_all(&self.date_time_utc.timestamp.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Appends a frame. Mono and mosaic frames are taken from the first band, while RGB and
+    /// BGR frames are interleaved from the first three. Values are rounded and clamped to the
+    /// range of the pixel depth.
+    pub fn write_frame(&mut self, image: &Image, timestamp: &TimeStamp) -> Result<()> {
+        if image.width != self.image_width || image.height != self.image_height {
+            return Err(SolHatError::IncompatibleInputs(format!(
+                "Frame is {}x{}, expected {}x{}",
+                image.width, image.height, self.image_width, self.image_height
+            ))
+            .into());
+        }
+
+        let bands: Vec<usize> = match self.color_id {
+            ColorFormatId::Rgb => vec![0, 1, 2],
+            ColorFormatId::Bgr => vec![2, 1, 0],
+            _ => vec![0],
+        };
+        if image.num_bands() < bands.len() {
+            return Err(SolHatError::IncompatibleInputs(format!(
+                "Frame of {} bands cannot be written as {:?}",
+                image.num_bands(),
+                self.color_id
+            ))
+            .into());
+        }
+
+        let max_value = ((1_u32 << self.pixel_depth) - 1) as f32;
+        let bytes_per_sample = self.bytes_per_sample();
+        let mut bytes = Vec::with_capacity(
+            self.image_width * self.image_height * bands.len() * bytes_per_sample,
+        );
+
+        for y in 0..self.image_height {
+            for x in 0..self.image_width {
+                for band in bands.iter() {
+                    let v = image
+                        .get_band(*band)
+                        .get(x, y)
+                        .round()
+                        .clamp(0.0, max_value);
+                    if bytes_per_sample == 1 {
+                        bytes.push(v as u8);
+                    } else {
+                        bytes.extend_from_slice(&(v as u16).to_le_bytes());
+                    }
+                }
+            }
+        }
+
+        self.writer.write_all(&bytes)?;
+        self.timestamps.push(timestamp.timestamp);
+        Ok(())
+    }
+
+    /// Writes the timestamp trailer and the final header, returning the number of frames written
+    pub fn finish(mut self) -> Result<usize> {
+        for ts in self.timestamps.iter() {
+            self.writer.write_all(&ts.to_le_bytes())?;
+        }
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.flush()?;
+
+        info!(
+            "Wrote {} frames to {}",
+            self.timestamps.len(),
+            self.output_path
+        );
+        Ok(self.timestamps.len())
+    }
+}
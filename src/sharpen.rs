@@ -0,0 +1,480 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+use sciimg::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::context::ProcessContext;
+use crate::datasource::{self, DataSource};
+use crate::error::SolHatError;
+use crate::phasecorrelation::fft2d;
+
+/// Smallest value divided by in the Richardson-Lucy update, avoiding blow ups in dark sky
+const RL_EPSILON: f32 = 1.0e-6;
+
+/// Median absolute deviation to standard deviation of normally distributed noise
+const MAD_TO_SIGMA: f32 = 1.0 / 0.6745;
+
+/// Gain and noise reduction of a single wavelet detail layer
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub struct WaveletLayer {
+    pub gain: f32,    // Multiplier of the layer's detail. One leaves it unchanged.
+    pub denoise: f32, // Soft threshold, in multiples of the layer's estimated noise
+}
+
+impl WaveletLayer {
+    /// Parses a comma separated list of layers, finest first, each as `gain` or `gain:denoise`
+    /// (e.g. "2.0:1.0,1.5:0.5,1.2")
+    pub fn parse_layers(s: &str) -> Result<Vec<WaveletLayer>> {
+        let invalid = |l: &str| -> anyhow::Error {
+            SolHatError::InvalidParameter(format!(
+                "Invalid wavelet layer: {}. Expected <gain> or <gain>:<denoise>, e.g. 1.5:0.5",
+                l
+            ))
+            .into()
+        };
+        s.split(',')
+            .map(|l| {
+                let mut parts = l.trim().split(':');
+                let gain = parts
+                    .next()
+                    .and_then(|g| g.parse::<f32>().ok())
+                    .ok_or_else(|| invalid(l))?;
+                let denoise = match parts.next() {
+                    Some(d) => d.parse::<f32>().map_err(|_| invalid(l))?,
+                    None => 0.0,
+                };
+                if parts.next().is_some() || denoise < 0.0 {
+                    return Err(invalid(l));
+                }
+                Ok(WaveletLayer { gain, denoise })
+            })
+            .collect()
+    }
+}
+
+/// Point spread function deconvolved with Richardson-Lucy
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Psf {
+    Gaussian(f32), // Standard deviation in pixels
+    File(String),  // Image of the PSF, of odd width and height, centered
+}
+
+impl Psf {
+    /// The PSF given by either a Gaussian sigma or an image path, if any
+    pub fn from_options(sigma: Option<f32>, file: &Option<String>) -> Result<Option<Psf>> {
+        match (sigma, file) {
+            (Some(_), Some(_)) => Err(SolHatError::InvalidParameter(
+                "Specify either a Gaussian PSF sigma or a PSF image, not both".to_string(),
+            )
+            .into()),
+            (Some(sigma), None) if sigma > 0.0 => Ok(Some(Psf::Gaussian(sigma))),
+            (Some(sigma), None) => Err(SolHatError::InvalidParameter(format!(
+                "Invalid PSF sigma: {}. Must be positive",
+                sigma
+            ))
+            .into()),
+            (None, Some(path)) => Ok(Some(Psf::File(path.to_owned()))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Post-stack sharpening. Deconvolution is applied first, followed by the wavelet layers.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub struct SharpenParameters {
+    pub wavelet_layers: Vec<WaveletLayer>,
+    pub psf: Option<Psf>,
+    pub iterations: usize,           // Richardson-Lucy iterations
+    pub dering_width: Option<usize>, // Pixels to either side of the limb kept from ringing
+    pub limb_threshold: f32,         // Value separating the disk from the sky when deringing
+}
+
+impl SharpenParameters {
+    pub fn is_enabled(&self) -> bool {
+        !self.wavelet_layers.is_empty() || (self.psf.is_some() && self.iterations > 0)
+    }
+}
+
+/// A square, odd sized, convolution kernel normalized to a sum of one
+#[derive(Debug, Clone)]
+pub struct Kernel {
+    pub size: usize,
+    pub values: Vec<f32>,
+}
+
+impl Kernel {
+    pub fn gaussian(sigma: f32) -> Result<Kernel> {
+        if sigma <= 0.0 {
+            return Err(SolHatError::InvalidParameter(format!(
+                "Invalid PSF sigma: {}. Must be positive",
+                sigma
+            ))
+            .into());
+        }
+        let radius = (sigma * 3.0).ceil() as i32;
+        let size = (radius * 2 + 1) as usize;
+        let mut values = Vec::with_capacity(size * size);
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                values.push((-((x * x + y * y) as f32) / (2.0 * sigma * sigma)).exp());
+            }
+        }
+        Kernel::normalized(size, values)
+    }
+
+    /// A kernel from the first band of an image
+    pub fn from_image(image: &Image) -> Result<Kernel> {
+        if image.width != image.height || image.width % 2 == 0 {
+            return Err(SolHatError::InvalidParameter(format!(
+                "PSF image must be square with an odd size, got {}x{}",
+                image.width, image.height
+            ))
+            .into());
+        }
+        let buffer = image.get_band(0);
+        let mut values = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                values.push(buffer.get(x, y).max(0.0));
+            }
+        }
+        Kernel::normalized(image.width, values)
+    }
+
+    pub fn from_psf(psf: &Psf) -> Result<Kernel> {
+        match psf {
+            Psf::Gaussian(sigma) => Kernel::gaussian(*sigma),
+            Psf::File(path) => Kernel::from_image(&open_image(path)?),
+        }
+    }
+
+    fn normalized(size: usize, mut values: Vec<f32>) -> Result<Kernel> {
+        let sum: f32 = values.iter().sum();
+        if sum <= 0.0 {
+            return Err(
+                SolHatError::InvalidParameter("PSF has no positive values".to_string()).into(),
+            );
+        }
+        values.iter_mut().for_each(|v| *v /= sum);
+        Ok(Kernel { size, values })
+    }
+
+    /// The kernel rotated by 180 degrees
+    fn flipped(&self) -> Kernel {
+        Kernel {
+            size: self.size,
+            values: self.values.iter().rev().copied().collect(),
+        }
+    }
+}
+
+/// Spectrum of a kernel for convolving planes of one size by FFT. Planes are extended by the
+/// kernel radius at each edge so that the circular convolution doesn't wrap around, which
+/// also leaves no limit on the kernel size relative to the plane.
+struct KernelSpectrum {
+    width: usize,
+    height: usize,
+    radius: usize,
+    values: Vec<Complex<f64>>,
+}
+
+impl KernelSpectrum {
+    fn new(kernel: &Kernel, plane_width: usize, plane_height: usize) -> KernelSpectrum {
+        let radius = kernel.size / 2;
+        let width = plane_width + radius * 2;
+        let height = plane_height + radius * 2;
+
+        // Centered on the origin, with negative offsets wrapped to the far edges
+        let mut values = vec![Complex::new(0.0, 0.0); width * height];
+        iproduct!(0..kernel.size, 0..kernel.size).for_each(|(ky, kx)| {
+            let x = (kx + width - radius) % width;
+            let y = (ky + height - radius) % height;
+            values[y * width + x] = Complex::new(kernel.values[ky * kernel.size + kx] as f64, 0.0);
+        });
+        fft2d(&mut values, width, height, false);
+
+        KernelSpectrum {
+            width,
+            height,
+            radius,
+            values,
+        }
+    }
+}
+
+/// Opens a single image of any supported input format
+pub fn open_image(path: &str) -> Result<Image> {
+    Ok(datasource::open_data_source(&[path.to_string()])?
+        .get_frame(0)?
+        .buffer)
+}
+
+/// A single band as a plain row-major plane of values
+struct Plane {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl Plane {
+    fn from_band(image: &Image, band: usize) -> Plane {
+        let buffer = image.get_band(band);
+        let mut values = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                values.push(buffer.get(x, y));
+            }
+        }
+        Plane {
+            width: image.width,
+            height: image.height,
+            values,
+        }
+    }
+
+    fn new_like(&self, values: Vec<f32>) -> Plane {
+        Plane {
+            width: self.width,
+            height: self.height,
+            values,
+        }
+    }
+
+    fn get_clamped(&self, x: i32, y: i32) -> f32 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.values[y * self.width + x]
+    }
+
+    /// Convolves with the kernel of the spectrum, edges extended by their nearest pixel. Each
+    /// output pixel is the sum of its neighbors weighted by the kernel values at their offsets.
+    fn convolve(&self, spectrum: &KernelSpectrum) -> Plane {
+        let (width, height) = (spectrum.width, spectrum.height);
+        let radius = spectrum.radius;
+
+        let mut data: Vec<Complex<f64>> = iproduct!(0..height, 0..width)
+            .map(|(y, x)| {
+                let v = self.get_clamped(x as i32 - radius as i32, y as i32 - radius as i32);
+                Complex::new(v as f64, 0.0)
+            })
+            .collect();
+        fft2d(&mut data, width, height, false);
+        data.iter_mut()
+            .zip(spectrum.values.iter())
+            .for_each(|(d, k)| *d *= k.conj());
+        fft2d(&mut data, width, height, true);
+
+        let n = (width * height) as f64;
+        self.new_like(
+            iproduct!(0..self.height, 0..self.width)
+                .map(|(y, x)| (data[(y + radius) * width + x + radius].re / n) as f32)
+                .collect(),
+        )
+    }
+
+    /// Separable convolution with the B3-spline kernel, its taps `step` pixels apart
+    fn smooth_b3spline(&self, step: i32) -> Plane {
+        const B3: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+        let pass = |src: &Plane, dx: i32, dy: i32| {
+            let mut values = vec![0.0; src.values.len()];
+            values
+                .par_chunks_mut(src.width)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    row.iter_mut().enumerate().for_each(|(x, v)| {
+                        *v = B3.iter().enumerate().fold(0.0, |ttl, (i, k)| {
+                            let o = (i as i32 - 2) * step;
+                            ttl + k * src.get_clamped(x as i32 + o * dx, y as i32 + o * dy)
+                        });
+                    });
+                });
+            src.new_like(values)
+        };
+        pass(&pass(self, 1, 0), 0, 1)
+    }
+
+    /// Box mean over the square within `radius` pixels, by separable running sums
+    fn box_mean(&self, radius: usize) -> Plane {
+        let r = radius as i32;
+        let n = (2 * r + 1) as f32;
+        let pass = |src: &Plane, dx: i32, dy: i32| {
+            let mut values = vec![0.0; src.values.len()];
+            values
+                .par_chunks_mut(src.width)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    row.iter_mut().enumerate().for_each(|(x, v)| {
+                        *v = (-r..=r)
+                            .map(|o| src.get_clamped(x as i32 + o * dx, y as i32 + o * dy))
+                            .sum::<f32>()
+                            / n;
+                    });
+                });
+            src.new_like(values)
+        };
+        pass(&pass(self, 1, 0), 0, 1)
+    }
+
+    fn put_into_band(&self, image: &mut Image, band: usize) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.put(x, y, self.values[y * self.width + x], band);
+            }
+        }
+    }
+}
+
+/// Noise estimate of a wavelet detail layer from the median absolute coefficient
+fn estimate_layer_noise(detail: &[f32]) -> f32 {
+    let mut abs: Vec<f32> = detail
+        .iter()
+        .filter(|v| !v.is_nan())
+        .map(|v| v.abs())
+        .collect();
+    if abs.is_empty() {
+        return 0.0;
+    }
+    let mid = abs.len() / 2;
+    let (_, median, _) = abs.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+    *median * MAD_TO_SIGMA
+}
+
+fn soft_threshold(v: f32, threshold: f32) -> f32 {
+    v.signum() * (v.abs() - threshold).max(0.0)
+}
+
+fn wavelet_sharpen_plane(plane: &Plane, layers: &[WaveletLayer]) -> Plane {
+    let mut residual = plane.new_like(plane.values.clone());
+    let mut result = vec![0.0; plane.values.len()];
+
+    for (n, layer) in layers.iter().enumerate() {
+        let smoothed = residual.smooth_b3spline(1 << n);
+        let detail: Vec<f32> = residual
+            .values
+            .iter()
+            .zip(smoothed.values.iter())
+            .map(|(c, s)| c - s)
+            .collect();
+        let threshold = layer.denoise * estimate_layer_noise(&detail);
+        result
+            .iter_mut()
+            .zip(detail.iter())
+            .for_each(|(r, d)| *r += layer.gain * soft_threshold(*d, threshold));
+        residual = smoothed;
+    }
+
+    result
+        .iter_mut()
+        .zip(residual.values.iter())
+        .for_each(|(r, c)| *r += c);
+    plane.new_like(result)
+}
+
+fn richardson_lucy_plane(plane: &Plane, kernel: &Kernel, iterations: usize) -> Plane {
+    let observed: Vec<f32> = plane.values.iter().map(|v| v.max(0.0)).collect();
+    let spectrum = KernelSpectrum::new(kernel, plane.width, plane.height);
+    let flipped = KernelSpectrum::new(&kernel.flipped(), plane.width, plane.height);
+    let mut estimate = plane.new_like(observed.clone());
+
+    for _ in 0..iterations {
+        let blurred = estimate.convolve(&spectrum);
+        let ratio = plane.new_like(
+            observed
+                .iter()
+                .zip(blurred.values.iter())
+                .map(|(o, b)| o / b.max(RL_EPSILON))
+                .collect(),
+        );
+        let correction = ratio.convolve(&flipped);
+        estimate
+            .values
+            .iter_mut()
+            .zip(correction.values.iter())
+            .for_each(|(e, c)| *e *= c);
+    }
+    estimate
+}
+
+fn map_bands<F: Fn(&Plane) -> Plane>(image: &Image, f: F) -> Image {
+    let mut result = image.clone();
+    for band in 0..image.num_bands() {
+        f(&Plane::from_band(image, band)).put_into_band(&mut result, band);
+    }
+    result
+}
+
+/// À trous (B3-spline) wavelet sharpening. Each layer, finest first, has its detail
+/// coefficients soft thresholded for noise and then multiplied by its gain.
+pub fn wavelet_sharpen(image: &Image, layers: &[WaveletLayer]) -> Image {
+    map_bands(image, |p| wavelet_sharpen_plane(p, layers))
+}
+
+/// Richardson-Lucy deconvolution of each band by the kernel. Convolutions are done by FFT, so
+/// the cost of each iteration hardly depends on the kernel size.
+pub fn richardson_lucy(image: &Image, kernel: &Kernel, iterations: usize) -> Image {
+    map_bands(image, |p| richardson_lucy_plane(p, kernel, iterations))
+}
+
+/// Blends the sharpened image back to the original approaching the limb, where the step from
+/// disk to sky otherwise rings. The blend follows the fraction of disk pixels within `width`
+/// of each pixel, being fully original where disk and sky are evenly mixed.
+pub fn dering_limb(original: &Image, sharpened: &Image, threshold: f32, width: usize) -> Image {
+    let disk = Plane::from_band(original, 0);
+    let mask = disk.new_like(
+        disk.values
+            .iter()
+            .map(|v| if *v >= threshold { 1.0 } else { 0.0 })
+            .collect(),
+    );
+    let disk_fraction = mask.box_mean(width);
+
+    let mut result = sharpened.clone();
+    for band in 0..original.num_bands() {
+        let orig = original.get_band(band);
+        let sharp = sharpened.get_band(band);
+        for y in 0..original.height {
+            for x in 0..original.width {
+                let f = (2.0 * disk_fraction.values[y * original.width + x] - 1.0).abs();
+                let o = orig.get(x, y);
+                result.put(x, y, o + f * (sharp.get(x, y) - o), band);
+            }
+        }
+    }
+    result
+}
+
+/// Applies deconvolution, then the wavelet layers, then limb deringing if enabled
+pub fn sharpen(image: &Image, params: &SharpenParameters) -> Result<Image> {
+    let mut sharpened = image.clone();
+
+    if let Some(psf) = &params.psf {
+        if params.iterations > 0 {
+            let kernel = Kernel::from_psf(psf)?;
+            sharpened = richardson_lucy(&sharpened, &kernel, params.iterations);
+        }
+    }
+
+    if !params.wavelet_layers.is_empty() {
+        sharpened = wavelet_sharpen(&sharpened, &params.wavelet_layers);
+    }
+
+    if let Some(width) = params.dering_width {
+        sharpened = dering_limb(image, &sharpened, params.limb_threshold, width);
+    }
+
+    Ok(sharpened)
+}
+
+/// The final, optional, pipeline stage sharpening the stack
+pub fn process_sharpening<F: DataSource>(
+    context: &mut ProcessContext<F>,
+    stacked_buffer: &Image,
+) -> Result<Image> {
+    let t = Instant::now();
+    let sharpened = sharpen(stacked_buffer, &context.parameters.sharpen)?;
+    context.stats.record_stage_timing("sharpening", t.elapsed());
+    Ok(sharpened)
+}
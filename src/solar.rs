@@ -20,6 +20,51 @@ pub fn position_from_lat_lon_and_time(lat: f64, lon: f64, ts: &timestamp::TimeSt
     // (pos.corrected_solar_elevation_angle, pos.solar_azimuth_angle)
 }
 
+/// Orientation of the solar disk as seen from the Earth, in degrees
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SolarOrientation {
+    pub p: f64,  // Position angle of the northern extremity of the axis of rotation
+    pub b0: f64, // Heliographic latitude of the center of the disk
+    pub l0: f64, // Heliographic (Carrington) longitude of the center of the disk
+}
+
+/// Computes the orientation of the solar disk at a Julian day, per Meeus, Astronomical
+/// Algorithms, chapters 25 (low accuracy solar coordinates) and 29
+pub fn orientation_from_julian_day(jd: f64) -> SolarOrientation {
+    let t = (jd - 2451545.0) / 36525.0;
+
+    // Apparent longitude of the Sun
+    let mean_longitude = 280.46646 + 36000.76983 * t + 0.0003032 * t * t;
+    let mean_anomaly = (357.52911 + 35999.05029 * t - 0.0001537 * t * t).to_radians();
+    let equation_of_center = (1.914602 - 0.004817 * t - 0.000014 * t * t) * mean_anomaly.sin()
+        + (0.019993 - 0.000101 * t) * (2.0 * mean_anomaly).sin()
+        + 0.000289 * (3.0 * mean_anomaly).sin();
+    let omega = (125.04 - 1934.136 * t).to_radians();
+    let lambda =
+        (mean_longitude + equation_of_center - 0.00569 - 0.00478 * omega.sin()).to_radians();
+
+    // True obliquity of the ecliptic
+    let epsilon = (23.0 + 26.0 / 60.0 + 21.448 / 3600.0 - (46.8150 * t) / 3600.0
+        + 0.00256 * omega.cos())
+    .to_radians();
+
+    let theta = ((jd - 2398220.0) * 360.0 / 25.38).rem_euclid(360.0);
+    let inclination = 7.25_f64.to_radians();
+    let k = (73.6667 + 1.3958333 * (jd - 2396758.0) / 36525.0).to_radians();
+
+    let x = (-lambda.cos() * epsilon.tan()).atan();
+    let y = (-(lambda - k).cos() * inclination.tan()).atan();
+    let b0 = ((lambda - k).sin() * inclination.sin()).asin();
+    // tan(eta) = tan(lambda - k) cos(I), with the quadrant chosen to match Meeus example 29.a
+    let eta = (-(lambda - k).sin() * inclination.cos()).atan2(-(lambda - k).cos());
+
+    SolarOrientation {
+        p: (x + y).to_degrees(),
+        b0: b0.to_degrees(),
+        l0: (eta.to_degrees() - theta).rem_euclid(360.0),
+    }
+}
+
 // #[derive(Debug, Clone)]
 // pub struct SolarPosition {
 //     solar_declination: f64,
@@ -1,17 +1,26 @@
-use anyhow::{anyhow, Result};
+use std::time::Instant;
+
+use anyhow::Result;
 use rayon::prelude::*;
 
 use crate::context::ProcessContext;
 use crate::datasource::DataSource;
-use crate::drizzle::{
-    AverageStackBuffer, BilinearDrizzle, MedianStackBuffer, MinimumStackBuffer, StackAlgorithm,
-    StackAlgorithmImpl, StackBuffer,
-};
+use crate::drizzle::{Drizzle, StackAlgorithmImpl};
+use crate::error::SolHatError;
 use crate::framerecord::FrameRecord;
+use crate::weighting::FrameWeighting;
 use sciimg::prelude::Image;
 
+/// The stacked image with the maps of how its pixels were sampled
+pub struct StackedImage {
+    pub image: Image,
+    pub weight_map: Image,
+    pub coverage_map: Image,
+}
+
+/// Stacks the frame records, keeping the weight and coverage maps of the stack in the context
 pub fn process_frame_stacking<C, F>(
-    context: &ProcessContext<F>,
+    context: &mut ProcessContext<F>,
     on_frame_checked: C,
 ) -> Result<Image>
 where
@@ -19,25 +28,59 @@ where
     F: DataSource + Send + Sync + 'static,
 {
     if context.frame_records.is_empty() {
-        return Err(anyhow!("No frames to stack!"));
+        return Err(SolHatError::EmptyStack.into());
     }
 
-    match context.parameters.algorithm.allow_parallel() {
+    let algorithm = context.parameters.algorithm;
+    if context.parameters.frame_weighting != FrameWeighting::Equal
+        && !algorithm.supports_weighting()
+    {
+        return Err(SolHatError::InvalidParameter(format!(
+            "Frame weighting is not supported by the {:?} stacking algorithm",
+            algorithm
+        ))
+        .into());
+    }
+
+    let t = Instant::now();
+    let stacked = match algorithm.allow_parallel() {
         true => process_frame_stacking_parallel(context, on_frame_checked),
         false => process_frame_stacking_linear(context, on_frame_checked),
-    }
+    };
+    context.stats.record_stage_timing("stacking", t.elapsed());
+
+    let stacked = stacked.map(|stacked| {
+        context.weight_map = Some(stacked.weight_map);
+        context.coverage_map = Some(stacked.coverage_map);
+        stacked.image
+    });
+
+    // Stacking is the last stage to read frames
+    let mut cache = context.lock_frame_cache();
+    context.stats.frame_cache_hits = cache.hits;
+    context.stats.frame_cache_misses = cache.misses;
+    info!(
+        "Frame cache: {} hits, {} misses, {} frames held",
+        cache.hits,
+        cache.misses,
+        cache.len()
+    );
+    cache.clear();
+    drop(cache);
+
+    stacked
 }
 
 pub fn process_frame_stacking_parallel<C, F>(
     context: &ProcessContext<F>,
     on_frame_checked: C,
-) -> Result<Image>
+) -> Result<StackedImage>
 where
     C: Fn(&FrameRecord) + Send + Sync + 'static,
     F: DataSource + Send + Sync + 'static,
 {
     if context.frame_records.is_empty() {
-        return Err(anyhow!("No frames to stack!"));
+        return Err(SolHatError::EmptyStack.into());
     }
 
     let stack_algorithm = context.parameters.algorithm;
@@ -54,9 +97,14 @@ where
     let out_width = (in_width as f32 * context.parameters.drizzle_scale.value()).ceil() as usize;
     let out_height = (in_height as f32 * context.parameters.drizzle_scale.value()).ceil() as usize;
 
-    let num_source_bands = context.frame_records[0].num_bands(context)?;
+    // Raw CFA frames are single band mosaics which stack into three color bands
+    let cfa_pattern = context.cfa_pattern();
+    let num_source_bands = match cfa_pattern {
+        Some(_) => 3,
+        None => context.frame_records[0].num_bands(context)?,
+    };
 
-    let mut master_drizzle: BilinearDrizzle = BilinearDrizzle::new(
+    let mut master_drizzle: Drizzle = Drizzle::new(
         context
             .parameters
             .crop_width
@@ -68,24 +116,20 @@ where
         context.parameters.drizzle_scale,
         context.parameters.horiz_offset,
         context.parameters.vert_offset,
-        match stack_algorithm {
-            StackAlgorithm::Average => StackAlgorithmImpl::Average(AverageStackBuffer::new(
-                out_width,
-                out_height,
-                num_source_bands,
-            )),
-            StackAlgorithm::Median => StackAlgorithmImpl::Median(MedianStackBuffer::new(
-                out_width,
-                out_height,
-                num_source_bands,
-            )),
-            StackAlgorithm::Minimum => StackAlgorithmImpl::Minimum(MinimumStackBuffer::new(
-                out_width,
-                out_height,
-                num_source_bands,
-            )),
-        },
+        StackAlgorithmImpl::new(
+            stack_algorithm,
+            context.parameters.stack_kappa,
+            out_width,
+            out_height,
+            num_source_bands,
+        )?,
+    );
+    master_drizzle.set_cfa_pattern(cfa_pattern);
+    master_drizzle.set_kernel(
+        context.parameters.drizzle_kernel,
+        context.parameters.drizzle_pixfrac,
     );
+    master_drizzle.set_interpolation(context.parameters.interpolation);
 
     let mut num_per_chunk = context.frame_records.len() / num_cpus::get();
     if num_per_chunk == 0 {
@@ -93,64 +137,58 @@ where
         num_per_chunk = 1;
     }
 
-    let sub_drizzles: Vec<BilinearDrizzle> = context
-        .frame_records
-        .par_chunks(num_per_chunk)
-        .map(|record_chunk| {
-            let mut drizzle =
-                BilinearDrizzle::new(
-                    in_width,
-                    in_height,
-                    context.parameters.drizzle_scale,
-                    context.parameters.horiz_offset,
-                    context.parameters.vert_offset,
-                    match stack_algorithm {
-                        StackAlgorithm::Average => StackAlgorithmImpl::Average(
-                            AverageStackBuffer::new(out_width, out_height, num_source_bands),
-                        ),
-                        StackAlgorithm::Median => StackAlgorithmImpl::Median(
-                            MedianStackBuffer::new(out_width, out_height, num_source_bands),
-                        ),
-                        StackAlgorithm::Minimum => StackAlgorithmImpl::Minimum(
-                            MinimumStackBuffer::new(out_width, out_height, num_source_bands),
-                        ),
-                    },
-                );
-
-            record_chunk.iter().for_each(|fr| {
-                let frame = fr
-                    .get_calibrated_frame(context)
-                    .expect("Failed to retrieve calibrated frame");
-
-                drizzle
-                    .add_with_transform(&frame.buffer, &fr.offset, fr.computed_rotation)
-                    .expect("Failed to drizzle frame onto buffer");
-
-                on_frame_checked(fr);
-            });
-
-            drizzle
-        })
-        .collect();
-
-    // // Combines all the sub drizzle buffers into the master drizzle
-    sub_drizzles.iter().for_each(|d| {
-        master_drizzle.add_drizzle(d).unwrap();
-    });
+    for pass in 0..stack_algorithm.num_passes() {
+        if pass > 0 {
+            master_drizzle.next_pass();
+        }
+
+        let sub_drizzles = context
+            .frame_records
+            .par_chunks(num_per_chunk)
+            .map(|record_chunk| {
+                let mut drizzle = master_drizzle.empty_like()?;
+
+                for fr in record_chunk {
+                    let frame = fr.get_calibrated_frame(context)?;
+
+                    drizzle.add_with_transform_and_shift_map(
+                        &frame.buffer,
+                        &fr.offset,
+                        fr.computed_rotation,
+                        fr.shift_map.as_ref(),
+                        fr.weight,
+                    )?;
 
-    master_drizzle.get_finalized()
+                    on_frame_checked(fr);
+                }
+
+                Ok(drizzle)
+            })
+            .collect::<Result<Vec<Drizzle>>>()?;
+
+        // // Combines all the sub drizzle buffers into the master drizzle
+        for d in sub_drizzles.iter() {
+            master_drizzle.add_drizzle(d)?;
+        }
+    }
+
+    Ok(StackedImage {
+        image: master_drizzle.get_finalized()?,
+        weight_map: master_drizzle.get_weight_map()?,
+        coverage_map: master_drizzle.get_coverage_map()?,
+    })
 }
 
 pub fn process_frame_stacking_linear<C, F>(
     context: &ProcessContext<F>,
     on_frame_checked: C,
-) -> Result<Image>
+) -> Result<StackedImage>
 where
     C: Fn(&FrameRecord) + Send + Sync + 'static,
     F: DataSource + Send + Sync + 'static,
 {
     if context.frame_records.is_empty() {
-        return Err(anyhow!("No frames to stack!"));
+        return Err(SolHatError::EmptyStack.into());
     }
 
     let stack_algorithm = context.parameters.algorithm;
@@ -167,9 +205,14 @@ where
     let out_width = (in_width as f32 * context.parameters.drizzle_scale.value()).ceil() as usize;
     let out_height = (in_height as f32 * context.parameters.drizzle_scale.value()).ceil() as usize;
 
-    let num_source_bands = context.frame_records[0].num_bands(context)?;
+    // Raw CFA frames are single band mosaics which stack into three color bands
+    let cfa_pattern = context.cfa_pattern();
+    let num_source_bands = match cfa_pattern {
+        Some(_) => 3,
+        None => context.frame_records[0].num_bands(context)?,
+    };
 
-    let mut master_drizzle: BilinearDrizzle = BilinearDrizzle::new(
+    let mut master_drizzle: Drizzle = Drizzle::new(
         context
             .parameters
             .crop_width
@@ -181,36 +224,44 @@ where
         context.parameters.drizzle_scale,
         context.parameters.horiz_offset,
         context.parameters.vert_offset,
-        match stack_algorithm {
-            StackAlgorithm::Average => StackAlgorithmImpl::Average(AverageStackBuffer::new(
-                out_width,
-                out_height,
-                num_source_bands,
-            )),
-            StackAlgorithm::Median => StackAlgorithmImpl::Median(MedianStackBuffer::new(
-                out_width,
-                out_height,
-                num_source_bands,
-            )),
-            StackAlgorithm::Minimum => StackAlgorithmImpl::Minimum(MinimumStackBuffer::new(
-                out_width,
-                out_height,
-                num_source_bands,
-            )),
-        },
+        StackAlgorithmImpl::new(
+            stack_algorithm,
+            context.parameters.stack_kappa,
+            out_width,
+            out_height,
+            num_source_bands,
+        )?,
+    );
+    master_drizzle.set_cfa_pattern(cfa_pattern);
+    master_drizzle.set_kernel(
+        context.parameters.drizzle_kernel,
+        context.parameters.drizzle_pixfrac,
     );
+    master_drizzle.set_interpolation(context.parameters.interpolation);
 
-    context.frame_records.iter().for_each(|fr| {
-        let frame = fr
-            .get_calibrated_frame(context)
-            .expect("Failed to retrieve calibrated frame");
+    for pass in 0..stack_algorithm.num_passes() {
+        if pass > 0 {
+            master_drizzle.next_pass();
+        }
 
-        master_drizzle
-            .add_with_transform(&frame.buffer, &fr.offset, fr.computed_rotation)
-            .expect("Failed to drizzle frame onto buffer");
+        for fr in context.frame_records.iter() {
+            let frame = fr.get_calibrated_frame(context)?;
 
-        on_frame_checked(fr);
-    });
+            master_drizzle.add_with_transform_and_shift_map(
+                &frame.buffer,
+                &fr.offset,
+                fr.computed_rotation,
+                fr.shift_map.as_ref(),
+                fr.weight,
+            )?;
+
+            on_frame_checked(fr);
+        }
+    }
 
-    master_drizzle.get_finalized()
+    Ok(StackedImage {
+        image: master_drizzle.get_finalized()?,
+        weight_map: master_drizzle.get_weight_map()?,
+        coverage_map: master_drizzle.get_coverage_map()?,
+    })
 }
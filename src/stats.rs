@@ -1,5 +1,55 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use serde::Serialize;
 
+use crate::context::ProcessParameters;
+use crate::framerecord::FrameRecord;
+
+/// Reason a frame was excluded from the stack during frame limiting
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum DiscardReason {
+    MinSigma,
+    MaxSigma,
+    TopPercentage,
+    MaxFrames,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FrameStats {
+    pub source_file_id: String,
+    pub frame_id: usize,
+    pub sigma: f64,
+    pub computed_rotation: f64,
+    pub offset_h: f32,
+    pub offset_v: f32,
+    pub weight: f32,
+    pub used: bool,
+    pub discard_reason: Option<DiscardReason>,
+}
+
+impl FrameStats {
+    pub fn from_frame_record(fr: &FrameRecord) -> Self {
+        FrameStats {
+            source_file_id: fr.source_file_id.clone(),
+            frame_id: fr.frame_id,
+            sigma: fr.sigma,
+            computed_rotation: fr.computed_rotation,
+            offset_h: fr.offset.h,
+            offset_v: fr.offset.v,
+            weight: fr.weight,
+            used: false,
+            discard_reason: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub seconds: f64,
+}
+
 #[derive(Debug, Default, Clone, Serialize)]
 pub struct ProcessStats {
     pub total_frames: usize,
@@ -10,6 +60,74 @@ pub struct ProcessStats {
     pub num_frames_discarded_min_sigma: usize,
     pub num_frames_discarded_max_sigma: usize,
     pub num_frames_discarded_top_percentage: usize,
+    pub num_frames_discarded_max_frames: usize,
     pub initial_rotation: f32,
     pub quality_values: Vec<f32>,
+    pub frames: Vec<FrameStats>,
+    pub stage_timings: Vec<StageTiming>,
+    pub frame_cache_hits: usize,
+    pub frame_cache_misses: usize,
+}
+
+impl ProcessStats {
+    pub fn record_stage_timing(&mut self, stage: &str, elapsed: Duration) {
+        info!("Stage '{}' completed in {}s", stage, elapsed.as_secs_f64());
+        self.stage_timings.push(StageTiming {
+            stage: stage.to_owned(),
+            seconds: elapsed.as_secs_f64(),
+        });
+    }
+
+    /// Replaces the per-frame statistics with those of the supplied records, which are
+    /// assumed to be the full set of frames under consideration.
+    pub fn set_frames(&mut self, frame_records: &[FrameRecord]) {
+        self.frames = frame_records
+            .iter()
+            .map(FrameStats::from_frame_record)
+            .collect();
+        self.total_frames = self.frames.len();
+        self.quality_values = frame_records.iter().map(|fr| fr.sigma as f32).collect();
+
+        let (min_sigma, max_sigma) = frame_records
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(mn, mx), fr| {
+                (mn.min(fr.sigma as f32), mx.max(fr.sigma as f32))
+            });
+        if !frame_records.is_empty() {
+            self.min_sigma = min_sigma;
+            self.max_sigma = max_sigma;
+        }
+    }
+
+    /// Applies `f` to the statistics entry matching each of the supplied frame records.
+    /// Records without a matching entry are added.
+    pub fn update_frames<U>(&mut self, frame_records: &[FrameRecord], f: U)
+    where
+        U: Fn(&mut FrameStats, &FrameRecord),
+    {
+        let index: HashMap<(String, usize), usize> = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(i, fs)| ((fs.source_file_id.clone(), fs.frame_id), i))
+            .collect();
+
+        frame_records.iter().for_each(|fr| {
+            match index.get(&(fr.source_file_id.clone(), fr.frame_id)) {
+                Some(i) => f(&mut self.frames[*i], fr),
+                None => {
+                    let mut fs = FrameStats::from_frame_record(fr);
+                    f(&mut fs, fr);
+                    self.frames.push(fs);
+                }
+            }
+        });
+    }
+}
+
+/// Full record of a processing run, written out alongside the stacked image
+#[derive(Debug, Serialize)]
+pub struct ProcessReport<'a> {
+    pub parameters: &'a ProcessParameters,
+    pub stats: &'a ProcessStats,
 }
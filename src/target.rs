@@ -1,5 +1,6 @@
+use crate::error::SolHatError;
 use crate::{lunar, parallacticangle, solar, timestamp::TimeStamp};
-use anyhow::{Error, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default)]
@@ -30,7 +31,9 @@ impl Target {
             "MOON" => Ok(Target::Moon),
             "SUN" => Ok(Target::Sun),
             "NONE" => Ok(Target::None),
-            _ => Err(Error::msg(format!("Invalid target supplied: '{}'", s))),
+            _ => Err(
+                SolHatError::InvalidParameter(format!("Invalid target supplied: '{}'", s)).into(),
+            ),
         }
     }
 
@@ -52,7 +55,12 @@ impl Target {
                     info!("Calculating position for Sun");
                     solar::position_from_lat_lon_and_time(obs_latitude, obs_longitude, ts)
                 }
-                _ => return Err(Error::msg("Unsupported target for rotation")),
+                _ => {
+                    return Err(SolHatError::InvalidParameter(
+                        "Unsupported target for rotation".to_string(),
+                    )
+                    .into())
+                }
             };
 
             let rotation =
@@ -0,0 +1,122 @@
+// Writer of uncompressed 32-bit floating point TIFF images, for output which preserves values
+// beyond the 16-bit integer range. Specification: TIFF Revision 6.0, with SampleFormat of 3
+// (IEEE floating point).
+
+use std::fs::File;
+use std::io::Write;
+
+use anyhow::Result;
+use sciimg::image::Image;
+
+use crate::error::SolHatError;
+
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+
+const SAMPLE_FORMAT_IEEE_FLOAT: u16 = 3;
+const PHOTOMETRIC_BLACK_IS_ZERO: u16 = 1;
+const PHOTOMETRIC_RGB: u16 = 2;
+
+/// A directory entry. Values which don't fit in the four bytes of the entry are written to
+/// `extra` and referenced by offset.
+fn ifd_entry(
+    ifd: &mut Vec<u8>,
+    extra: &mut Vec<u8>,
+    extra_offset: usize,
+    tag: u16,
+    values: &[u32],
+    field_type: u16,
+) {
+    ifd.extend_from_slice(&tag.to_le_bytes());
+    ifd.extend_from_slice(&field_type.to_le_bytes());
+    ifd.extend_from_slice(&(values.len() as u32).to_le_bytes());
+
+    let mut bytes = vec![];
+    for v in values.iter() {
+        match field_type {
+            TYPE_SHORT => bytes.extend_from_slice(&(*v as u16).to_le_bytes()),
+            _ => bytes.extend_from_slice(&v.to_le_bytes()),
+        }
+    }
+
+    if bytes.len() <= 4 {
+        bytes.resize(4, 0);
+        ifd.extend_from_slice(&bytes);
+    } else {
+        ifd.extend_from_slice(&((extra_offset + extra.len()) as u32).to_le_bytes());
+        extra.extend_from_slice(&bytes);
+    }
+}
+
+/// Writes the image as a single strip, little endian, 32-bit float TIFF. One band is written
+/// as grayscale, three as RGB.
+pub fn save_float_tiff(image: &Image, output_path: &str) -> Result<()> {
+    let num_bands = image.num_bands();
+    let (samples_per_pixel, photometric) = match num_bands {
+        1 => (1, PHOTOMETRIC_BLACK_IS_ZERO),
+        3 => (3, PHOTOMETRIC_RGB),
+        _ => {
+            return Err(SolHatError::InvalidParameter(format!(
+                "Cannot write a TIFF of {} bands",
+                num_bands
+            ))
+            .into())
+        }
+    };
+
+    const HEADER_SIZE: usize = 8;
+    const NUM_ENTRIES: usize = 11;
+    let ifd_size = 2 + NUM_ENTRIES * 12 + 4;
+    let extra_offset = HEADER_SIZE + ifd_size;
+
+    // The arrays of per-sample values are the only ones which may not fit within an entry
+    let extra_size = if samples_per_pixel > 2 {
+        2 * samples_per_pixel * 2
+    } else {
+        0
+    };
+    let data_offset = extra_offset + extra_size;
+    let data_size = image.width * image.height * samples_per_pixel * 4;
+
+    let mut ifd = vec![];
+    let mut extra = vec![];
+    ifd.extend_from_slice(&(NUM_ENTRIES as u16).to_le_bytes());
+
+    let per_sample = |v: u16| vec![v as u32; samples_per_pixel];
+    let mut entry = |tag: u16, values: &[u32], field_type: u16| {
+        ifd_entry(&mut ifd, &mut extra, extra_offset, tag, values, field_type)
+    };
+
+    // Entries must be sorted by tag
+    entry(256, &[image.width as u32], TYPE_LONG); // ImageWidth
+    entry(257, &[image.height as u32], TYPE_LONG); // ImageLength
+    entry(258, &per_sample(32), TYPE_SHORT); // BitsPerSample
+    entry(259, &[1], TYPE_SHORT); // Compression: none
+    entry(262, &[photometric as u32], TYPE_SHORT); // PhotometricInterpretation
+    entry(273, &[data_offset as u32], TYPE_LONG); // StripOffsets
+    entry(277, &[samples_per_pixel as u32], TYPE_SHORT); // SamplesPerPixel
+    entry(278, &[image.height as u32], TYPE_LONG); // RowsPerStrip
+    entry(279, &[data_size as u32], TYPE_LONG); // StripByteCounts
+    entry(284, &[1], TYPE_SHORT); // PlanarConfiguration: chunky
+    entry(339, &per_sample(SAMPLE_FORMAT_IEEE_FLOAT), TYPE_SHORT); // SampleFormat
+    ifd.extend_from_slice(&0_u32.to_le_bytes()); // No further directories
+
+    let mut bytes = Vec::with_capacity(data_offset + data_size);
+    bytes.extend_from_slice(b"II");
+    bytes.extend_from_slice(&42_u16.to_le_bytes());
+    bytes.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+    bytes.extend_from_slice(&ifd);
+    bytes.extend_from_slice(&extra);
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            for band in 0..samples_per_pixel {
+                bytes.extend_from_slice(&image.get_band(band).get(x, y).to_le_bytes());
+            }
+        }
+    }
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&bytes)?;
+    Ok(())
+}
@@ -1,8 +1,11 @@
 extern crate astro;
 
+use anyhow::Result;
 use astro::*;
 use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 
+use crate::error::SolHatError;
+
 const SEPTASECONDS_PER_SECOND: u64 = 10000000;
 const SEPTASECONDS_PER_MICROSECOND: u64 = 10;
 //const SEPTASECONDS_PER_PART_MINUTE : u64 = SEPTASECONDS_PER_DAY * 6;
@@ -99,6 +102,43 @@ impl TimeStamp {
         }
     }
 
+    /// Converts a UTC date/time to the SER timestamp format, in units of 100 nanoseconds
+    /// since midnight of January 1st, year 1.
+    pub fn from_naive_datetime(date_time: &NaiveDateTime) -> TimeStamp {
+        let epoch = NaiveDate::from_ymd_opt(1, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let micros = (*date_time - epoch).num_microseconds().unwrap_or(0).max(0) as u64;
+        TimeStamp::from_u64(micros * SEPTASECONDS_PER_MICROSECOND)
+    }
+
+    /// Parses a UTC date/time such as `2024-04-08T18:20:00.5`. A space may separate the date
+    /// and time, and fractional seconds are optional.
+    pub fn parse(s: &str) -> Result<TimeStamp> {
+        ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+            .iter()
+            .find_map(|fmt| NaiveDateTime::parse_from_str(s.trim(), fmt).ok())
+            .map(|dt| TimeStamp::from_naive_datetime(&dt))
+            .ok_or_else(|| {
+                SolHatError::InvalidParameter(format!(
+                    "Invalid date/time: {}. Expected YYYY-MM-DDTHH:MM:SS",
+                    s
+                ))
+                .into()
+            })
+    }
+
+    /// Timestamp of the last modification of a file, for sources which don't record capture times
+    pub fn from_file_modified(path: &str) -> TimeStamp {
+        match std::fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => {
+                TimeStamp::from_naive_datetime(&DateTime::<Utc>::from(modified).naive_utc())
+            }
+            Err(_) => TimeStamp::from_u64(0),
+        }
+    }
+
     pub fn to_julian_day(&self) -> f64 {
         let day_of_month = time::DayOfMonth {
             day: self.day as u8,
@@ -0,0 +1,115 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::context::ProcessContext;
+use crate::datasource::DataSource;
+use crate::error::SolHatError;
+use crate::framerecord::FrameRecord;
+
+/// How much each frame contributes to the stack, as a function of its quality (sigma). Weights
+/// are scaled so the best frame has a weight of one.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum FrameWeighting {
+    #[default]
+    Equal, // Every frame contributes fully
+    Linear,     // Proportional to the sigma
+    Power(f32), // Proportional to the sigma raised to the exponent
+    Rank,       // Falls off linearly with the position of the frame when ordered by sigma
+}
+
+impl FrameWeighting {
+    pub fn from(s: &str) -> Result<FrameWeighting> {
+        let invalid = || -> anyhow::Error {
+            SolHatError::InvalidParameter(format!(
+                "Invalid frame weighting: {}. Valid options: equal, linear, rank, power, or power:<exponent>",
+                s
+            ))
+            .into()
+        };
+        match s.to_uppercase().as_str() {
+            "EQUAL" | "NONE" => Ok(FrameWeighting::Equal),
+            "LINEAR" => Ok(FrameWeighting::Linear),
+            "RANK" => Ok(FrameWeighting::Rank),
+            "POWER" => Ok(FrameWeighting::Power(2.0)),
+            u => match u.strip_prefix("POWER:").map(|e| e.parse::<f32>()) {
+                Some(Ok(exponent)) if exponent > 0.0 => Ok(FrameWeighting::Power(exponent)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// Computes the weight of each of the supplied frame sigmas, in the same order
+pub fn compute_frame_weights(sigmas: &[f64], weighting: FrameWeighting) -> Vec<f32> {
+    let max_sigma = sigmas.iter().fold(0.0_f64, |m, s| m.max(*s));
+
+    // Without a usable quality measure, there's nothing to tell the frames apart by
+    if max_sigma <= 0.0 {
+        return vec![1.0; sigmas.len()];
+    }
+
+    match weighting {
+        FrameWeighting::Equal => vec![1.0; sigmas.len()],
+        FrameWeighting::Linear => sigmas
+            .iter()
+            .map(|s| (s.max(0.0) / max_sigma) as f32)
+            .collect(),
+        FrameWeighting::Power(exponent) => sigmas
+            .iter()
+            .map(|s| ((s.max(0.0) / max_sigma) as f32).powf(exponent))
+            .collect(),
+        FrameWeighting::Rank => {
+            // Frames without a quality measure rank last
+            let key = |i: usize| {
+                if sigmas[i].is_nan() {
+                    f64::MIN
+                } else {
+                    sigmas[i]
+                }
+            };
+            let mut order: Vec<usize> = (0..sigmas.len()).collect();
+            order.sort_by(|a, b| key(*b).total_cmp(&key(*a)));
+            let n = sigmas.len() as f32;
+            let mut weights = vec![0.0; sigmas.len()];
+            order
+                .iter()
+                .enumerate()
+                .for_each(|(rank, i)| weights[*i] = (n - rank as f32) / n);
+            weights
+        }
+    }
+}
+
+/// Assigns each frame its stacking weight according to the frame weighting parameter
+pub fn frame_weight_determinate<C, F>(
+    context: &mut ProcessContext<F>,
+    on_frame_checked: C,
+) -> Result<Vec<FrameRecord>>
+where
+    C: Fn(&FrameRecord) + Send + Sync + 'static,
+    F: DataSource,
+{
+    let t = Instant::now();
+    let sigmas: Vec<f64> = context.frame_records.iter().map(|fr| fr.sigma).collect();
+    let weights = compute_frame_weights(&sigmas, context.parameters.frame_weighting);
+
+    let frame_records: Vec<FrameRecord> = context
+        .frame_records
+        .iter()
+        .zip(weights.iter())
+        .map(|(fr, w)| {
+            let mut fr_copy = fr.clone();
+            fr_copy.weight = *w;
+            on_frame_checked(&fr_copy);
+            fr_copy
+        })
+        .collect();
+
+    context.stats.update_frames(&frame_records, |fs, fr| {
+        fs.weight = fr.weight;
+    });
+    context.stats.record_stage_timing("weighting", t.elapsed());
+    Ok(frame_records)
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::alignmentpoints::{compute_shift_map, frame_alignment_point_analysis, ShiftMap};
+
+const SIZE: usize = 160;
+const TILE_SIZE: usize = 32;
+const SEARCH_RADIUS: usize = 4;
+
+// Content right of this column is moved by (2, -1) in the locally shifted frame
+const SHIFT_BOUNDARY: usize = 64;
+
+/// Positions of the texture spots, on a jittered 10 pixel grid. None are placed near the
+/// shift boundary so that the tiles on either side of it see clean shifts.
+fn spots() -> Vec<(f32, f32)> {
+    let mut seed: u32 = 12345;
+    let mut jitter = || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        ((seed >> 16) % 5) as f32
+    };
+
+    let mut spots = vec![];
+    for gy in 0..17 {
+        for gx in 0..17 {
+            let x = 4.0 + gx as f32 * 10.0 + jitter();
+            let y = 4.0 + gy as f32 * 10.0 + jitter();
+            if (x - SHIFT_BOUNDARY as f32).abs() >= 10.0 {
+                spots.push((x, y));
+            }
+        }
+    }
+    spots
+}
+
+fn texture(spots: &[(f32, f32)], x: f32, y: f32) -> f32 {
+    8000.0
+        + spots
+            .iter()
+            .map(|(sx, sy)| 15000.0 * (-((x - sx).powi(2) + (y - sy).powi(2)) / 8.0).exp())
+            .sum::<f32>()
+}
+
+fn textured_frame(local_shift: bool) -> Result<Image> {
+    let spots = spots();
+    let mut image = Image::new_with_bands(SIZE, SIZE, 1, ImageMode::U16BIT)?;
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let (sx, sy) = if local_shift && x >= SHIFT_BOUNDARY {
+                (x as f32 - 2.0, y as f32 + 1.0)
+            } else {
+                (x as f32, y as f32)
+            };
+            image.put(x, y, texture(&spots, sx, sy).round(), 0);
+        }
+    }
+    Ok(image)
+}
+
+/// Checks that the points left of the boundary measured no shift and those right of it
+/// measured (2, -1). Points too close to the edge for the search area must be invalid.
+fn assert_local_shifts(shift_map: &ShiftMap, shifted: bool) {
+    for gy in 0..shift_map.grid_height {
+        for gx in 0..shift_map.grid_width {
+            let ap = shift_map.get(gx, gy);
+            let interior = (1..4).contains(&gx) && (1..4).contains(&gy);
+            assert_eq!(ap.valid, interior, "point {},{}", gx, gy);
+            if !interior {
+                continue;
+            }
+
+            let (h, v) = if shifted && ap.x >= SHIFT_BOUNDARY {
+                (2.0, -1.0)
+            } else {
+                (0.0, 0.0)
+            };
+            assert!(
+                (ap.shift_h - h).abs() < 0.2 && (ap.shift_v - v).abs() < 0.2,
+                "point {},{} shift {},{}",
+                gx,
+                gy,
+                ap.shift_h,
+                ap.shift_v
+            );
+        }
+    }
+}
+
+#[test]
+fn test_compute_shift_map_recovers_local_shift() -> Result<()> {
+    let reference = textured_frame(false)?;
+    let shifted = textured_frame(true)?;
+
+    let shift_map = compute_shift_map(
+        reference.get_band(0),
+        shifted.get_band(0),
+        TILE_SIZE,
+        SEARCH_RADIUS,
+        5000.0,
+    );
+    assert_eq!((shift_map.grid_width, shift_map.grid_height), (5, 5));
+    assert_local_shifts(&shift_map, true);
+
+    // The background is skipped
+    let shift_map = compute_shift_map(
+        reference.get_band(0),
+        shifted.get_band(0),
+        TILE_SIZE,
+        SEARCH_RADIUS,
+        50000.0,
+    );
+    assert!(shift_map.points.iter().all(|ap| !ap.valid));
+    Ok(())
+}
+
+#[test]
+fn test_alignment_point_analysis() -> Result<()> {
+    let frames = vec![
+        textured_frame(false)?,
+        textured_frame(false)?,
+        textured_frame(true)?,
+    ];
+    let path = common::write_ser("alignmentpoints", &frames)?;
+
+    let mut params = common::parameters(&[path], 0);
+    params.ap_tile_size = Some(TILE_SIZE);
+    params.ap_search_radius = SEARCH_RADIUS;
+    params.ap_top_percentage = Some(50.0);
+    params.ap_reference_frames = 2;
+    let mut context = common::context(&params)?;
+
+    let frame_records = frame_alignment_point_analysis(&mut context, |_| {})?;
+    assert_eq!(frame_records.len(), 3);
+
+    let shift_maps: Vec<&ShiftMap> = frame_records
+        .iter()
+        .map(|fr| fr.shift_map.as_ref().unwrap())
+        .collect();
+    assert_local_shifts(shift_maps[0], false);
+    assert_local_shifts(shift_maps[1], false);
+    assert_local_shifts(shift_maps[2], true);
+
+    // At least the top half of the frames is accepted at each measured point
+    for i in 0..shift_maps[0].points.len() {
+        if !shift_maps[0].points[i].valid {
+            continue;
+        }
+        let accepted = shift_maps.iter().filter(|sm| sm.points[i].accepted).count();
+        assert!(accepted >= 2, "point {} accepted by {} frames", i, accepted);
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+// Synthetic inputs shared by the tests which run pipeline stages over a process context
+
+#![allow(dead_code)]
+
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::calibrationframe::CalibrationImage;
+use solhat::context::{ProcessContext, ProcessParameters};
+use solhat::datasource::{ColorFormatId, PixelFormat};
+use solhat::demosaic::DemosaicMethod;
+use solhat::drizzle::{DrizzleKernel, Scale, StackAlgorithm};
+use solhat::interpolation::InterpolationMethod;
+use solhat::offsetting::RegistrationMethod;
+use solhat::output::OutputScaling;
+use solhat::serwriter::SerWriter;
+use solhat::sharpen::SharpenParameters;
+use solhat::target::Target;
+use solhat::timestamp::TimeStamp;
+use solhat::weighting::FrameWeighting;
+
+pub const DISK_VALUE: f32 = 20000.0;
+pub const SKY_VALUE: f32 = 100.0;
+
+/// A uniform disk on a dark sky, centered offset from the middle of the frame
+pub fn disk_frame(size: usize, radius: f32, dx: f32, dy: f32) -> Result<Image> {
+    let mut image = Image::new_with_bands(size, size, 1, ImageMode::U16BIT)?;
+    let c = size as f32 / 2.0;
+    for y in 0..size {
+        for x in 0..size {
+            let r = ((x as f32 - c - dx).powi(2) + (y as f32 - c - dy).powi(2)).sqrt();
+            image.put(x, y, if r <= radius { DISK_VALUE } else { SKY_VALUE }, 0);
+        }
+    }
+    Ok(image)
+}
+
+/// Writes the mono frames to a 16-bit SER in the temp directory, 1ms apart
+pub fn write_ser(name: &str, frames: &[Image]) -> Result<String> {
+    let path = std::env::temp_dir()
+        .join(format!("solhat_test_{}.ser", name))
+        .to_string_lossy()
+        .to_string();
+    let mut writer = SerWriter::create(
+        &path,
+        ColorFormatId::Mono,
+        frames[0].width,
+        frames[0].height,
+        16,
+    )?;
+    for (i, frame) in frames.iter().enumerate() {
+        writer.write_frame(frame, &TimeStamp::from_u64(i as u64 * 10000))?;
+    }
+    writer.finish()?;
+    Ok(path)
+}
+
+/// Writes a mono, 16-bit SER of disks, frame `i` shifted by `i * shift` pixels to the right
+pub fn write_disk_ser(name: &str, size: usize, frames: usize, shift: f32) -> Result<String> {
+    let frames = (0..frames)
+        .map(|i| disk_frame(size, size as f32 / 4.0, i as f32 * shift, 0.0))
+        .collect::<Result<Vec<Image>>>()?;
+    write_ser(name, &frames)
+}
+
+pub fn parameters(input_files: &[String], frame_cache_mb: usize) -> ProcessParameters {
+    ProcessParameters {
+        input_files: input_files.to_vec(),
+        obj_detection_threshold: 5000.0,
+        obs_latitude: 0.0,
+        obs_longitude: 0.0,
+        target: Target::Sun,
+        crop_width: None,
+        crop_height: None,
+        max_frames: None,
+        min_sigma: None,
+        max_sigma: None,
+        top_percentage: None,
+        drizzle_scale: Scale::default(),
+        drizzle_kernel: DrizzleKernel::default(),
+        drizzle_pixfrac: 1.0,
+        interpolation: InterpolationMethod::default(),
+        algorithm: StackAlgorithm::Average,
+        stack_kappa: 2.5,
+        initial_rotation: 0.0,
+        flat_inputs: None,
+        dark_inputs: None,
+        darkflat_inputs: None,
+        bias_inputs: None,
+        hot_pixel_map: None,
+        analysis_window_size: 128,
+        vert_offset: 0,
+        horiz_offset: 0,
+        ap_tile_size: None,
+        ap_search_radius: 8,
+        ap_top_percentage: None,
+        ap_reference_frames: 10,
+        registration: RegistrationMethod::CenterOfMass,
+        demosaic_method: DemosaicMethod::default(),
+        pixel_format: PixelFormat::default(),
+        frame_cache_mb,
+        output_scaling: OutputScaling::default(),
+        frame_weighting: FrameWeighting::default(),
+        sharpen: SharpenParameters::default(),
+    }
+}
+
+/// A context over the inputs without calibration frames
+pub fn context(parameters: &ProcessParameters) -> Result<ProcessContext> {
+    ProcessContext::create_with_calibration_frames(
+        parameters,
+        CalibrationImage::new_empty(),
+        CalibrationImage::new_empty(),
+        CalibrationImage::new_empty(),
+        CalibrationImage::new_empty(),
+    )
+}
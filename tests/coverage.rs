@@ -0,0 +1,73 @@
+use anyhow::Result;
+use sciimg::imagebuffer::Offset;
+use sciimg::prelude::*;
+use solhat::coverage::{fully_covered_region, Region};
+use solhat::drizzle::{Drizzle, DrizzleKernel, Scale, StackAlgorithm, StackAlgorithmImpl};
+
+fn flat_image(value: f32) -> Result<Image> {
+    let mut image = Image::new_with_bands(16, 16, 1, ImageMode::U16BIT)?;
+    for y in 0..16 {
+        for x in 0..16 {
+            image.put(x, y, value, 0);
+        }
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_shifted_frames_coverage() -> Result<()> {
+    for kernel in [DrizzleKernel::Square, DrizzleKernel::Interpolate] {
+        let mut drizzle = Drizzle::new(
+            16,
+            16,
+            Scale::default(),
+            0,
+            0,
+            StackAlgorithmImpl::new(StackAlgorithm::Average, 2.5, 16, 16, 1)?,
+        );
+        drizzle.set_kernel(kernel, 1.0);
+        drizzle.add_with_transform(&flat_image(100.0)?, &Offset { h: 0.0, v: 0.0 }, 0.0)?;
+        drizzle.add_with_transform(&flat_image(100.0)?, &Offset { h: 4.0, v: 2.0 }, 0.0)?;
+
+        // Pixels covered by a single frame are not darkened by the other
+        let stacked = drizzle.get_finalized()?;
+        assert!((stacked.get_band(0).get(1, 1) - 100.0).abs() < 0.01);
+
+        let coverage = drizzle.get_coverage_map()?;
+        assert_eq!(coverage.get_band(0).get(1, 1), 1.0);
+        assert_eq!(coverage.get_band(0).get(8, 8), 2.0);
+
+        assert_eq!(
+            fully_covered_region(&coverage, 2),
+            Some(Region {
+                x: 4,
+                y: 2,
+                width: 12,
+                height: 14
+            })
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_no_region_covered_by_every_frame() -> Result<()> {
+    let mut drizzle = Drizzle::new(
+        16,
+        16,
+        Scale::default(),
+        0,
+        0,
+        StackAlgorithmImpl::new(StackAlgorithm::Average, 2.5, 16, 16, 1)?,
+    );
+    drizzle.set_kernel(DrizzleKernel::Square, 1.0);
+    drizzle.add_with_transform(&flat_image(100.0)?, &Offset { h: 0.0, v: 0.0 }, 0.0)?;
+    drizzle.add_with_transform(&flat_image(100.0)?, &Offset { h: 16.0, v: 0.0 }, 0.0)?;
+
+    // The second frame is shifted clear of the first, leaving no pixel covered by both
+    let coverage = drizzle.get_coverage_map()?;
+    assert_eq!(coverage.get_band(0).get(1, 1), 1.0);
+    assert_eq!(fully_covered_region(&coverage, 2), None);
+    assert!(fully_covered_region(&coverage, 1).is_some());
+    Ok(())
+}
@@ -0,0 +1,382 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::datasource::{open_data_source, ColorFormatId, DataSource};
+use solhat::error::SolHatError;
+use solhat::fits::{save_fits, FitsDataType, FitsKeyword, FitsValue};
+
+const WIDTH: usize = 6;
+const HEIGHT: usize = 4;
+const FRAMES: usize = 4;
+
+fn value(frame: usize, x: usize, y: usize) -> u8 {
+    (frame * 50 + y * 10 + x) as u8
+}
+
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(name)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut c = id.to_vec();
+    c.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    c.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        c.push(0);
+    }
+    c
+}
+
+fn list(list_type: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+    let mut body = list_type.to_vec();
+    children.iter().for_each(|c| body.extend_from_slice(c));
+    chunk(b"LIST", &body)
+}
+
+/// Writes an AVI of the video format and frames, with an audio stream ahead of the video stream
+fn write_avi(name: &str, compression: &[u8; 4], strf: &[u8], frames: &[Vec<u8>]) -> Result<String> {
+    let mut avih = vec![0; 56];
+    avih[0..4].copy_from_slice(&20000_u32.to_le_bytes());
+
+    let mut audio_strh = b"auds".to_vec();
+    audio_strh.resize(56, 0);
+    let mut video_strh = b"vids".to_vec();
+    video_strh.extend_from_slice(compression);
+    video_strh.resize(56, 0);
+
+    let hdrl = list(
+        b"hdrl",
+        &[
+            chunk(b"avih", &avih),
+            list(
+                b"strl",
+                &[chunk(b"strh", &audio_strh), chunk(b"strf", &[0; 18])],
+            ),
+            list(
+                b"strl",
+                &[chunk(b"strh", &video_strh), chunk(b"strf", strf)],
+            ),
+        ],
+    );
+
+    let mut movi_chunks = vec![chunk(b"00wb", &[1, 2, 3])];
+    frames
+        .iter()
+        .for_each(|frame| movi_chunks.push(chunk(b"01db", frame)));
+    let movi = list(b"movi", &movi_chunks);
+
+    let mut riff_body = b"AVI ".to_vec();
+    riff_body.extend_from_slice(&hdrl);
+    riff_body.extend_from_slice(&movi);
+
+    let path = temp_path(&format!("solhat_test_{}.avi", name));
+    std::fs::write(&path, chunk(b"RIFF", &riff_body))?;
+    Ok(path)
+}
+
+/// The BITMAPINFOHEADER of 8-bit frames
+fn bitmap_info(compression: &[u8; 4], palette: &[[u8; 4]]) -> Vec<u8> {
+    let mut strf = vec![];
+    strf.extend_from_slice(&40_u32.to_le_bytes());
+    strf.extend_from_slice(&(WIDTH as i32).to_le_bytes());
+    strf.extend_from_slice(&(HEIGHT as i32).to_le_bytes());
+    strf.extend_from_slice(&1_u16.to_le_bytes());
+    strf.extend_from_slice(&8_u16.to_le_bytes());
+    strf.extend_from_slice(compression);
+    strf.resize(32, 0);
+    strf.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+    strf.resize(40, 0);
+    palette
+        .iter()
+        .for_each(|entry| strf.extend_from_slice(entry));
+    strf
+}
+
+/// Writes an uncompressed Y800 AVI
+fn write_test_avi(name: &str) -> Result<String> {
+    let frames: Vec<Vec<u8>> = (0..FRAMES)
+        .map(|f| pixels(HEIGHT, WIDTH).map(|(y, x)| value(f, x, y)).collect())
+        .collect();
+    write_avi(name, b"Y800", &bitmap_info(b"Y800", &[]), &frames)
+}
+
+/// Writes a palettized BI_RGB AVI, of bottom-up rows padded to four bytes
+fn write_palettized_avi(name: &str, palette: &[[u8; 4]]) -> Result<String> {
+    let stride = WIDTH.div_ceil(4) * 4;
+    let frames: Vec<Vec<u8>> = (0..FRAMES)
+        .map(|f| {
+            (0..HEIGHT)
+                .rev()
+                .flat_map(|y| {
+                    let mut row: Vec<u8> = (0..WIDTH).map(|x| value(f, x, y)).collect();
+                    row.resize(stride, 0);
+                    row
+                })
+                .collect()
+        })
+        .collect();
+    write_avi(name, &[0; 4], &bitmap_info(&[0; 4], palette), &frames)
+}
+
+fn pixels(height: usize, width: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..height).flat_map(move |y| (0..width).map(move |x| (y, x)))
+}
+
+fn card(text: &str) -> Vec<u8> {
+    format!("{:<80}", text).into_bytes()
+}
+
+/// Writes an unsigned 16-bit FITS cube with the rows in the standard bottom-up order
+fn write_test_fits(name: &str) -> Result<String> {
+    write_test_fits_cube(name, FRAMES, &[])
+}
+
+fn write_test_fits_cube(name: &str, planes: usize, extra_cards: &[&str]) -> Result<String> {
+    let mut cards = vec![
+        "SIMPLE  =                    T".to_string(),
+        "BITPIX  =                   16".to_string(),
+        "NAXIS   =                    3".to_string(),
+        format!("NAXIS1  = {:>20}", WIDTH),
+        format!("NAXIS2  = {:>20}", HEIGHT),
+        format!("NAXIS3  = {:>20}", planes),
+        "BZERO   =                32768".to_string(),
+        "ROWORDER= 'BOTTOM-UP'          / Order of the rows".to_string(),
+        "DATE-OBS= '2024-04-08T18:20:00.5'".to_string(),
+    ];
+    cards.extend(extra_cards.iter().map(|c| c.to_string()));
+    cards.push("END".to_string());
+
+    let mut bytes = vec![];
+    cards.iter().for_each(|c| bytes.extend(card(c)));
+    bytes.resize(2880, b' ');
+
+    for f in 0..planes {
+        for y in (0..HEIGHT).rev() {
+            for x in 0..WIDTH {
+                let v = (value(f, x, y) as i32 * 100 - 32768) as i16;
+                bytes.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+    bytes.resize(bytes.len().div_ceil(2880) * 2880, 0);
+
+    let path = temp_path(&format!("solhat_test_{}.fits", name));
+    std::fs::write(&path, bytes)?;
+    Ok(path)
+}
+
+#[test]
+fn test_avi_data_source() -> Result<()> {
+    let source = open_data_source(&[write_test_avi("single")?])?;
+    source.validate()?;
+
+    assert_eq!(source.file_id(), "AVI");
+    assert_eq!(source.color_id(), ColorFormatId::Mono);
+    assert_eq!(
+        (source.image_width(), source.image_height()),
+        (WIDTH, HEIGHT)
+    );
+    assert_eq!(source.frame_count(), FRAMES);
+
+    let frame = source.get_frame(2)?;
+    assert_eq!(frame.buffer.get_band(0).get(4, 1), value(2, 4, 1) as f32);
+    Ok(())
+}
+
+#[test]
+fn test_fits_cube_data_source() -> Result<()> {
+    let source = open_data_source(&[write_test_fits("cube")?])?;
+    source.validate()?;
+
+    assert_eq!(source.file_id(), "FITS");
+    assert_eq!(
+        (source.image_width(), source.image_height()),
+        (WIDTH, HEIGHT)
+    );
+    assert_eq!(source.frame_count(), FRAMES);
+    assert_eq!(source.date_time().year, 2024);
+    assert_eq!(source.date_time().hour, 18);
+
+    let frame = source.get_frame(1)?;
+    assert_eq!(
+        frame.buffer.get_band(0).get(4, 1),
+        value(1, 4, 1) as f32 * 100.0
+    );
+    Ok(())
+}
+
+#[test]
+fn test_fits_output_round_trip() -> Result<()> {
+    let mut image = Image::new_with_bands(WIDTH, HEIGHT, 1, ImageMode::U16BIT)?;
+    for (y, x) in pixels(HEIGHT, WIDTH) {
+        image.put(x, y, value(3, x, y) as f32 * 100.0, 0);
+    }
+
+    for (name, data_type) in [
+        ("f32", FitsDataType::Float32),
+        ("u16", FitsDataType::UInt16),
+    ] {
+        let path = temp_path(&format!("solhat_test_output_{}.fits", name));
+        save_fits(
+            &image,
+            &path,
+            data_type,
+            &[FitsKeyword::new(
+                "DATE-OBS",
+                FitsValue::String("2024-04-08T18:20:00.500".to_string()),
+                "Start of observation (UTC)",
+            )],
+        )?;
+
+        let source = open_data_source(&[path])?;
+        source.validate()?;
+        assert_eq!(source.frame_count(), 1);
+        assert_eq!(source.date_time().minute, 20);
+
+        let frame = source.get_frame(0)?;
+        assert_eq!(
+            frame.buffer.get_band(0).get(4, 1),
+            value(3, 4, 1) as f32 * 100.0
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_unsupported_data_source() {
+    assert!(open_data_source(&["capture.mov".to_string()]).is_err());
+}
+
+#[test]
+fn test_multi_source() -> Result<()> {
+    let avi = write_test_avi("multi")?;
+    let source = open_data_source(&[avi.clone(), avi])?;
+    source.validate()?;
+    assert_eq!(source.frame_count(), FRAMES * 2);
+
+    let frame = source.get_frame(FRAMES + 3)?;
+    assert_eq!(frame.buffer.get_band(0).get(1, 2), value(3, 1, 2) as f32);
+    Ok(())
+}
+
+#[test]
+fn test_multi_source_rejects_mismatched_inputs() -> Result<()> {
+    // Same frame size, but 8-bit AVI and 16-bit FITS
+    let source = open_data_source(&[write_test_avi("mismatch")?, write_test_fits("mismatch")?])?;
+    let err = source.validate().unwrap_err().to_string();
+    assert!(err.contains("pixel depth"), "{}", err);
+    Ok(())
+}
+
+#[test]
+fn test_palettized_avi_data_source() -> Result<()> {
+    // A grey palette which inverts the indices
+    let inverted: Vec<[u8; 4]> = (0..=255).map(|i| [255 - i, 255 - i, 255 - i, 0]).collect();
+    let source = open_data_source(&[write_palettized_avi("inverted", &inverted)?])?;
+    assert_eq!(source.color_id(), ColorFormatId::Mono);
+    let frame = source.get_frame(2)?;
+    assert_eq!(
+        frame.buffer.get_band(0).get(4, 1),
+        (255 - value(2, 4, 1)) as f32
+    );
+
+    let identity: Vec<[u8; 4]> = (0..=255).map(|i| [i, i, i, 0]).collect();
+    let source = open_data_source(&[write_palettized_avi("identity", &identity)?])?;
+    let frame = source.get_frame(2)?;
+    assert_eq!(frame.buffer.get_band(0).get(4, 1), value(2, 4, 1) as f32);
+
+    let color = [[0, 0, 255, 0], [255, 0, 0, 0]];
+    let err = open_data_source(&[write_palettized_avi("color", &color)?])
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::Format(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_fits_three_plane_cube() -> Result<()> {
+    // Without a hint that the planes are colors, they are frames
+    let source = open_data_source(&[write_test_fits_cube("planes", 3, &[])?])?;
+    assert_eq!(source.color_id(), ColorFormatId::Mono);
+    assert_eq!(source.frame_count(), 3);
+
+    let source = open_data_source(&[write_test_fits_cube("rgb", 3, &["CTYPE3  = 'RGB     '"])?])?;
+    source.validate()?;
+    assert_eq!(source.color_id(), ColorFormatId::Rgb);
+    assert_eq!(source.frame_count(), 1);
+    let frame = source.get_frame(0)?;
+    assert_eq!(
+        frame.buffer.get_band(2).get(4, 1),
+        value(2, 4, 1) as f32 * 100.0
+    );
+
+    // RGB output is read back as such
+    let path = temp_path("solhat_test_output_rgb.fits");
+    save_fits(&frame.buffer, &path, FitsDataType::UInt16, &[])?;
+    let source = open_data_source(&[path])?;
+    assert_eq!(source.color_id(), ColorFormatId::Rgb);
+    assert_eq!(source.frame_count(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_image_sequence_data_source() -> Result<()> {
+    let dir = std::env::temp_dir().join("solhat_test_sequence");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+
+    // Frames are ordered by file name, and files which aren't images are skipped
+    for f in 0..3 {
+        let image = Image::new_with_bands_and_fill(
+            WIDTH,
+            HEIGHT,
+            1,
+            ImageMode::U8BIT,
+            (f + 1) as f32 * 50.0,
+        )?;
+        image.save(&dir.join(format!("frame_{}.png", f)).to_string_lossy())?;
+    }
+    std::fs::write(dir.join("notes.txt"), "not a frame")?;
+
+    let source = open_data_source(&[dir.to_string_lossy().to_string()])?;
+    source.validate()?;
+    assert_eq!(source.file_id(), "IMAGES");
+    assert_eq!(source.color_id(), ColorFormatId::Mono);
+    assert_eq!(
+        (source.image_width(), source.image_height()),
+        (WIDTH, HEIGHT)
+    );
+    assert_eq!(source.frame_count(), 3);
+
+    let levels = (0..3)
+        .map(|f| Ok(source.get_frame(f)?.buffer.get_band(0).get(1, 1)))
+        .collect::<Result<Vec<f32>>>()?;
+    assert!(
+        levels[0] < levels[1] && levels[1] < levels[2],
+        "{:?}",
+        levels
+    );
+
+    let err = source.get_frame(3).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::FrameOutOfRange {
+            frame: 3,
+            frame_count: 3
+        })
+    ));
+
+    // A frame of another size is rejected when read
+    Image::new_with_bands(WIDTH + 1, HEIGHT, 1, ImageMode::U8BIT)?
+        .save(&dir.join("frame_1.png").to_string_lossy())?;
+    assert!(source.get_frame(1).is_err());
+
+    std::fs::remove_file(dir.join("frame_2.png"))?;
+    assert!(source.validate().is_err());
+    Ok(())
+}
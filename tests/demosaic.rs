@@ -0,0 +1,72 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::datasource::ColorFormatId;
+use solhat::demosaic::{self, DemosaicMethod};
+
+/// Builds a mosaic of a uniformly red scene for the given pattern phase of the red pixel
+fn red_mosaic(red_x: usize, red_y: usize) -> Result<ImageBuffer> {
+    let mut buffer = ImageBuffer::new_as_mode(16, 16, ImageMode::U16BIT)?;
+    for y in 0..16 {
+        for x in 0..16 {
+            if x % 2 == red_x && y % 2 == red_y {
+                buffer.put(x, y, 1000.0);
+            }
+        }
+    }
+    Ok(buffer)
+}
+
+#[test]
+fn test_demosaic_all_bayer_phases() -> Result<()> {
+    for (color_id, red_x, red_y) in [
+        (ColorFormatId::BayerRggb, 0, 0),
+        (ColorFormatId::BayerGrbg, 1, 0),
+        (ColorFormatId::BayerGbrg, 0, 1),
+        (ColorFormatId::BayerBggr, 1, 1),
+    ] {
+        let rgb = demosaic::demosaic(&red_mosaic(red_x, red_y)?, color_id, DemosaicMethod::Malvar)?;
+        assert_eq!(rgb.num_bands(), 3);
+        assert_eq!(rgb.width, 16);
+        assert_eq!(rgb.height, 16);
+
+        let r = rgb.get_band(0).get(8, 8);
+        let g = rgb.get_band(1).get(8, 8);
+        let b = rgb.get_band(2).get(8, 8);
+        assert!(r > g && r > b, "{:?}: r={}, g={}, b={}", color_id, r, g, b);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_demosaic_rejects_non_mosaic() -> Result<()> {
+    let buffer = red_mosaic(0, 0)?;
+    assert!(demosaic::demosaic(&buffer, ColorFormatId::Mono, DemosaicMethod::Malvar).is_err());
+    assert!(demosaic::demosaic(&buffer, ColorFormatId::Rgb, DemosaicMethod::Raw).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_demosaic_half_resolution_methods() -> Result<()> {
+    let buffer = red_mosaic(1, 1)?;
+
+    let rgb = demosaic::demosaic(
+        &buffer,
+        ColorFormatId::BayerBggr,
+        DemosaicMethod::SuperPixel,
+    )?;
+    assert_eq!(rgb.num_bands(), 3);
+    assert_eq!((rgb.width, rgb.height), (8, 8));
+    assert_eq!(rgb.get_band(0).get(4, 4), 1000.0);
+    assert_eq!(rgb.get_band(1).get(4, 4), 0.0);
+    assert_eq!(rgb.get_band(2).get(4, 4), 0.0);
+
+    let red = demosaic::demosaic(
+        &buffer,
+        ColorFormatId::BayerBggr,
+        DemosaicMethod::RedChannel,
+    )?;
+    assert_eq!(red.num_bands(), 1);
+    assert_eq!((red.width, red.height), (8, 8));
+    assert_eq!(red.get_band(0).get(4, 4), 1000.0);
+    Ok(())
+}
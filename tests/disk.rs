@@ -0,0 +1,71 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::disk::fit_disk;
+use solhat::interpolation::InterpolationMethod;
+
+// A limb darkened disk with a soft edge on a dark background, with a bright prominence
+// outside of the limb. The steepest fall of the limb lies a fraction of a pixel inside of the
+// radius, darkened as it is.
+fn disk_buffer(width: usize, height: usize, cx: f32, cy: f32, radius: f32) -> Result<ImageBuffer> {
+    let mut buffer = ImageBuffer::new_as_mode(width, height, ImageMode::U16BIT)?;
+    for y in 0..height {
+        for x in 0..width {
+            let r = ((x as f32 - cx).powi(2) + (y as f32 - cy).powi(2)).sqrt();
+            let mu = (1.0 - (r / radius).min(1.0).powi(2)).sqrt();
+            let edge = 1.0 / (1.0 + ((r - radius) / 0.7).exp());
+            let v = 200.0 + 30000.0 * (0.4 + 0.6 * mu) * edge;
+            buffer.put(x, y, v);
+        }
+    }
+    for y in (cy as usize - 4)..(cy as usize + 4) {
+        for x in (cx + radius) as usize + 1..(cx + radius) as usize + 12 {
+            if x < width {
+                buffer.put(x, y, 9000.0);
+            }
+        }
+    }
+    Ok(buffer)
+}
+
+#[test]
+fn test_fit_centered_disk() -> Result<()> {
+    let buffer = disk_buffer(200, 200, 101.3, 98.6, 70.0)?;
+    for method in [
+        InterpolationMethod::Nearest,
+        InterpolationMethod::Bilinear,
+        InterpolationMethod::Bicubic,
+        InterpolationMethod::Lanczos3,
+    ] {
+        let disk = fit_disk(&buffer, None, method)?;
+        assert!(
+            (disk.x - 101.3).abs() < 0.5,
+            "{:?} x was {}",
+            method,
+            disk.x
+        );
+        assert!((disk.y - 98.6).abs() < 0.5, "{:?} y was {}", method, disk.y);
+        assert!(
+            (disk.radius - 70.0).abs() < 1.0,
+            "{:?} radius was {}",
+            method,
+            disk.radius
+        );
+        assert!(disk.radius_err < 0.5);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_fit_partial_disk() -> Result<()> {
+    // A third of the disk lies beyond the right edge of the frame
+    let buffer = disk_buffer(200, 200, 160.0, 100.0, 60.0)?;
+    let disk = fit_disk(&buffer, Some(5000.0), InterpolationMethod::default())?;
+    assert!((disk.x - 160.0).abs() < 0.5, "x was {}", disk.x);
+    assert!((disk.y - 100.0).abs() < 0.5, "y was {}", disk.y);
+    assert!(
+        (disk.radius - 60.0).abs() < 1.0,
+        "radius was {}",
+        disk.radius
+    );
+    Ok(())
+}
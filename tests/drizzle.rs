@@ -0,0 +1,114 @@
+use anyhow::Result;
+use sciimg::imagebuffer::Offset;
+use sciimg::prelude::*;
+use solhat::drizzle::{Drizzle, DrizzleKernel, Scale, StackAlgorithm, StackAlgorithmImpl};
+
+fn ramp_image(width: usize, height: usize) -> Result<Image> {
+    let mut image = Image::new_with_bands(width, height, 1, ImageMode::U16BIT)?;
+    for y in 0..height {
+        for x in 0..width {
+            image.put(x, y, (x * 100) as f32, 0);
+        }
+    }
+    Ok(image)
+}
+
+fn drizzle(kernel: DrizzleKernel, pixfrac: f32, scale: f32) -> Result<Drizzle> {
+    let out = (8.0 * scale).ceil() as usize;
+    let mut drizzle = Drizzle::new(
+        8,
+        8,
+        Scale::new(scale).unwrap(),
+        0,
+        0,
+        StackAlgorithmImpl::new(StackAlgorithm::Average, 2.5, out, out, 1)?,
+    );
+    drizzle.set_kernel(kernel, pixfrac);
+    Ok(drizzle)
+}
+
+#[test]
+fn test_scale_from() -> Result<()> {
+    assert_eq!(Scale::from("1.25")?.value(), 1.25);
+    assert!(Scale::from("0").is_err());
+    assert!(Scale::from("x2").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_point_kernel_is_identity_without_transform() -> Result<()> {
+    let image = ramp_image(8, 8)?;
+    let mut d = drizzle(DrizzleKernel::Point, 1.0, 1.0)?;
+    d.add_with_transform(&image, &Offset { h: 0.0, v: 0.0 }, 0.0)?;
+
+    let stacked = d.get_finalized()?;
+    for x in 0..8 {
+        assert_eq!(stacked.get_band(0).get(x, 4), (x * 100) as f32);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_square_kernel_half_pixel_shift() -> Result<()> {
+    let image = ramp_image(8, 8)?;
+    let mut d = drizzle(DrizzleKernel::Square, 1.0, 1.0)?;
+    d.add_with_transform(&image, &Offset { h: 0.5, v: 0.0 }, 0.0)?;
+
+    // Each output pixel is covered by half of each of two neighboring input pixels
+    let stacked = d.get_finalized()?;
+    assert!((stacked.get_band(0).get(4, 4) - 350.0).abs() < 0.01);
+
+    let weights = d.get_weight_map()?;
+    assert!((weights.get_band(0).get(4, 4) - 1.0).abs() < 0.001);
+    Ok(())
+}
+
+#[test]
+fn test_small_pixfrac_upscaled_weights() -> Result<()> {
+    let image = ramp_image(8, 8)?;
+    let mut d = drizzle(DrizzleKernel::Square, 0.5, 2.0)?;
+    d.add_with_transform(&image, &Offset { h: 0.0, v: 0.0 }, 0.0)?;
+
+    // Drops of one output pixel fall centered on the corners shared by four output pixels
+    let weights = d.get_weight_map()?;
+    assert!((weights.get_band(0).get(4, 4) - 0.25).abs() < 0.001);
+    let stacked = d.get_finalized()?;
+    assert!((stacked.get_band(0).get(4, 4) - 200.0).abs() < 0.01);
+    Ok(())
+}
+
+#[test]
+fn test_interpolate_kernel_subpixel_shift() -> Result<()> {
+    let image = ramp_image(8, 8)?;
+    let stack = |h: f32| -> Result<f32> {
+        let mut d = drizzle(DrizzleKernel::Interpolate, 1.0, 2.0)?;
+        d.add_with_transform(&image, &Offset { h, v: 0.0 }, 0.0)?;
+        Ok(d.get_finalized()?.get_band(0).get(8, 8))
+    };
+
+    // Output pixel 8 of the doubled frame is centered at input x = 3.75
+    let unshifted = stack(0.0)?;
+    assert!((unshifted - 375.0).abs() < 0.01, "{}", unshifted);
+    let shifted = stack(0.25)?;
+    assert!((shifted - 350.0).abs() < 0.01, "{}", shifted);
+    Ok(())
+}
+
+#[test]
+fn test_lanczos_kernel_weights_are_positive() -> Result<()> {
+    let image = ramp_image(8, 8)?;
+    let mut d = drizzle(DrizzleKernel::Lanczos, 1.0, 1.0)?;
+    d.add_with_transform(&image, &Offset { h: 0.3, v: 0.7 }, 0.0)?;
+
+    let weights = d.get_weight_map()?;
+    let stacked = d.get_finalized()?;
+    for y in 0..8 {
+        for x in 0..8 {
+            assert!(weights.get_band(0).get(x, y) >= 0.0);
+            assert!(stacked.get_band(0).get(x, y).is_finite());
+        }
+    }
+    // Interior pixels are reached by whole drops, which each total one pixel of weight
+    assert!((weights.get_band(0).get(4, 4) - 1.0).abs() < 0.05);
+    Ok(())
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::Result;
+use solhat::calibrationframe::CalibrationImage;
+use solhat::context::ProcessContext;
+use solhat::error::SolHatError;
+use solhat::ser::SerFile;
+use solhat::stacking::process_frame_stacking;
+
+#[test]
+fn test_missing_file_is_io_error() {
+    let path = std::env::temp_dir().join("solhat_test_does_not_exist.ser");
+    let err = SerFile::load_ser(&path.to_string_lossy()).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::Io(e)) if e.kind() == io::ErrorKind::NotFound
+    ));
+}
+
+#[test]
+fn test_empty_stack() -> Result<()> {
+    let path = common::write_disk_ser("emptystack", 64, 2, 0.0)?;
+    let mut context = common::context(&common::parameters(&[path], 0))?;
+    context.frame_records.clear();
+
+    let err = process_frame_stacking(&mut context, |_| {}).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::EmptyStack)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_calibration_mismatch() -> Result<()> {
+    let path = common::write_disk_ser("calibrationmismatch", 64, 2, 0.0)?;
+    let result: Result<ProcessContext> = ProcessContext::create_with_calibration_frames(
+        &common::parameters(&[path], 0),
+        CalibrationImage::new_black(32, 32, 1)?,
+        CalibrationImage::new_empty(),
+        CalibrationImage::new_empty(),
+        CalibrationImage::new_empty(),
+    );
+    let err = result.err().unwrap();
+
+    match err.downcast_ref::<SolHatError>() {
+        Some(SolHatError::CalibrationMismatch(msg)) => {
+            assert_eq!(msg, "Flat is 32x32, frames are 64x64")
+        }
+        other => panic!("Unexpected error: {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_open_error_keeps_type() {
+    let err = common::context(&common::parameters(&["capture.mov".to_string()], 0))
+        .err()
+        .unwrap();
+    assert!(err
+        .to_string()
+        .starts_with("Failed to open input file capture.mov"));
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::UnsupportedFileType(f)) if f == "capture.mov"
+    ));
+}
+
+#[test]
+fn test_poisoned_frame_cache_is_recovered() -> Result<()> {
+    let path = common::write_disk_ser("poisonedcache", 64, 2, 0.0)?;
+    let context = common::context(&common::parameters(&[path], 64))?;
+
+    // A worker panicking while holding the cache
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _cache = context.lock_frame_cache();
+        panic!("Worker failed");
+    }));
+    assert!(context.frame_cache.is_poisoned());
+
+    let frame = context.frame_records[0].get_frame(&context)?;
+    assert_eq!(frame.buffer.width, 64);
+    Ok(())
+}
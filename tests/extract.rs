@@ -0,0 +1,112 @@
+mod common;
+
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::context::ProcessContext;
+use solhat::datasource::DataSource;
+use solhat::extract::{extract_frames, frame_window_filter, shift_frame};
+use solhat::ser::SerFile;
+use solhat::timestamp::TimeStamp;
+
+/// Five frames of 64x64, one millisecond apart
+fn context(name: &str) -> Result<ProcessContext> {
+    let path = common::write_disk_ser(name, 64, 5, 0.0)?;
+    common::context(&common::parameters(&[path], 0))
+}
+
+fn window(
+    context: &ProcessContext,
+    first_frame: Option<usize>,
+    last_frame: Option<usize>,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+) -> Result<Vec<usize>> {
+    let mut frame_ids: Vec<usize> = frame_window_filter(
+        context,
+        first_frame,
+        last_frame,
+        start_time.map(TimeStamp::from_u64),
+        end_time.map(TimeStamp::from_u64),
+    )?
+    .iter()
+    .map(|fr| fr.frame_id)
+    .collect();
+    frame_ids.sort();
+    Ok(frame_ids)
+}
+
+#[test]
+fn test_frame_window_filter() -> Result<()> {
+    let context = context("extractwindow")?;
+
+    assert_eq!(
+        window(&context, None, None, None, None)?,
+        vec![0, 1, 2, 3, 4]
+    );
+    assert_eq!(
+        window(&context, Some(1), Some(3), None, None)?,
+        vec![1, 2, 3]
+    );
+    assert_eq!(
+        window(&context, None, None, Some(20000), None)?,
+        vec![2, 3, 4]
+    );
+    assert_eq!(
+        window(&context, Some(1), Some(3), None, Some(20000))?,
+        vec![1, 2]
+    );
+    assert!(window(&context, Some(4), Some(3), None, None)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_shift_frame() -> Result<()> {
+    let mut image = Image::new_with_bands(8, 8, 1, ImageMode::U16BIT)?;
+    image.put(2, 3, 100.0, 0);
+    image.put(7, 7, 200.0, 0);
+
+    // Offsets are rounded to whole pixels, and values moved off the frame are dropped
+    let shifted = shift_frame(&image, 1.4, -0.6, false)?;
+    assert_eq!(shifted.get_band(0).get(3, 2), 100.0);
+    assert_eq!(
+        shifted.get_band(0).buffer.to_vector().iter().sum::<f32>(),
+        100.0
+    );
+
+    // Mosaics move by whole pattern cells
+    let shifted = shift_frame(&image, 1.4, -0.6, true)?;
+    assert_eq!(shifted.get_band(0).get(4, 3), 100.0);
+    Ok(())
+}
+
+#[test]
+fn test_extract_frames() -> Result<()> {
+    let mut context = context("extractsource")?;
+    context.frame_records = frame_window_filter(&context, Some(1), Some(3), None, None)?;
+
+    let output_path = std::env::temp_dir()
+        .join("solhat_test_extracted.ser")
+        .to_string_lossy()
+        .to_string();
+    assert_eq!(
+        extract_frames(&mut context, &output_path, false, |_| {})?,
+        3
+    );
+
+    let ser = SerFile::load_ser(&output_path)?;
+    ser.validate_ser()?;
+    assert_eq!(ser.frame_count, 3);
+    assert_eq!((ser.image_width, ser.image_height), (64, 64));
+    assert_eq!(ser.get_frame_timestamp(0)?.timestamp, 10000);
+    assert_eq!(ser.get_frame_timestamp(2)?.timestamp, 30000);
+    assert_eq!(
+        ser.get_frame(1)?.buffer.get_band(0).get(32, 32),
+        common::DISK_VALUE
+    );
+    assert!(context
+        .stats
+        .stage_timings
+        .iter()
+        .any(|t| t.stage == "extract"));
+    Ok(())
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::anaysis::frame_sigma_analysis;
+use solhat::datasource::DataFrame;
+use solhat::framecache::FrameCache;
+use solhat::offsetting::frame_offset_analysis;
+use solhat::timestamp::TimeStamp;
+
+/// A 512x512 single band frame, which is exactly one megabyte
+fn frame(fill: f32) -> Result<Arc<DataFrame>> {
+    Ok(Arc::new(DataFrame {
+        buffer: Image::new_with_bands_and_fill(512, 512, 1, ImageMode::U16BIT, fill)?,
+        timestamp: TimeStamp::from_u64(0),
+    }))
+}
+
+#[test]
+fn test_frame_cache_keeps_first_frames_when_full() -> Result<()> {
+    let mut cache = FrameCache::new(2);
+
+    cache.put("a", 0, frame(1.0)?);
+    cache.put("a", 1, frame(2.0)?);
+    cache.put("b", 0, frame(3.0)?);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get("b", 0).is_none());
+    assert_eq!(cache.get("a", 0).unwrap().buffer.get_band(0).get(0, 0), 1.0);
+
+    // Dropping a frame makes room for another
+    cache.retain(|source_file_id, frame_id| (source_file_id, frame_id) != ("a", 1));
+    cache.put("b", 0, frame(3.0)?);
+    assert_eq!(cache.get("b", 0).unwrap().buffer.get_band(0).get(0, 0), 3.0);
+    assert!(cache.get("a", 1).is_none());
+    assert_eq!(cache.used_bytes(), 2 * 1024 * 1024);
+    assert_eq!((cache.hits, cache.misses), (2, 2));
+    Ok(())
+}
+
+#[test]
+fn test_frame_cache_disabled() -> Result<()> {
+    let mut cache = FrameCache::new(0);
+    assert!(!cache.is_enabled());
+
+    cache.put("a", 0, frame(1.0)?);
+    assert!(cache.is_empty());
+    assert!(cache.get("a", 0).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_frame_cache_hits_across_stages() -> Result<()> {
+    // Four one megabyte frames swept twice through a cache holding only two of them
+    let path = common::write_disk_ser("framecache", 512, 4, 1.0)?;
+    let mut context = common::context(&common::parameters(&[path], 2))?;
+
+    context.frame_records = frame_sigma_analysis(&mut context, |_| {})?;
+    context.frame_records = frame_offset_analysis(&mut context, |_| {})?;
+
+    let cache = context.frame_cache.lock().unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!((cache.hits, cache.misses), (2, 6));
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::interpolation::{InterpolationMethod, Interpolator};
+
+// A vertical step edge between columns 7 and 8
+fn edge_buffer() -> Result<ImageBuffer> {
+    let mut buffer = ImageBuffer::new_as_mode(16, 4, ImageMode::U16BIT)?;
+    for y in 0..4 {
+        for x in 8..16 {
+            buffer.put(x, y, 1000.0);
+        }
+    }
+    Ok(buffer)
+}
+
+// Steepest rise between neighboring samples of the edge shifted by half a pixel
+fn max_step(method: InterpolationMethod) -> Result<f32> {
+    let buffer = edge_buffer()?;
+    let interpolator = method.interpolator();
+    let samples: Vec<f32> = (2..13)
+        .map(|x| {
+            interpolator
+                .interpolate(&buffer, x as f32 + 0.5, 1.0)
+                .unwrap()
+        })
+        .collect();
+    Ok(samples.windows(2).map(|w| w[1] - w[0]).fold(0.0, f32::max))
+}
+
+#[test]
+fn test_interpolators_reproduce_pixels() -> Result<()> {
+    let buffer = edge_buffer()?;
+    for method in [
+        InterpolationMethod::Nearest,
+        InterpolationMethod::Bilinear,
+        InterpolationMethod::Bicubic,
+        InterpolationMethod::Lanczos3,
+    ] {
+        let interpolator = method.interpolator();
+        assert!((interpolator.interpolate(&buffer, 7.0, 1.0).unwrap() - 0.0).abs() < 0.01);
+        assert!((interpolator.interpolate(&buffer, 8.0, 1.0).unwrap() - 1000.0).abs() < 0.01);
+        assert!(interpolator.interpolate(&buffer, -0.5, 1.0).is_none());
+        assert!(interpolator.interpolate(&buffer, 16.0, 1.0).is_none());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sharper_than_bilinear_on_edge() -> Result<()> {
+    let bilinear = max_step(InterpolationMethod::Bilinear)?;
+    assert!((bilinear - 500.0).abs() < 0.01);
+    assert!(max_step(InterpolationMethod::Bicubic)? > bilinear);
+    assert!(max_step(InterpolationMethod::Lanczos3)? > bilinear);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::interpolation::InterpolationMethod;
+use solhat::ldcorrect::limb_darkening_correction_on_image;
+use solhat::limbdarkening::LimbDarkeningModel;
+
+// A limb darkened disk centered near the right edge of the image, partially outside of it
+fn off_center_disk() -> Result<Image> {
+    let (cx, cy, radius) = (150.0, 80.0, 60.0);
+    let mut image = Image::new_with_bands(180, 160, 1, ImageMode::U16BIT)?;
+    for y in 0..160 {
+        for x in 0..180 {
+            let r = ((x as f32 - cx).powi(2) + (y as f32 - cy).powi(2)).sqrt();
+            let v = if r <= radius {
+                let mu = (1.0 - (r / radius).powi(2)).sqrt();
+                20000.0 * (1.0 - 0.5 * (1.0 - mu))
+            } else {
+                100.0
+            };
+            image.put(x, y, v, 0);
+        }
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_off_center_correction_is_radially_symmetric() -> Result<()> {
+    let image = off_center_disk()?;
+
+    for (center, radius) in [(Some((150.0, 80.0)), Some(60.0)), (None, None)] {
+        let corrected = limb_darkening_correction_on_image(
+            &image,
+            center,
+            radius,
+            LimbDarkeningModel::Linear,
+            &[0.5],
+            0.0,
+            false,
+            InterpolationMethod::default(),
+        )?;
+        let band = corrected.image.get_band(0);
+
+        // Equally distant from the center to the left and above
+        for d in [10, 30, 50] {
+            let left = band.get(150 - d, 80);
+            let above = band.get(150, 80 - d);
+            assert!(
+                (left - above).abs() < 200.0,
+                "{} != {} at distance {}",
+                left,
+                above,
+                d
+            );
+        }
+
+        // The sky is left as is
+        assert_eq!(band.get(20, 80), 100.0);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_fitted_correction_flattens_disk() -> Result<()> {
+    let image = off_center_disk()?;
+    let corrected = limb_darkening_correction_on_image(
+        &image,
+        Some((150.0, 80.0)),
+        Some(60.0),
+        LimbDarkeningModel::Linear,
+        &[0.0],
+        0.0,
+        false,
+        InterpolationMethod::default(),
+    )?;
+
+    let fit = &corrected.fits[0];
+    assert!((fit.coefficients[0] - 0.5).abs() < 0.02, "{}", fit);
+
+    let band = corrected.image.get_band(0);
+    for d in [0, 20, 40, 55] {
+        let v = band.get(150 - d, 80);
+        assert!((v - 20000.0).abs() < 300.0, "{} at distance {}", v, d);
+    }
+    Ok(())
+}
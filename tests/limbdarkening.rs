@@ -0,0 +1,48 @@
+use anyhow::Result;
+use solhat::error::SolHatError;
+use solhat::limbdarkening::{fit_limb_darkening, LimbDarkeningModel};
+
+// A profile sampled evenly in radius, as across a disk
+fn profile(model: LimbDarkeningModel, coefficients: &[f64]) -> Vec<(f64, f64)> {
+    (0..100)
+        .map(|r| {
+            let mu = (1.0 - (r as f64 / 100.0).powi(2)).sqrt();
+            (mu, 15000.0 * model.relative_intensity(coefficients, mu))
+        })
+        .collect()
+}
+
+#[test]
+fn test_fits_recover_coefficients() -> Result<()> {
+    for (model, coefficients) in [
+        (LimbDarkeningModel::Linear, vec![0.56]),
+        (LimbDarkeningModel::Quadratic, vec![0.4, 0.25]),
+        (LimbDarkeningModel::SquareRoot, vec![0.1, 0.6]),
+        (LimbDarkeningModel::Logarithmic, vec![0.7, 0.2]),
+        (
+            LimbDarkeningModel::NeckelLabs,
+            vec![0.3, 1.3, -1.2, 1.1, -0.6, 0.1],
+        ),
+    ] {
+        let fit = fit_limb_darkening(model, &profile(model, &coefficients))?;
+        assert!((fit.center_intensity - 15000.0).abs() < 1.0, "{}", fit);
+        fit.coefficients
+            .iter()
+            .zip(coefficients.iter())
+            .for_each(|(f, c)| assert!((f - c).abs() < 1.0e-3, "{}", fit));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_fit_rejects_nan_samples() {
+    let mut samples = profile(LimbDarkeningModel::Linear, &[0.56]);
+    samples[10].1 = f64::NAN;
+    let err = fit_limb_darkening(LimbDarkeningModel::Linear, &samples)
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::Format(_))
+    ));
+}
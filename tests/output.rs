@@ -0,0 +1,75 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::fits::FitsDataType;
+use solhat::output::{save_output, scale_for_output, validate_output, OutputScaling};
+
+#[test]
+fn test_output_scaling_from() -> Result<()> {
+    assert_eq!(OutputScaling::from("linear")?, OutputScaling::Linear);
+    assert_eq!(OutputScaling::from("0.5")?, OutputScaling::Factor(0.5));
+    assert!(OutputScaling::from("-2").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_factor_scaling_is_fixed_and_clipped() -> Result<()> {
+    let mut image = Image::new_with_bands(4, 1, 1, ImageMode::U16BIT)?;
+    [100.0, 200.0, 30000.0, 40000.0]
+        .iter()
+        .enumerate()
+        .for_each(|(x, v)| image.put(x, 0, *v, 0));
+
+    scale_for_output(&mut image, OutputScaling::Factor(2.0));
+
+    let values: Vec<f32> = (0..4).map(|x| image.get_band(0).get(x, 0)).collect();
+    assert_eq!(values, vec![200.0, 400.0, 60000.0, 65535.0]);
+    Ok(())
+}
+
+#[test]
+fn test_linear_output_requires_float_format() -> Result<()> {
+    let image = Image::new_with_bands(4, 4, 1, ImageMode::U16BIT)?;
+    let path = |name: &str| {
+        std::env::temp_dir()
+            .join(name)
+            .to_string_lossy()
+            .to_string()
+    };
+
+    let linear = OutputScaling::Linear;
+    assert!(save_output(
+        &image,
+        &path("solhat_test_linear.png"),
+        linear,
+        FitsDataType::Float32,
+        &[]
+    )
+    .is_err());
+    assert!(save_output(
+        &image,
+        &path("solhat_test_linear.fits"),
+        linear,
+        FitsDataType::UInt16,
+        &[]
+    )
+    .is_err());
+
+    let tiff_path = path("solhat_test_linear.tif");
+    save_output(&image, &tiff_path, linear, FitsDataType::Float32, &[])?;
+    let bytes = std::fs::read(&tiff_path)?;
+    assert_eq!(&bytes[0..4], b"II*\0");
+    assert_eq!(bytes.len(), 8 + 138 + 4 * 4 * 4);
+    Ok(())
+}
+
+#[test]
+fn test_validate_output() -> Result<()> {
+    let linear = OutputScaling::Linear;
+    validate_output("stack.fits", linear, FitsDataType::Float32)?;
+    validate_output("stack.tif", linear, FitsDataType::UInt16)?;
+    validate_output("stack.png", OutputScaling::Normalize, FitsDataType::UInt16)?;
+    assert!(validate_output("stack.png", linear, FitsDataType::Float32).is_err());
+    assert!(validate_output("stack.fits", linear, FitsDataType::UInt16).is_err());
+    assert!(validate_output("stack.ser", OutputScaling::Normalize, FitsDataType::Float32).is_err());
+    Ok(())
+}
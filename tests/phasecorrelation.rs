@@ -0,0 +1,30 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::phasecorrelation::PhaseCorrelator;
+
+fn gaussian_spot(width: usize, height: usize, cx: f64, cy: f64) -> Result<Image> {
+    let mut image = Image::new_with_bands(width, height, 1, ImageMode::U16BIT)?;
+    for y in 0..height {
+        for x in 0..width {
+            let d2 = (x as f64 - cx).powi(2) + (y as f64 - cy).powi(2);
+            image.put(x, y, (10000.0 * (-d2 / 18.0).exp()) as f32, 0);
+        }
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_phase_correlation_displacement() -> Result<()> {
+    let reference = gaussian_spot(64, 64, 30.0, 28.0)?;
+    let shifted = gaussian_spot(64, 64, 33.0, 26.0)?;
+
+    let correlator = PhaseCorrelator::new(&reference);
+    let (dx, dy) = correlator.displacement(&shifted)?;
+    assert!((dx - 3.0).abs() < 0.25, "dx = {}", dx);
+    assert!((dy + 2.0).abs() < 0.25, "dy = {}", dy);
+
+    let offset = correlator.offset(&shifted)?;
+    assert!((offset.h + 3.0).abs() < 0.25);
+    assert!((offset.v - 2.0).abs() < 0.25);
+    Ok(())
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use anyhow::Result;
+use solhat::fits::{FitsKeyword, FitsValue};
+use solhat::provenance::stack_keywords;
+
+fn value<'a>(keywords: &'a [FitsKeyword], key: &str) -> &'a FitsValue {
+    &keywords
+        .iter()
+        .find(|k| k.key == key)
+        .unwrap_or_else(|| panic!("Missing keyword {}", key))
+        .value
+}
+
+fn float(keywords: &[FitsKeyword], key: &str) -> f64 {
+    match value(keywords, key) {
+        FitsValue::Float(f) => *f,
+        v => panic!("{} is not a float: {:?}", key, v),
+    }
+}
+
+#[test]
+fn test_stack_keywords() -> Result<()> {
+    // Frame i is captured i milliseconds after midnight of January 1st, year 1
+    let path = common::write_disk_ser("provenance", 64, 5, 0.0)?;
+    let mut params = common::parameters(&[path], 0);
+    params.top_percentage = Some(50.0);
+    let mut context = common::context(&params)?;
+    context.stats.total_frames = 5;
+    context.frame_records.sort_by_key(|fr| fr.frame_id);
+    context.frame_records.truncate(3);
+
+    let image = common::disk_frame(200, 60.0, 0.0, 0.0)?;
+    let keywords = stack_keywords(&context, &image)?;
+
+    // Frames without a capture time are left out of the times
+    for (key, expected) in [
+        ("DATE-OBS", "0001-01-01T00:00:00.001"),
+        ("DATE-BEG", "0001-01-01T00:00:00.001"),
+        ("DATE-END", "0001-01-01T00:00:00.002"),
+        ("TIMESYS", "UTC"),
+        ("OBJECT", "Sun"),
+        ("STACKALG", "Average"),
+    ] {
+        assert_eq!(
+            value(&keywords, key),
+            &FitsValue::String(expected.to_string()),
+            "{}",
+            key
+        );
+    }
+    assert_eq!(value(&keywords, "NFRAMES"), &FitsValue::Integer(5));
+    assert_eq!(value(&keywords, "NCOMBINE"), &FitsValue::Integer(3));
+    assert_eq!(float(&keywords, "TOPPCT"), 50.0);
+    assert_eq!(float(&keywords, "DRIZZLE"), 1.0);
+    assert!(!keywords.iter().any(|k| k.key == "MINSIGMA"));
+    ["SOLAR_P", "SOLAR_B0", "SOLAR_L0"].iter().for_each(|key| {
+        float(&keywords, key);
+    });
+
+    // 1-based, bottom-up pixel coordinates of the disk, which is centered at 100,100
+    assert!((float(&keywords, "DISK_X") - 101.0).abs() < 0.5);
+    assert!((float(&keywords, "DISK_Y") - 100.0).abs() < 0.5);
+    assert!((float(&keywords, "RSUN_PIX") - 60.0).abs() < 1.0);
+    assert!(!keywords.iter().any(|k| k.key.starts_with("CRPIX")));
+    Ok(())
+}
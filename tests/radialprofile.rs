@@ -0,0 +1,32 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::radialprofile::RadialProfile;
+
+#[test]
+fn test_profile_of_off_center_rings() -> Result<()> {
+    // Values rising by 100 per pixel wide ring about a center near the corner
+    let mut image = Image::new_with_bands(40, 30, 1, ImageMode::U16BIT)?;
+    for y in 0..30 {
+        for x in 0..40 {
+            let r = ((x as f64 - 5.0).powi(2) + (y as f64 - 5.0).powi(2)).sqrt();
+            image.put(x, y, (r.floor() * 100.0) as f32, 0);
+        }
+    }
+
+    let profile = RadialProfile::compute(&image, (5.0, 5.0), Some(20.0), 1.0)?;
+    assert_eq!(profile.bands.len(), 1);
+    assert_eq!(profile.bands[0].len(), 20);
+    profile.bands[0].iter().enumerate().for_each(|(i, b)| {
+        assert_eq!(b.radius, i as f64 + 0.5);
+        assert_eq!(b.mean, i as f64 * 100.0);
+        assert_eq!(b.median, i as f64 * 100.0);
+        assert_eq!(b.std_dev, 0.0);
+    });
+
+    // Only the single pixel at the center falls within the first ring
+    assert_eq!(profile.bands[0][0].count, 1);
+    assert!(profile
+        .to_csv()
+        .starts_with("band,radius,mean,median,std_dev,count\n0,0.5,0,0,0,1\n"));
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use sciimg::binfilereader::{BinFileReader, Endian};
+use sciimg::prelude::*;
+use solhat::datasource::{ByteOrder, ColorFormatId, DataSource, PixelFormat};
+use solhat::error::SolHatError;
+use solhat::ser::SerFile;
+use solhat::serwriter::SerWriter;
+use solhat::timestamp::TimeStamp;
+
+const WIDTH: usize = 16;
+const HEIGHT: usize = 16;
+
+/// A smooth gradient which fits within 12 bits
+fn gradient(x: usize, y: usize) -> u16 {
+    (x * 16 + y * 8) as u16
+}
+
+fn ser_header(
+    width: usize,
+    height: usize,
+    pixel_depth: i32,
+    frame_count: usize,
+    endian_flag: i32,
+) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![];
+    bytes.extend_from_slice(b"LUCAM-RECORDER");
+    bytes.extend_from_slice(&0_i32.to_le_bytes()); // Camera series
+    bytes.extend_from_slice(&0_i32.to_le_bytes()); // Mono
+    bytes.extend_from_slice(&endian_flag.to_le_bytes());
+    bytes.extend_from_slice(&(width as i32).to_le_bytes());
+    bytes.extend_from_slice(&(height as i32).to_le_bytes());
+    bytes.extend_from_slice(&pixel_depth.to_le_bytes());
+    bytes.extend_from_slice(&(frame_count as i32).to_le_bytes());
+    bytes.extend_from_slice(&[0; 120]); // Observer, instrument, telescope
+    bytes.extend_from_slice(&0_u64.to_le_bytes());
+    bytes.extend_from_slice(&0_u64.to_le_bytes());
+    bytes
+}
+
+/// Writes a single frame, mono, 12-bit SER file to the temp directory
+fn write_test_ser(name: &str, big_endian: bool, shift: u32, endian_flag: i32) -> Result<String> {
+    let mut bytes = ser_header(WIDTH, HEIGHT, 12, 1, endian_flag);
+
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let v = gradient(x, y) << shift;
+            if big_endian {
+                bytes.extend_from_slice(&v.to_be_bytes());
+            } else {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("solhat_test_{}.ser", name));
+    std::fs::write(&path, bytes)?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[test]
+fn test_ser_detects_big_endian_and_shifts_12bit() -> Result<()> {
+    // Flag of 0 is what much software writes regardless of the data
+    let path = write_test_ser("be12", true, 0, 0)?;
+    let ser = SerFile::load_ser(&path)?;
+    ser.validate_ser()?;
+
+    assert_eq!(ser.byte_order, ByteOrder::BigEndian);
+    assert_eq!(ser.sample_shift, 4);
+
+    let frame = ser.get_frame(0)?;
+    assert_eq!(
+        frame.buffer.get_band(0).get(5, 7),
+        (gradient(5, 7) << 4) as f32
+    );
+    Ok(())
+}
+
+#[test]
+fn test_ser_trusts_little_endian_flag() -> Result<()> {
+    // A flag of 1 is unambiguous and wins over what the data looks like
+    let path = write_test_ser("be12flag", true, 0, 1)?;
+    let ser = SerFile::load_ser(&path)?;
+    assert_eq!(ser.header_byte_order(), Some(ByteOrder::LittleEndian));
+    assert_eq!(ser.byte_order, ByteOrder::LittleEndian);
+
+    let path = write_test_ser("le12flag", false, 0, 0)?;
+    let ser = SerFile::load_ser(&path)?;
+    assert_eq!(ser.header_byte_order(), None);
+    assert_eq!(ser.byte_order, ByteOrder::LittleEndian);
+    Ok(())
+}
+
+#[test]
+fn test_ser_truncated_is_rejected_before_probing() -> Result<()> {
+    let path = write_test_ser("truncated", false, 0, 0)?;
+    let mut bytes = std::fs::read(&path)?;
+    bytes.truncate(bytes.len() - 10);
+    std::fs::write(&path, bytes)?;
+
+    let err = SerFile::load_ser(&path).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::Format(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_ser_already_shifted_12bit_is_left_alone() -> Result<()> {
+    let path = write_test_ser("le12msb", false, 4, 1)?;
+    let ser = SerFile::load_ser(&path)?;
+
+    assert_eq!(ser.byte_order, ByteOrder::LittleEndian);
+    assert_eq!(ser.sample_shift, 0);
+
+    let frame = ser.get_frame(0)?;
+    assert_eq!(
+        frame.buffer.get_band(0).get(5, 7),
+        (gradient(5, 7) << 4) as f32
+    );
+    Ok(())
+}
+
+#[test]
+fn test_ser_pixel_format_override() -> Result<()> {
+    let path = write_test_ser("le12override", false, 0, 1)?;
+    let mut ser = SerFile::load_ser(&path)?;
+    ser.set_pixel_format(&PixelFormat {
+        byte_order: ByteOrder::LittleEndian,
+        bit_depth: None,
+        shift_to_16bit: false,
+    })?;
+
+    assert_eq!(ser.sample_shift, 0);
+    let frame = ser.get_frame(0)?;
+    assert_eq!(frame.buffer.get_band(0).get(5, 7), gradient(5, 7) as f32);
+    Ok(())
+}
+
+#[test]
+fn test_ser_writer_round_trip() -> Result<()> {
+    let path = std::env::temp_dir()
+        .join("solhat_test_written.ser")
+        .to_string_lossy()
+        .to_string();
+
+    let mut writer = SerWriter::create(&path, ColorFormatId::Rgb, WIDTH, HEIGHT, 16)?;
+    writer.observer = "Observer".to_string();
+    writer.date_time_utc = TimeStamp::parse("2024-04-08T18:20:00")?;
+
+    let mut image = Image::new_with_bands(WIDTH, HEIGHT, 3, ImageMode::U16BIT)?;
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            (0..3).for_each(|b| image.put(x, y, (gradient(x, y) + b as u16) as f32, b));
+        }
+    }
+    for f in 0..3 {
+        writer.write_frame(&image, &TimeStamp::from_u64(1000 + f))?;
+    }
+    assert_eq!(writer.finish()?, 3);
+
+    let ser = SerFile::load_ser(&path)?;
+    ser.validate_ser()?;
+    assert!(ser.has_timestamps());
+    assert_eq!(ser.color_id, ColorFormatId::Rgb);
+    assert_eq!(ser.frame_count, 3);
+    assert_eq!(ser.observer.trim_end_matches('\0'), "Observer");
+    assert_eq!(ser.date_time_utc.hour, 18);
+    assert_eq!(ser.get_frame_timestamp(2)?.timestamp, 1002);
+
+    let frame = ser.get_frame(1)?;
+    assert_eq!(
+        frame.buffer.get_band(2).get(5, 7),
+        (gradient(5, 7) + 2) as f32
+    );
+    Ok(())
+}
+
+/// Benchmark of bulk frame decoding against reading each value individually through
+/// `BinFileReader` as was previously done. Run with `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn bench_ser_bulk_frame_decoding() -> Result<()> {
+    let (width, height, frame_count) = (1024, 1024, 16);
+
+    let mut bytes = ser_header(width, height, 16, frame_count, 1);
+    for i in 0..(width * height * frame_count) {
+        bytes.extend_from_slice(&((i % 65536) as u16).to_le_bytes());
+    }
+    let path = std::env::temp_dir().join("solhat_test_bench.ser");
+    std::fs::write(&path, bytes)?;
+    let path = path.to_string_lossy().to_string();
+
+    let reader = BinFileReader::new_as_endiness(&path, Endian::LittleEndian);
+    let t = Instant::now();
+    let mut per_value_sum = 0.0;
+    for f in 0..frame_count {
+        let start = 178 + f * width * height * 2;
+        for i in 0..(width * height) {
+            per_value_sum += reader.read_u16(start + i * 2)? as f64;
+        }
+    }
+    let per_value = t.elapsed();
+
+    let ser = SerFile::load_ser(&path)?;
+    let t = Instant::now();
+    let mut bulk_sum = 0.0;
+    for f in 0..frame_count {
+        let frame = ser.get_ser_frame(f)?;
+        bulk_sum += frame
+            .buffer
+            .get_band(0)
+            .buffer
+            .to_vector()
+            .iter()
+            .map(|v| *v as f64)
+            .sum::<f64>();
+    }
+    let bulk = t.elapsed();
+
+    println!(
+        "Decoded {} frames of {}x{}: per-value {:?}, bulk {:?} ({:.1}x)",
+        frame_count,
+        width,
+        height,
+        per_value,
+        bulk,
+        per_value.as_secs_f64() / bulk.as_secs_f64()
+    );
+
+    assert_eq!(per_value_sum, bulk_sum);
+    assert!(bulk < per_value);
+    Ok(())
+}
+
+#[test]
+fn test_ser_errors_are_typed() -> Result<()> {
+    let path = write_test_ser("errors", false, 0, 0)?;
+    let ser = SerFile::load_ser(&path)?;
+    let err = ser.get_frame(3).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::FrameOutOfRange {
+            frame: 3,
+            frame_count: 1
+        })
+    ));
+
+    // Color format ids are at bytes 18 through 21 of the header
+    let mut bytes = std::fs::read(&path)?;
+    bytes[18..22].copy_from_slice(&7_i32.to_le_bytes());
+    std::fs::write(&path, bytes)?;
+    let err = SerFile::load_ser(&path).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<SolHatError>(),
+        Some(SolHatError::UnsupportedColorFormat(7))
+    ));
+    Ok(())
+}
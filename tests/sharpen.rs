@@ -0,0 +1,94 @@
+use anyhow::Result;
+use sciimg::prelude::*;
+use solhat::sharpen::{richardson_lucy, wavelet_sharpen, Kernel, WaveletLayer};
+
+// A gaussian spot of the given sigma centered in a 32x32 image
+fn spot_image(sigma: f32) -> Result<Image> {
+    let mut image = Image::new_with_bands(32, 32, 1, ImageMode::U16BIT)?;
+    for y in 0..32 {
+        for x in 0..32 {
+            let d2 =
+                ((x as f32 - 16.0).powi(2) + (y as f32 - 16.0).powi(2)) / (2.0 * sigma * sigma);
+            image.put(x, y, 100.0 + 10000.0 * (-d2).exp(), 0);
+        }
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_parse_wavelet_layers() -> Result<()> {
+    let layers = WaveletLayer::parse_layers("2.0:1.0, 1.5")?;
+    assert_eq!(
+        layers,
+        vec![
+            WaveletLayer {
+                gain: 2.0,
+                denoise: 1.0
+            },
+            WaveletLayer {
+                gain: 1.5,
+                denoise: 0.0
+            }
+        ]
+    );
+    assert!(WaveletLayer::parse_layers("2.0:x").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_unit_wavelet_gains_reproduce_image() -> Result<()> {
+    let image = spot_image(2.0)?;
+    let layers = vec![
+        WaveletLayer {
+            gain: 1.0,
+            denoise: 0.0
+        };
+        4
+    ];
+    let result = wavelet_sharpen(&image, &layers);
+    for y in 0..32 {
+        for x in 0..32 {
+            let (a, b) = (image.get_band(0).get(x, y), result.get_band(0).get(x, y));
+            assert!((a - b).abs() < 0.05, "{} != {} at {},{}", a, b, x, y);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sharpening_raises_peak() -> Result<()> {
+    let image = spot_image(2.0)?;
+    let peak = image.get_band(0).get(16, 16);
+
+    let layers = vec![WaveletLayer {
+        gain: 2.0,
+        denoise: 0.0,
+    }];
+    assert!(wavelet_sharpen(&image, &layers).get_band(0).get(16, 16) > peak);
+
+    let deconvolved = richardson_lucy(&image, &Kernel::gaussian(1.0)?, 10);
+    assert!(deconvolved.get_band(0).get(16, 16) > peak);
+    Ok(())
+}
+
+#[test]
+fn test_richardson_lucy_large_psf_keeps_flat_image() -> Result<()> {
+    let mut image = Image::new_with_bands(32, 32, 1, ImageMode::U16BIT)?;
+    for y in 0..32 {
+        for x in 0..32 {
+            image.put(x, y, 1000.0, 0);
+        }
+    }
+
+    // The kernel is larger than the image, with its edges extended
+    let kernel = Kernel::gaussian(10.0)?;
+    assert!(kernel.size > 32);
+    let deconvolved = richardson_lucy(&image, &kernel, 5);
+    for y in 0..32 {
+        for x in 0..32 {
+            let v = deconvolved.get_band(0).get(x, y);
+            assert!((v - 1000.0).abs() < 0.5, "{} at {},{}", v, x, y);
+        }
+    }
+    Ok(())
+}
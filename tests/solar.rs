@@ -0,0 +1,10 @@
+use solhat::solar;
+
+#[test]
+fn test_solar_orientation_matches_meeus_example() {
+    // Meeus, Astronomical Algorithms, example 29.a: 1992 October 13, 0h TD
+    let orientation = solar::orientation_from_julian_day(2448908.5);
+    assert!((orientation.p - 26.27).abs() < 0.01, "{:?}", orientation);
+    assert!((orientation.b0 - 5.99).abs() < 0.01, "{:?}", orientation);
+    assert!((orientation.l0 - 238.63).abs() < 0.05, "{:?}", orientation);
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use solhat::drizzle::{MedianStackBuffer, SigmaClipStackBuffer, StackAlgorithm, StackBuffer};
+
+// Samples about 100 and a single outlier, split between two buffers as the parallel stacking
+// path would
+fn stack_with_outlier(samples: &[f32], winsorize: bool) -> Result<f32> {
+    let mut master = SigmaClipStackBuffer::new(1, 1, 1)?.with_clipping(2.5, winsorize);
+    for pass in 0..StackAlgorithm::SigmaClip.num_passes() {
+        if pass > 0 {
+            master.next_pass();
+        }
+        let mut first = master.empty_like()?;
+        let mut second = master.empty_like()?;
+        samples.iter().enumerate().for_each(|(i, v)| {
+            let buffer = if i % 2 == 0 { &mut first } else { &mut second };
+            buffer.put(0, 0, &[*v, 0.0, 0.0]);
+        });
+        master.add_other(&first);
+        master.add_other(&second);
+    }
+
+    Ok(master.get_finalized()?.get_band(0).get(0, 0))
+}
+
+// Five each of 98 and 102, and an outlier
+fn noisy_samples_with_outlier() -> Vec<f32> {
+    let mut samples: Vec<f32> = (0..10)
+        .map(|i| if i % 2 == 0 { 98.0 } else { 102.0 })
+        .collect();
+    samples.push(1000.0);
+    samples
+}
+
+// Samples split between two buffers, through every pass of the median
+fn stack_median(samples: &[f32]) -> Result<f32> {
+    let mut master = MedianStackBuffer::new(1, 1, 1)?;
+    for pass in 0..StackAlgorithm::Median.num_passes() {
+        if pass > 0 {
+            master.next_pass();
+        }
+        let mut first = master.empty_like()?;
+        let mut second = master.empty_like()?;
+        samples.iter().enumerate().for_each(|(i, v)| {
+            let buffer = if i % 2 == 0 { &mut first } else { &mut second };
+            buffer.put(0, 0, &[*v, 0.0, 0.0]);
+        });
+        master.add_other(&first);
+        master.add_other(&second);
+    }
+
+    Ok(master.get_finalized()?.get_band(0).get(0, 0))
+}
+
+#[test]
+fn test_sigma_clip_rejects_outlier() -> Result<()> {
+    let clipped = stack_with_outlier(&noisy_samples_with_outlier(), false)?;
+    assert!((clipped - 100.0).abs() < 0.01, "{}", clipped);
+    Ok(())
+}
+
+#[test]
+fn test_winsorized_limits_outlier() -> Result<()> {
+    let mean = 2000.0 / 11.0;
+    let winsorized = stack_with_outlier(&noisy_samples_with_outlier(), true)?;
+    assert!(winsorized > 100.0 && winsorized < mean, "{}", winsorized);
+    Ok(())
+}
+
+#[test]
+fn test_sigma_clip_rejects_outlier_of_small_stack() -> Result<()> {
+    // Within 2.5 standard deviations of the mean of all five samples
+    let samples = [101.0, 99.0, 100.0, 102.0, 1000.0];
+    let clipped = stack_with_outlier(&samples, false)?;
+    assert!((clipped - 100.5).abs() < 0.01, "{}", clipped);
+    Ok(())
+}
+
+#[test]
+fn test_median_is_bounded_estimate() -> Result<()> {
+    // 0 through 100 in a scattered order, with a few extreme values to either side
+    let mut samples: Vec<f32> = (0..101).map(|i| ((i * 37) % 101) as f32).collect();
+    samples.extend([-5000.0, 60000.0, 65000.0]);
+    samples.push(-4000.0);
+
+    // The median of the 105 samples is the 53rd smallest, which is 50
+    let median = stack_median(&samples)?;
+    assert!((median - 50.0).abs() < 1.0, "median was {}", median);
+    Ok(())
+}
+
+#[test]
+fn test_median_with_samples_on_bin_edges() -> Result<()> {
+    // Samples on the edges of the bins of the first pass, for which rounding leaves the median
+    // just below the window narrowed down to, so more samples fall below it than its rank
+    let (lo, span) = (54.44262_f32, 87.52878_f32);
+    let edges = [
+        0, 1, 2, 2, 5, 7, 8, 9, 9, 9, 9, 10, 11, 11, 12, 13, 13, 14, 15, 16,
+    ];
+    let samples: Vec<f32> = edges.iter().map(|b| lo + *b as f32 * span / 16.0).collect();
+
+    // The median is the 11th smallest sample, on the edge of the 9th bin
+    let median = stack_median(&samples)?;
+    let expected = lo + 9.0 * span / 16.0;
+    assert!(median.is_finite(), "median was {}", median);
+    assert!((median - expected).abs() < 0.1, "median was {}", median);
+    Ok(())
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use anyhow::Result;
+use solhat::anaysis::frame_sigma_analysis;
+use solhat::limiting::frame_limit_determinate;
+use solhat::offsetting::frame_offset_analysis;
+use solhat::stacking::process_frame_stacking;
+use solhat::stats::DiscardReason;
+use solhat::weighting::frame_weight_determinate;
+
+#[test]
+fn test_stats_and_report_of_pipeline() -> Result<()> {
+    // Six disks, each shifted a pixel further right than the last
+    let path = common::write_disk_ser("stats", 256, 6, 1.0)?;
+    let mut params = common::parameters(&[path], 64);
+    params.top_percentage = Some(50.0);
+    let mut context = common::context(&params)?;
+
+    context.frame_records = frame_sigma_analysis(&mut context, |_| {})?;
+    context.frame_records = frame_limit_determinate(&mut context, |_| {})?;
+    context.frame_records = frame_weight_determinate(&mut context, |_| {})?;
+    context.frame_records = frame_offset_analysis(&mut context, |_| {})?;
+    process_frame_stacking(&mut context, |_| {})?;
+
+    let stats = &context.stats;
+    assert_eq!(stats.total_frames, 6);
+    assert_eq!(stats.quality_values.len(), 6);
+    assert!(stats.min_sigma > 0.0 && stats.min_sigma <= stats.max_sigma);
+    assert_eq!(stats.num_frames_used, 3);
+    assert_eq!(stats.num_frames_discarded, 3);
+    assert_eq!(stats.num_frames_discarded_top_percentage, 3);
+    assert!(stats.frame_cache_hits > 0);
+
+    assert_eq!(stats.frames.len(), 6);
+    for fs in stats.frames.iter() {
+        if fs.used {
+            assert_eq!(fs.discard_reason, None);
+            assert!(
+                (fs.offset_h.abs() - fs.frame_id as f32).abs() < 0.5,
+                "frame {} offset {}",
+                fs.frame_id,
+                fs.offset_h
+            );
+            assert!(fs.offset_v.abs() < 0.5);
+        } else {
+            assert_eq!(fs.discard_reason, Some(DiscardReason::TopPercentage));
+        }
+    }
+
+    let stages: Vec<&str> = stats
+        .stage_timings
+        .iter()
+        .map(|t| t.stage.as_str())
+        .collect();
+    assert_eq!(
+        stages,
+        vec!["sigma", "limiting", "weighting", "offset", "stacking"]
+    );
+    assert!(stats.stage_timings.iter().all(|t| t.seconds >= 0.0));
+
+    let report_path = std::env::temp_dir().join("solhat_test_report.json");
+    context.write_report(&report_path.to_string_lossy())?;
+    let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report_path)?)?;
+    assert_eq!(report["parameters"]["top_percentage"], 50.0);
+    assert_eq!(report["stats"]["total_frames"], 6);
+    assert_eq!(report["stats"]["num_frames_used"], 3);
+    assert_eq!(report["stats"]["frames"].as_array().unwrap().len(), 6);
+    assert_eq!(report["stats"]["stage_timings"][4]["stage"], "stacking");
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+use solhat::timestamp::TimeStamp;
+
+#[test]
+fn test_timestamp_parse() -> Result<()> {
+    let ts = TimeStamp::parse("2024-04-08T18:20:05")?;
+    assert_eq!(
+        (ts.year, ts.month, ts.day, ts.hour, ts.minute, ts.second),
+        (2024, 4, 8, 18, 20, 5)
+    );
+    assert_eq!(ts.microsecond, 0);
+
+    // A space may separate the date and time, and seconds may be fractional
+    let fractional = TimeStamp::parse(" 2024-04-08 18:20:05.25 ")?;
+    assert_eq!((fractional.second, fractional.microsecond), (5, 250000));
+    assert_eq!(fractional.timestamp - ts.timestamp, 2500000);
+    Ok(())
+}
+
+#[test]
+fn test_timestamp_parse_rejects_invalid() {
+    for s in [
+        "",
+        "yesterday",
+        "2024-04-08",
+        "18:20:05",
+        "2024-13-01T00:00:00",
+        "2024-04-08T25:00:00",
+        "2024/04/08 18:20:05",
+    ] {
+        assert!(TimeStamp::parse(s).is_err(), "{}", s);
+    }
+}
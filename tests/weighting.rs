@@ -0,0 +1,50 @@
+use anyhow::Result;
+use solhat::drizzle::{AverageStackBuffer, StackBuffer};
+use solhat::weighting::{compute_frame_weights, FrameWeighting};
+
+#[test]
+fn test_frame_weighting_from() -> Result<()> {
+    assert_eq!(FrameWeighting::from("rank")?, FrameWeighting::Rank);
+    assert_eq!(FrameWeighting::from("power")?, FrameWeighting::Power(2.0));
+    assert_eq!(
+        FrameWeighting::from("power:1.5")?,
+        FrameWeighting::Power(1.5)
+    );
+    assert!(FrameWeighting::from("power:-1").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_compute_frame_weights() {
+    let sigmas = [2.0, 4.0, 1.0];
+    assert_eq!(
+        compute_frame_weights(&sigmas, FrameWeighting::Linear),
+        vec![0.5, 1.0, 0.25]
+    );
+    assert_eq!(
+        compute_frame_weights(&sigmas, FrameWeighting::Power(2.0)),
+        vec![0.25, 1.0, 0.0625]
+    );
+    let rank = compute_frame_weights(&sigmas, FrameWeighting::Rank);
+    assert_eq!(rank[1], 1.0);
+    assert!(rank[0] < rank[1] && rank[2] < rank[0]);
+
+    // Frames without a quality measure rank and weigh last
+    let sigmas = [2.0, f64::NAN, 1.0];
+    let rank = compute_frame_weights(&sigmas, FrameWeighting::Rank);
+    assert!(rank[1] < rank[2] && rank[2] < rank[0]);
+    assert_eq!(
+        compute_frame_weights(&sigmas, FrameWeighting::Linear),
+        vec![1.0, 0.0, 0.5]
+    );
+}
+
+#[test]
+fn test_weighted_average() -> Result<()> {
+    let mut buffer = AverageStackBuffer::new(1, 1, 1)?;
+    buffer.put_weighted(0, 0, &[100.0, 0.0, 0.0], 1.0);
+    buffer.put_weighted(0, 0, &[400.0, 0.0, 0.0], 0.5);
+    let v = buffer.get_finalized()?.get_band(0).get(0, 0);
+    assert!((v - 200.0).abs() < 0.01);
+    Ok(())
+}